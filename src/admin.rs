@@ -0,0 +1,267 @@
+//! Ollama model management: list, pull, show, delete, load and unload.
+//!
+//! [`OllamaAdmin`] wraps the administrative part of Ollama's REST API so
+//! deployment code does not have to shell out to the `ollama` CLI:
+//!
+//! ```rust,ignore
+//! let admin = OllamaAdmin::new("http://localhost", 11434);
+//! if !admin.list().await?.iter().any(|m| m.name == "llama3.2:1b") {
+//!     admin.pull("llama3.2:1b", |p| {
+//!         if let Some(f) = p.fraction() {
+//!             println!("{}: {:.0}%", p.status, f * 100.0);
+//!         }
+//!     }).await?;
+//! }
+//!
+//! // Fill in context size and tool support from the model's metadata.
+//! let mut model = ModelConfig::new("llama3.2:1b");
+//! let capabilities = admin.autofill(&mut model).await?;
+//! registry.insert(model.clone(), capabilities);
+//!
+//! // Keep it loaded so the first request does not pay the load time.
+//! model.keep_alive = Some("-1".into());
+//! admin.load(&model).await?;
+//! ```
+//!
+//! Requires the `admin` feature.
+
+use log::{debug, info, warn};
+use ollama_rs::{generation::{chat::request::ChatMessageRequest, parameters::KeepAlive}, Ollama};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+pub use ollama_rs::models::LocalModel;
+
+use crate::{ModelCapabilities, ModelConfig, LLM};
+
+/// One progress update of [`OllamaAdmin::pull`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PullProgress {
+    /// Current step, e.g. `"pulling manifest"`, `"downloading"` or `"success"`.
+    pub status: String,
+    /// Digest of the layer being downloaded, if any.
+    pub digest: Option<String>,
+    /// Total size of the layer in bytes, if known.
+    pub total: Option<u64>,
+    /// Bytes of the layer downloaded so far, if known.
+    pub completed: Option<u64>,
+}
+
+impl PullProgress {
+    /// Share of the current layer downloaded, between `0.0` and `1.0`, when
+    /// both sizes are known.
+    pub fn fraction(&self) -> Option<f64> {
+        match (self.completed, self.total) {
+            (Some(completed), Some(total)) if total > 0 => Some(completed as f64 / total as f64),
+            _ => None,
+        }
+    }
+}
+
+/// Metadata of a local model, as reported by [`OllamaAdmin::show`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelDetails {
+    /// The model identifier.
+    pub name: String,
+    /// Default parameters from the Modelfile, one `name value` per line.
+    pub parameters: String,
+    /// The prompt template.
+    pub template: String,
+    /// The model license.
+    pub license: String,
+    /// Maximum context length the model was trained for, if reported.
+    pub context_length: Option<u32>,
+    /// Capabilities reported by Ollama, e.g. `"completion"`, `"tools"`,
+    /// `"vision"`, `"embedding"`, `"thinking"`.
+    pub capabilities: Vec<String>,
+    /// The raw `model_info` map (architecture, parameter count, …).
+    pub info: Map<String, Value>,
+}
+
+impl ModelDetails {
+    /// Returns `true` if Ollama reports the capability `name`.
+    pub fn supports(&self, name: &str) -> bool {
+        self.capabilities.iter().any(|c| c == name)
+    }
+
+    /// The reported capabilities mapped onto [`ModelCapabilities`].
+    ///
+    /// Ollama does not report JSON mode support, so
+    /// [`ModelCapabilities::json_mode`] is left `false` for profiles to declare.
+    pub fn model_capabilities(&self) -> ModelCapabilities {
+        ModelCapabilities {
+            tools: self.supports("tools"),
+            vision: self.supports("vision"),
+            embeddings: self.supports("embedding"),
+            context_size: self.context_length,
+            ..Default::default()
+        }
+    }
+}
+
+/// Administrative client for one Ollama server; see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct OllamaAdmin {
+    ollama: Ollama,
+    client: reqwest::Client,
+}
+
+impl OllamaAdmin {
+    /// Creates a client for the Ollama server at `host` and `port`.
+    pub fn new(host: &str, port: u16) -> Self {
+        OllamaAdmin {
+            ollama: Ollama::new(host, port),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Creates a client for the server of an [`LLM::Ollama`] backend.
+    /// Returns `None` for other backends.
+    pub fn for_llm(llm: &LLM) -> Option<Self> {
+        match llm {
+            LLM::Ollama(host, port, _) => Some(OllamaAdmin::new(host, *port)),
+            _ => None,
+        }
+    }
+
+    /// Lists the models available locally on the server.
+    ///
+    /// # Errors
+    /// Returns an error if the server cannot be reached or answers with an error.
+    pub async fn list(&self) -> Result<Vec<LocalModel>, Box<dyn std::error::Error>> {
+        Ok(self.ollama.list_local_models().await?)
+    }
+
+    /// Downloads `model`, calling `progress` for every status update the
+    /// server streams back.
+    ///
+    /// # Errors
+    /// Returns an error if the server cannot be reached, rejects the request
+    /// or reports an error while pulling.
+    pub async fn pull<F>(&self, model: &str, mut progress: F) -> Result<(), Box<dyn std::error::Error>>
+    where
+        F: FnMut(&PullProgress),
+    {
+        info!("Pulling Ollama model {model}");
+        let url = format!("{}api/pull", self.ollama.url_str());
+        let mut res = self.client.post(url).json(&json!({ "model": model, "stream": true })).send().await?;
+        if !res.status().is_success() {
+            return Err(format!("Pulling {model} failed: {}", res.text().await?).into());
+        }
+        // Status updates arrive as NDJSON; a chunk may hold partial lines.
+        let mut buf = Vec::new();
+        while let Some(chunk) = res.chunk().await? {
+            buf.extend_from_slice(&chunk);
+            while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buf.drain(..=pos).collect();
+                pull_status(model, &line, &mut progress)?;
+            }
+        }
+        pull_status(model, &buf, &mut progress)?;
+        info!("Pulled Ollama model {model}");
+        Ok(())
+    }
+
+    /// Fetches the metadata of `model`.
+    ///
+    /// # Errors
+    /// Returns an error if the model does not exist or the server cannot be reached.
+    pub async fn show(&self, model: &str) -> Result<ModelDetails, Box<dyn std::error::Error>> {
+        let info = self.ollama.show_model_info(model.to_string()).await?;
+        let context_length = info.model_info
+            .iter()
+            .find(|(key, _)| key.ends_with(".context_length"))
+            .and_then(|(_, value)| value.as_u64())
+            .map(|n| n.min(u32::MAX as u64) as u32);
+        Ok(ModelDetails {
+            name: model.to_string(),
+            parameters: info.parameters,
+            template: info.template,
+            license: info.license,
+            context_length,
+            capabilities: info.capabilities,
+            info: info.model_info,
+        })
+    }
+
+    /// Deletes `model` from the server.
+    ///
+    /// # Errors
+    /// Returns an error if the model does not exist or the server cannot be reached.
+    pub async fn delete(&self, model: &str) -> Result<(), Box<dyn std::error::Error>> {
+        info!("Deleting Ollama model {model}");
+        Ok(self.ollama.delete_model(model.to_string()).await?)
+    }
+
+    /// Loads `model` into memory without generating anything, keeping it
+    /// loaded for [`ModelConfig::keep_alive`] (or the server default).
+    ///
+    /// # Errors
+    /// Returns an error if the model does not exist or the server cannot be reached.
+    pub async fn load(&self, model: &ModelConfig) -> Result<(), Box<dyn std::error::Error>> {
+        debug!("Loading Ollama model {}", model.model);
+        self.keep_alive(&model.model, model.ollama_keep_alive()).await
+    }
+
+    /// Unloads `model` from memory.
+    ///
+    /// # Errors
+    /// Returns an error if the server cannot be reached.
+    pub async fn unload(&self, model: &str) -> Result<(), Box<dyn std::error::Error>> {
+        debug!("Unloading Ollama model {model}");
+        self.keep_alive(model, Some(KeepAlive::UnloadOnCompletion)).await
+    }
+
+    /// Fills in [`ModelConfig::context_size`] and [`ModelConfig::tool`] from
+    /// the model's metadata where they are unset, and returns its capabilities.
+    ///
+    /// A `tool` setting of `Some(true)` on a model without tool support is
+    /// kept but logged.
+    ///
+    /// # Errors
+    /// See [`OllamaAdmin::show`].
+    pub async fn autofill(&self, model: &mut ModelConfig) -> Result<ModelCapabilities, Box<dyn std::error::Error>> {
+        let capabilities = self.show(&model.model).await?.model_capabilities();
+        if model.context_size.is_none() {
+            model.context_size = capabilities.context_size;
+        }
+        match model.tool {
+            None => model.tool = Some(capabilities.tools),
+            Some(true) if !capabilities.tools => warn!("Model {} has tools enabled but does not report tool support", model.model),
+            _ => {}
+        }
+        Ok(capabilities)
+    }
+
+    /// Sends an empty chat request, which makes Ollama (un)load the model
+    /// according to `keep_alive`.
+    async fn keep_alive(&self, model: &str, keep_alive: Option<KeepAlive>) -> Result<(), Box<dyn std::error::Error>> {
+        let mut request = ChatMessageRequest::new(model.to_string(), Vec::new());
+        if let Some(keep_alive) = keep_alive {
+            request = request.keep_alive(keep_alive);
+        }
+        self.ollama.send_chat_messages(request).await?;
+        Ok(())
+    }
+}
+
+/// Parses one NDJSON line of a pull response and reports it to `progress`.
+///
+/// # Errors
+/// Returns an error if the line is not valid JSON or reports an error.
+fn pull_status<F>(model: &str, line: &[u8], progress: &mut F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: FnMut(&PullProgress),
+{
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return Ok(());
+    }
+    let value: Value = serde_json::from_slice(line)?;
+    if let Some(error) = value.get("error").and_then(Value::as_str) {
+        return Err(format!("Pulling {model} failed: {error}").into());
+    }
+    let status: PullProgress = serde_json::from_value(value)?;
+    debug!("Pull {model}: {status:?}");
+    progress(&status);
+    Ok(())
+}
@@ -0,0 +1,662 @@
+//! Tool-calling agent loop shared by all backends.
+//!
+//! [`AgentLoop`] sends a conversation to an [`AgentBackend`] together with
+//! the tools of a [`ComponentRegistry`], runs the tool calls of each reply
+//! and feeds their results back until the model answers without calling a
+//! tool — the final answer — or `max_steps` round trips have called tools.
+//! The tool calls of one reply are independent of each other and run
+//! concurrently unless [`AgentLoop::parallel`] is turned off. Every round
+//! trip is reported to the optional [`AgentLoop::on_step`] callback. With a
+//! [`ToolSelection`] only the tools relevant to the prompt are offered.
+//!
+//! [`Query`](crate::Query) runs this loop for Ollama, MistralAI and the mock
+//! backend whenever tools are attached; any other OpenAI-compatible server
+//! can be driven directly through [`OpenAiCompatible`]:
+//!
+//! ```rust,ignore
+//! let backend = OpenAiCompatible::new("http://localhost:8000/v1", "qwen2.5").api_key(key);
+//! let response = AgentLoop::default()
+//!     .max_steps(5)
+//!     .on_step(|step| println!("step {}: {} tool calls", step.index, step.tool_calls.len()))
+//!     .run(&backend, &registry, vec![AgentMessage::user("What is the weather in Oslo?".into())])
+//!     .await?;
+//! ```
+
+use std::{collections::VecDeque, fmt, future::Future, sync::{Arc, OnceLock}};
+
+use log::{debug, warn};
+use ollama_rs::{
+    generation::{
+        chat::{request::ChatMessageRequest, ChatMessage, ChatMessageResponse, MessageRole},
+        parameters::{FormatType, KeepAlive},
+        tools::{ToolCall, ToolCallFunction, ToolInfo},
+    },
+    models::ModelOptions,
+    Ollama,
+};
+use serde_json::{json, Value};
+
+use crate::components::{tools::{self, TurnRecords}, ComponentRegistry};
+use crate::{LlmError, Response, RetryPolicy, ToolCallRecord, ToolError, ToolSelection, Usage};
+
+/// Callback receiving every [`AgentStep`] as soon as it completes.
+pub type StepCallback = Arc<dyn Fn(&AgentStep) + Send + Sync>;
+
+/// One model round trip of an [`AgentLoop`] and the tool calls it made.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AgentStep {
+    /// Position of the round trip, starting at `1`.
+    pub index: usize,
+    /// Text of the model's reply (often empty when it calls tools).
+    pub text: String,
+    /// Tool calls requested by the reply, with their results, in call order.
+    pub tool_calls: Vec<ToolCallRecord>,
+    /// Token usage of this round trip.
+    pub usage: Usage,
+    /// Whether this reply is the final answer.
+    pub final_answer: bool,
+}
+
+/// A backend's reply to one round trip of an [`AgentLoop`].
+#[derive(Debug, Clone)]
+pub struct AgentReply {
+    /// The assistant message, including any tool calls it requests.
+    pub message: ChatMessage,
+    /// Token usage as reported by the backend.
+    pub usage: Usage,
+    /// The model that produced the reply.
+    pub model: String,
+    /// Why generation stopped, if the backend says.
+    pub finish_reason: Option<String>,
+}
+
+impl AgentReply {
+    /// Whether this reply is a final answer, i.e. it calls no tools.
+    pub fn is_final(&self) -> bool {
+        self.message.tool_calls.is_empty()
+    }
+
+    /// The reply as the [`Response`] to a turn sent without tools.
+    ///
+    /// # Errors
+    /// Returns an error if the model called a tool anyway.
+    pub(crate) fn into_response(self) -> Result<Response, Box<dyn std::error::Error>> {
+        if let Some(call) = self.message.tool_calls.first() {
+            return Err(format!("Model called tool '{}', but no tools are attached to the query", call.function.name).into());
+        }
+        Ok(Response {
+            text: self.message.content,
+            usage: self.usage,
+            model: self.model,
+            finish_reason: self.finish_reason,
+            ..Default::default()
+        })
+    }
+}
+
+/// A chat backend that can take part in an [`AgentLoop`].
+///
+/// Implementations translate the conversation and tool definitions into
+/// their wire format, make a single request and hand back the assistant
+/// message; running tools is left to the loop.
+pub trait AgentBackend {
+    /// Sends `messages` with `tools` offered to the model and returns its reply.
+    ///
+    /// `tools` is empty when the model must answer without calling any.
+    ///
+    /// # Errors
+    /// Returns an error if the request fails or the reply cannot be parsed.
+    fn send(&self, messages: &[ChatMessage], tools: &[ToolInfo]) -> impl Future<Output = Result<AgentReply, Box<dyn std::error::Error>>>;
+}
+
+/// A round trip failed after tools had already run in the same turn.
+///
+/// Tools may have side effects, so [`Query`](crate::Query) neither repeats
+/// such a turn nor falls back to another backend for it.
+#[derive(Debug)]
+pub struct ToolTurnError {
+    /// The tool calls made before the failure, in call order.
+    pub tool_calls: Vec<ToolCallRecord>,
+    error: Box<dyn std::error::Error>,
+}
+
+impl fmt::Display for ToolTurnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (after {} tool call(s))", self.error, self.tool_calls.len())
+    }
+}
+
+impl std::error::Error for ToolTurnError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
+/// An [`AgentBackend`] retrying each round trip under a [`RetryPolicy`].
+///
+/// Retrying single round trips rather than whole turns keeps the tools that
+/// already ran from running again.
+pub(crate) struct Retrying<'a, B> {
+    pub(crate) backend: &'a B,
+    pub(crate) policy: &'a RetryPolicy,
+    /// Names the backend in log messages.
+    pub(crate) what: String,
+}
+
+impl<B: AgentBackend> AgentBackend for Retrying<'_, B> {
+    async fn send(&self, messages: &[ChatMessage], tools: &[ToolInfo]) -> Result<AgentReply, Box<dyn std::error::Error>> {
+        self.policy.run(&self.what, || self.backend.send(messages, tools)).await
+    }
+}
+
+/// Limits and hooks of the tool-calling loop; see the [module documentation](self).
+#[derive(Clone)]
+pub struct AgentLoop {
+    /// Maximum number of round trips that may call tools. Once reached the
+    /// model is asked once more, without tools, for its final answer.
+    pub max_steps: usize,
+    /// Whether the tool calls of one reply run concurrently.
+    pub parallel: bool,
+    /// Called after every round trip.
+    pub on_step: Option<StepCallback>,
+    /// Offers only the tools relevant to the prompt instead of all of them.
+    pub selection: Option<ToolSelection>,
+}
+
+impl Default for AgentLoop {
+    fn default() -> Self {
+        AgentLoop {
+            max_steps: 10,
+            parallel: true,
+            on_step: None,
+            selection: None,
+        }
+    }
+}
+
+impl fmt::Debug for AgentLoop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AgentLoop")
+            .field("max_steps", &self.max_steps)
+            .field("parallel", &self.parallel)
+            .field("on_step", &self.on_step.is_some())
+            .field("selection", &self.selection)
+            .finish()
+    }
+}
+
+impl AgentLoop {
+    /// Sets [`AgentLoop::max_steps`].
+    pub fn max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Sets [`AgentLoop::parallel`].
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    /// Sets the callback receiving every [`AgentStep`].
+    pub fn on_step<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&AgentStep) + Send + Sync + 'static,
+    {
+        self.on_step = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets the [`ToolSelection`] choosing the tools offered for each prompt.
+    pub fn select_tools(mut self, selection: ToolSelection) -> Self {
+        self.selection = Some(selection);
+        self
+    }
+
+    /// Runs the loop on `backend` with the tools of `registry`, starting from
+    /// `messages`.
+    ///
+    /// With [`AgentLoop::selection`] set, the tools are chosen once, by
+    /// relevance to the last user message, and offered on every round trip.
+    ///
+    /// The returned [`Response`] carries the final answer, the token usage
+    /// summed over all round trips, and every tool call and approval decision
+    /// made on the way. `latency` and `backend` are left for the caller.
+    ///
+    /// # Errors
+    /// Returns an error if a round trip fails; a [`ToolTurnError`] if the round trip
+    /// failed after tools had run. Failing tools and resources and calls of
+    /// unknown tools are reported to the model instead.
+    pub async fn run<B: AgentBackend + ?Sized>(&self, backend: &B, registry: &ComponentRegistry, mut messages: Vec<ChatMessage>) -> Result<Response, Box<dyn std::error::Error>> {
+        let tools = match &self.selection {
+            Some(selection) => {
+                let prompt = messages.iter().rev().find(|m| m.role == MessageRole::User).map(|m| m.content.as_str());
+                selection.select(registry.tool_infos(), prompt.unwrap_or_default()).await
+            }
+            None => registry.tool_infos(),
+        };
+        let mut response = Response::default();
+        let mut index = 0;
+        loop {
+            index += 1;
+            let offered: &[ToolInfo] = if index > self.max_steps { &[] } else { &tools };
+            if offered.is_empty() && !tools.is_empty() {
+                warn!("Agent loop reached {} steps; asking for a final answer without tools", self.max_steps);
+            }
+            let reply = match Box::pin(backend.send(&messages, offered)).await {
+                Ok(reply) => reply,
+                Err(error) if !response.tool_calls.is_empty() => {
+                    return Err(Box::new(ToolTurnError { tool_calls: response.tool_calls, error }));
+                }
+                Err(error) => return Err(error),
+            };
+            let final_answer = offered.is_empty() || reply.is_final();
+            response.usage.prompt_tokens += reply.usage.prompt_tokens;
+            response.usage.completion_tokens += reply.usage.completion_tokens;
+            response.model = reply.model;
+            response.finish_reason = reply.finish_reason;
+
+            if final_answer {
+                self.report(AgentStep { index, text: reply.message.content.clone(), usage: reply.usage, final_answer, ..Default::default() });
+                response.text = reply.message.content;
+                return Ok(response);
+            }
+
+            debug!("Agent step {index}: {} tool call(s)", reply.message.tool_calls.len());
+            let results = self.call_tools(registry, &reply.message.tool_calls).await;
+            let text = reply.message.content.clone();
+            messages.push(reply.message);
+            let mut step = AgentStep { index, text, usage: reply.usage, final_answer, ..Default::default() };
+            for (result, records) in results {
+                messages.push(ChatMessage::new(MessageRole::Tool, result));
+                step.tool_calls.extend(records.calls);
+                response.tool_approvals.extend(records.approvals);
+            }
+            response.tool_calls.extend(step.tool_calls.iter().cloned());
+            self.report(step);
+        }
+    }
+
+    /// Runs `calls`, concurrently when [`AgentLoop::parallel`] is set, and
+    /// returns the text handed back to the model for each, in call order,
+    /// with the records the call left.
+    async fn call_tools(&self, registry: &ComponentRegistry, calls: &[ToolCall]) -> Vec<(String, TurnRecords)> {
+        if self.parallel {
+            return futures::future::join_all(calls.iter().map(|call| Self::call_tool(registry, call))).await;
+        }
+        let mut results = Vec::with_capacity(calls.len());
+        for call in calls {
+            results.push(Self::call_tool(registry, call).await);
+        }
+        results
+    }
+
+    /// Runs a single tool call, collecting the records it leaves in its own
+    /// scope so they keep call order when calls run concurrently.
+    ///
+    /// Failures are handed back to the model like [`ToolError`]s, so one
+    /// failing resource does not end the turn.
+    async fn call_tool(registry: &ComponentRegistry, call: &ToolCall) -> (String, TurnRecords) {
+        let name = &call.function.name;
+        let turn = registry.call_tool(name, call.function.arguments.clone());
+        let (result, records) = tools::collect_records(turn).await;
+        let text = match result {
+            Some(Ok(text)) => text,
+            Some(Err(e)) => {
+                warn!("Tool '{name}' failed: {e}");
+                match e.downcast::<ToolError>() {
+                    Ok(e) => e.model_message(name),
+                    Err(e) => ToolError::Failed(e.to_string()).model_message(name),
+                }
+            }
+            None => {
+                warn!("Model called unknown tool '{name}'");
+                format!("Error: there is no tool named '{name}'. Use one of the tools offered.")
+            }
+        };
+        (text, records)
+    }
+
+    fn report(&self, step: AgentStep) {
+        if let Some(on_step) = &self.on_step {
+            on_step(&step);
+        }
+    }
+}
+
+/// Ollama's `/api/chat` as an [`AgentBackend`].
+///
+/// The request is built with ollama-rs but sent here, because the client
+/// does not model every field, such as `min_p` in the options and
+/// `done_reason` in the reply.
+pub(crate) struct OllamaAgent {
+    pub(crate) ollama: Ollama,
+    pub(crate) model: String,
+    pub(crate) options: ModelOptions,
+    pub(crate) format: Option<FormatType>,
+    pub(crate) keep_alive: Option<KeepAlive>,
+    pub(crate) think: Option<bool>,
+    /// Sent within the options, which lack a field for it.
+    pub(crate) min_p: Option<f32>,
+}
+
+impl AgentBackend for OllamaAgent {
+    async fn send(&self, messages: &[ChatMessage], tools: &[ToolInfo]) -> Result<AgentReply, Box<dyn std::error::Error>> {
+        let mut request = ChatMessageRequest::new(self.model.clone(), messages.to_vec())
+            .options(self.options.clone())
+            .tools(tools.to_vec());
+        // Ollama does not call tools when a format is set, so only ask for
+        // one when no tools are offered or their results are in.
+        if let Some(format) = &self.format
+            && (tools.is_empty() || messages.last().is_some_and(|m| m.role == MessageRole::Tool))
+        {
+            request = request.format(format.clone());
+        }
+        if let Some(keep_alive) = &self.keep_alive {
+            request = request.keep_alive(keep_alive.clone());
+        }
+        if let Some(think) = self.think {
+            request = request.think(think);
+        }
+        debug!("Sending {} message(s) and {} tool(s) to Ollama", messages.len(), tools.len());
+        let mut body = serde_json::to_value(&request)?;
+        body["stream"] = json!(false);
+        if let Some(min_p) = self.min_p {
+            body["options"]["min_p"] = json!(min_p);
+        }
+        let response = http_client()
+            .post(format!("{}api/chat", self.ollama.url_str()))
+            .json(&body)
+            .send()
+            .await?;
+        let body = json_body(response).await.inspect_err(|e| debug!("Error communicating with Ollama: {e}"))?;
+        let done_reason = body["done_reason"].as_str().map(str::to_string);
+        let response: ChatMessageResponse = serde_json::from_value(body)?;
+        Ok(AgentReply {
+            usage: response.final_data.as_ref()
+                .map(|d| Usage::new(d.prompt_eval_count as u32, d.eval_count as u32))
+                .unwrap_or_default(),
+            finish_reason: done_reason.or_else(|| response.done.then(|| "stop".to_string())),
+            model: response.model,
+            message: response.message,
+        })
+    }
+}
+
+/// The HTTP client shared by the chat backends, so that their connections
+/// are pooled across queries instead of being set up for every round trip.
+fn http_client() -> reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(reqwest::Client::new).clone()
+}
+
+/// Reads the JSON body of `response`.
+///
+/// # Errors
+/// Returns [`LlmError::Http`] carrying the body text for error statuses, or
+/// an error if the body is not JSON.
+async fn json_body(response: reqwest::Response) -> Result<Value, Box<dyn std::error::Error>> {
+    let status = response.status();
+    if !status.is_success() {
+        let message = response.text().await.unwrap_or_else(|e| e.to_string());
+        return Err(Box::new(LlmError::Http { status: status.as_u16(), message }));
+    }
+    Ok(response.json().await?)
+}
+
+/// An OpenAI-compatible `/chat/completions` endpoint as an [`AgentBackend`].
+///
+/// Used for MistralAI and usable with any server speaking the OpenAI chat
+/// completions protocol (vLLM, llama.cpp, LM Studio, OpenAI itself, ...).
+#[derive(Debug, Clone)]
+pub struct OpenAiCompatible {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    json: bool,
+    params: serde_json::Map<String, Value>,
+}
+
+impl OpenAiCompatible {
+    /// Creates a backend for `model` served under `base_url` (the part
+    /// before `/chat/completions`, e.g. `https://api.openai.com/v1`).
+    pub fn new(base_url: &str, model: &str) -> Self {
+        OpenAiCompatible {
+            client: http_client(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: None,
+            model: model.to_string(),
+            json: false,
+            params: serde_json::Map::new(),
+        }
+    }
+
+    /// Creates a backend for `model` on the MistralAI API.
+    pub fn mistral(api_key: &str, model: &str) -> Self {
+        OpenAiCompatible::new("https://api.mistral.ai/v1", model).api_key(api_key)
+    }
+
+    /// The model named in every request.
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Sends `api_key` as a bearer token.
+    pub fn api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    /// Requests JSON output (`response_format: json_object`) for answers
+    /// given without tools or after tool results.
+    pub fn json(mut self, json: bool) -> Self {
+        self.json = json;
+        self
+    }
+
+    /// Adds a field to every request body, e.g. `temperature` or `max_tokens`.
+    pub fn param(mut self, key: &str, value: Value) -> Self {
+        self.params.insert(key.to_string(), value);
+        self
+    }
+
+    /// Converts `messages` into the OpenAI wire format.
+    ///
+    /// Tool calls get generated ids (nine alphanumeric characters, as
+    /// MistralAI requires) and tool results are paired with them in order.
+    fn wire_messages(messages: &[ChatMessage]) -> Vec<Value> {
+        let mut next_id = 0;
+        let mut pending: VecDeque<(String, String)> = VecDeque::new();
+        messages.iter().map(|message| match message.role {
+            MessageRole::Assistant if !message.tool_calls.is_empty() => {
+                let calls: Vec<Value> = message.tool_calls.iter().map(|call| {
+                    next_id += 1;
+                    let id = format!("call{next_id:05}");
+                    pending.push_back((id.clone(), call.function.name.clone()));
+                    json!({
+                        "id": id,
+                        "type": "function",
+                        "function": { "name": call.function.name, "arguments": call.function.arguments.to_string() },
+                    })
+                }).collect();
+                json!({ "role": "assistant", "content": message.content, "tool_calls": calls })
+            }
+            MessageRole::Tool => {
+                let (id, name) = pending.pop_front().unwrap_or_default();
+                json!({ "role": "tool", "content": message.content, "tool_call_id": id, "name": name })
+            }
+            MessageRole::System => json!({ "role": "system", "content": message.content }),
+            MessageRole::User => json!({ "role": "user", "content": message.content }),
+            MessageRole::Assistant => json!({ "role": "assistant", "content": message.content }),
+        }).collect()
+    }
+
+    /// Reads the assistant message out of a chat completion `body`.
+    fn parse_reply(body: &Value) -> Result<AgentReply, Box<dyn std::error::Error>> {
+        let choice = body["choices"].get(0).ok_or("Chat completion has no choices")?;
+        let mut message = ChatMessage::new(
+            MessageRole::Assistant,
+            choice["message"]["content"].as_str().unwrap_or_default().to_string(),
+        );
+        if let Some(calls) = choice["message"]["tool_calls"].as_array() {
+            message.tool_calls = calls.iter().map(|call| {
+                let arguments = &call["function"]["arguments"];
+                ToolCall {
+                    function: ToolCallFunction {
+                        name: call["function"]["name"].as_str().unwrap_or_default().to_string(),
+                        // Arguments arrive as a JSON string; keep them as text if they don't parse.
+                        arguments: match arguments.as_str() {
+                            Some(text) => serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string())),
+                            None => arguments.clone(),
+                        },
+                    },
+                }
+            }).collect();
+        }
+        let tokens = |key: &str| body["usage"][key].as_u64().unwrap_or_default() as u32;
+        Ok(AgentReply {
+            message,
+            usage: Usage::new(tokens("prompt_tokens"), tokens("completion_tokens")),
+            model: body["model"].as_str().unwrap_or_default().to_string(),
+            finish_reason: choice["finish_reason"].as_str().map(str::to_string),
+        })
+    }
+}
+
+impl AgentBackend for OpenAiCompatible {
+    async fn send(&self, messages: &[ChatMessage], tools: &[ToolInfo]) -> Result<AgentReply, Box<dyn std::error::Error>> {
+        let mut body = self.params.clone();
+        body.insert("model".to_string(), json!(self.model));
+        body.insert("messages".to_string(), Value::Array(Self::wire_messages(messages)));
+        if !tools.is_empty() {
+            let tools: Vec<Value> = tools.iter().map(|tool| json!({
+                "type": "function",
+                "function": {
+                    "name": tool.function.name,
+                    "description": tool.function.description,
+                    "parameters": tool.function.parameters,
+                },
+            })).collect();
+            body.insert("tools".to_string(), Value::Array(tools));
+        }
+        if self.json && (tools.is_empty() || messages.last().is_some_and(|m| m.role == MessageRole::Tool)) {
+            body.insert("response_format".to_string(), json!({ "type": "json_object" }));
+        }
+
+        let mut request = self.client.post(format!("{}/chat/completions", self.base_url)).json(&body);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        debug!("Sending {} message(s) and {} tool(s) to {}", messages.len(), tools.len(), self.base_url);
+        let body = json_body(request.send().await?).await?;
+        Self::parse_reply(&body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{mock::MockAgent, Component, MockLlm, Tool};
+
+    /// A [`MockAgent`] whose replies report fixed token usage.
+    struct Metered<'a>(MockAgent<'a>);
+
+    impl AgentBackend for Metered<'_> {
+        async fn send(&self, messages: &[ChatMessage], tools: &[ToolInfo]) -> Result<AgentReply, Box<dyn std::error::Error>> {
+            let mut reply = self.0.send(messages, tools).await?;
+            reply.usage = Usage::new(10, 2);
+            Ok(reply)
+        }
+    }
+
+    /// Tools `slow` and `fast` that log their name when they finish.
+    fn registry(finished: &Arc<Mutex<Vec<&'static str>>>) -> ComponentRegistry {
+        let tool = |name: &'static str, delay: u64| {
+            let finished = finished.clone();
+            Tool::new(name, name, move |_: &String| {
+                let finished = finished.clone();
+                async move {
+                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                    finished.lock().unwrap().push(name);
+                    name.to_string()
+                }
+            })
+        };
+        let mut registry = ComponentRegistry::new();
+        registry.register(Component { tools: vec![tool("slow", 50), tool("fast", 0)], ..Default::default() });
+        registry
+    }
+
+    fn user(text: &str) -> Vec<ChatMessage> {
+        vec![ChatMessage::new(MessageRole::User, text.to_string())]
+    }
+
+    #[tokio::test]
+    async fn asks_for_a_final_answer_without_tools_after_max_steps() {
+        let mock = MockLlm::new().tool_call("fast", json!({})).tool_call("fast", json!({})).reply("done");
+        let agent = AgentLoop::default().max_steps(2);
+        let response = agent.run(&MockAgent { mock: &mock, structured: false }, &registry(&Arc::default()), user("go")).await.unwrap();
+        assert_eq!(response.text, "done");
+        assert_eq!(response.tool_calls.len(), 2);
+        let offered: Vec<usize> = mock.requests().iter().map(|r| r.tools.len()).collect();
+        assert_eq!(offered, [2, 2, 0]);
+    }
+
+    #[tokio::test]
+    async fn hands_results_back_in_call_order() {
+        for parallel in [true, false] {
+            let finished = Arc::default();
+            let mock = MockLlm::new().tool_calls([("slow", json!({})), ("fast", json!({}))]).reply("done");
+            let agent = AgentLoop::default().parallel(parallel);
+            let response = agent.run(&MockAgent { mock: &mock, structured: false }, &registry(&finished), user("go")).await.unwrap();
+
+            let expected: &[&str] = if parallel { &["fast", "slow"] } else { &["slow", "fast"] };
+            assert_eq!(*finished.lock().unwrap(), expected);
+            let names: Vec<&str> = response.tool_calls.iter().map(|c| c.name.as_str()).collect();
+            assert_eq!(names, ["slow", "fast"]);
+            let results: Vec<String> = mock.requests()[1].messages.iter().rev().take(2).map(|m| m.content.clone()).collect();
+            assert_eq!(results, ["fast", "slow"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_every_step() {
+        let steps = Arc::new(Mutex::new(Vec::new()));
+        let seen = steps.clone();
+        let agent = AgentLoop::default().on_step(move |step| seen.lock().unwrap().push(step.clone()));
+        let mock = MockLlm::new().tool_call("fast", json!({})).reply("done");
+        agent.run(&MockAgent { mock: &mock, structured: false }, &registry(&Arc::default()), user("go")).await.unwrap();
+
+        let steps = steps.lock().unwrap();
+        assert_eq!(steps.len(), 2);
+        assert_eq!((steps[0].index, steps[0].final_answer, steps[0].tool_calls.len()), (1, false, 1));
+        assert_eq!((steps[1].index, steps[1].final_answer, steps[1].text.as_str()), (2, true, "done"));
+    }
+
+    #[tokio::test]
+    async fn tells_the_model_about_unknown_tools() {
+        let mock = MockLlm::new().tool_call("nope", json!({})).reply("sorry");
+        let response = AgentLoop::default()
+            .run(&MockAgent { mock: &mock, structured: false }, &registry(&Arc::default()), user("go"))
+            .await
+            .unwrap();
+        assert_eq!(response.text, "sorry");
+        assert!(response.tool_calls.is_empty());
+        let message = mock.requests()[1].messages.last().unwrap().clone();
+        assert_eq!(message.role, "tool");
+        assert!(message.content.contains("no tool named 'nope'"));
+    }
+
+    #[tokio::test]
+    async fn sums_usage_over_round_trips() {
+        let mock = MockLlm::new().tool_call("fast", json!({})).tool_call("fast", json!({})).reply("done");
+        let backend = Metered(MockAgent { mock: &mock, structured: false });
+        let response = AgentLoop::default().run(&backend, &registry(&Arc::default()), user("go")).await.unwrap();
+        assert_eq!(response.usage, Usage::new(30, 6));
+    }
+}
@@ -0,0 +1,314 @@
+//! Record/replay ("cassette") backend for deterministic, offline tests.
+//!
+//! [`Cassette::record`] wraps a real backend and appends every request and
+//! its response to a JSON file. [`Cassette::replay`] serves responses from
+//! that file without contacting any backend, matching requests by a
+//! fingerprint of their messages (including replayed history), model
+//! options, output format and offered tools. An unmatched request is an
+//! error, so a change in prompts shows up as a test failure instead of a
+//! silent live call.
+//!
+//! Identical requests recorded several times are replayed in recording
+//! order; once exhausted, the last response for that fingerprint is repeated.
+//! Tool calls made during recording are not re-executed on replay; only the
+//! final answer is served.
+//!
+//! # Example
+//! ```rust,ignore
+//! let backend = LLM::Ollama("localhost".into(), 11434, ModelConfig::new("llama3.2:1b"));
+//! // Locally, against a live Ollama:
+//! let llm = Cassette::record("tests/cassettes/summary.json", backend.clone());
+//! // In CI:
+//! let llm = Cassette::replay("tests/cassettes/summary.json", backend)?;
+//! let mut query = Query::new(llm, HistoryConfig::None);
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{Response, Usage, LLM};
+
+/// Whether a [`Cassette`] records live traffic or replays it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Forward requests to the wrapped backend and write them to the file.
+    Record,
+    /// Serve responses from the file; never contact the wrapped backend.
+    Replay,
+}
+
+/// A single message of a recorded request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct CassetteMessage {
+    pub(crate) role: String,
+    pub(crate) content: String,
+}
+
+/// Everything that determines a backend answer, used for fingerprinting.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct CassetteRequest {
+    /// [`LLM::name`] of the wrapped backend (includes the Ollama model).
+    pub(crate) backend: String,
+    pub(crate) messages: Vec<CassetteMessage>,
+    pub(crate) options: Value,
+    pub(crate) format: Option<Value>,
+    /// Names of the tools offered to the model.
+    pub(crate) tools: Vec<String>,
+}
+
+/// The recorded part of a [`Response`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CassetteResponse {
+    text: String,
+    usage: Usage,
+    model: String,
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Interaction {
+    fingerprint: String,
+    request: CassetteRequest,
+    response: CassetteResponse,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CassetteFile {
+    version: u32,
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    interactions: Vec<Interaction>,
+    /// Next interaction index to serve per fingerprint (replay only).
+    cursors: HashMap<String, usize>,
+}
+
+/// A recording or replaying wrapper around another [`LLM`] backend.
+///
+/// Build one with [`Cassette::record`] or [`Cassette::replay`], which return
+/// the ready-to-use [`LLM::Cassette`]. Clones share the same recording.
+#[derive(Clone)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    backend: Box<LLM>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Cassette {
+    /// Wraps `backend` so that every exchange is written to `path`.
+    ///
+    /// The file is (re)written after each exchange, replacing any previous
+    /// recording. `backend` must be a concrete backend, not a
+    /// [`LLM::Fallback`] chain.
+    pub fn record(path: impl Into<PathBuf>, backend: LLM) -> LLM {
+        LLM::Cassette(Cassette {
+            path: path.into(),
+            mode: CassetteMode::Record,
+            backend: Box::new(backend),
+            state: Arc::new(Mutex::new(State::default())),
+        })
+    }
+
+    /// Loads the recording at `path` and serves it in place of `backend`.
+    ///
+    /// `backend` must match the one used for recording since its name is part
+    /// of the request fingerprint; it is never contacted.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or is not a valid cassette.
+    pub fn replay(path: impl Into<PathBuf>, backend: LLM) -> Result<LLM, Box<dyn std::error::Error>> {
+        let path = path.into();
+        let file: CassetteFile = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+        log::debug!("Loaded {} cassette interactions from {}", file.interactions.len(), path.display());
+        Ok(LLM::Cassette(Cassette {
+            path,
+            mode: CassetteMode::Replay,
+            backend: Box::new(backend),
+            state: Arc::new(Mutex::new(State {
+                interactions: file.interactions,
+                cursors: HashMap::new(),
+            })),
+        }))
+    }
+
+    /// The cassette file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether this cassette records or replays.
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    /// The wrapped backend.
+    pub fn backend(&self) -> &LLM {
+        &self.backend
+    }
+
+    /// This cassette, sharing its recording, in front of `backend`.
+    pub(crate) fn with_backend(&self, backend: LLM) -> Cassette {
+        Cassette {
+            path: self.path.clone(),
+            mode: self.mode,
+            backend: Box::new(backend),
+            state: self.state.clone(),
+        }
+    }
+
+    /// Serves the recorded response for `request`.
+    ///
+    /// # Errors
+    /// Returns an error if no interaction with a matching fingerprint exists.
+    pub(crate) fn replay_response(&self, request: &CassetteRequest) -> Result<Response, Box<dyn std::error::Error>> {
+        let fingerprint = fingerprint(request)?;
+        let mut state = self.lock();
+        let matches: Vec<usize> = state.interactions
+            .iter()
+            .enumerate()
+            .filter(|(_, i)| i.fingerprint == fingerprint)
+            .map(|(n, _)| n)
+            .collect();
+        let Some(&last) = matches.last() else {
+            return Err(format!(
+                "No recorded interaction in cassette {} matches request {fingerprint} (last message: {:?})",
+                self.path.display(),
+                request.messages.last().map(|m| m.content.as_str()).unwrap_or_default(),
+            ).into());
+        };
+        let cursor = state.cursors.entry(fingerprint).or_default();
+        let index = matches.get(*cursor).copied().unwrap_or(last);
+        *cursor += 1;
+        let recorded = &state.interactions[index].response;
+        Ok(Response {
+            text: recorded.text.clone(),
+            usage: recorded.usage,
+            model: recorded.model.clone(),
+            finish_reason: recorded.finish_reason.clone(),
+            ..Default::default()
+        })
+    }
+
+    /// Appends an exchange and rewrites the cassette file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be written.
+    pub(crate) fn record_response(&self, request: CassetteRequest, response: &Response) -> Result<(), Box<dyn std::error::Error>> {
+        let interaction = Interaction {
+            fingerprint: fingerprint(&request)?,
+            request,
+            response: CassetteResponse {
+                text: response.text.clone(),
+                usage: response.usage,
+                model: response.model.clone(),
+                finish_reason: response.finish_reason.clone(),
+            },
+        };
+        let mut state = self.lock();
+        state.interactions.push(interaction);
+        let file = CassetteFile {
+            version: 1,
+            interactions: state.interactions.clone(),
+        };
+        std::fs::write(&self.path, serde_json::to_string_pretty(&file)?)?;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for Cassette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cassette")
+            .field("path", &self.path)
+            .field("mode", &self.mode)
+            .field("backend", &self.backend)
+            .finish()
+    }
+}
+
+impl PartialEq for Cassette {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path && self.mode == other.mode && self.backend == other.backend
+    }
+}
+
+/// Stable fingerprint of a request: FNV-1a (64 bit) over its canonical JSON.
+///
+/// `serde_json` sorts object keys, so the encoding does not depend on
+/// insertion order.
+fn fingerprint(request: &CassetteRequest) -> Result<String, serde_json::Error> {
+    let json = serde_json::to_string(&serde_json::to_value(request)?)?;
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in json.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    Ok(format!("{hash:016x}"))
+}
+
+#[cfg(all(test, feature="tools"))]
+mod tests {
+    use super::*;
+    use crate::{HistoryConfig, MockLlm, ModelOptions, Query};
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("erh_llm_{name}_{}.json", std::process::id()))
+    }
+
+    async fn ask(llm: &LLM, prompt: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let mut query = Query::new(llm.clone(), HistoryConfig::None);
+        query.setup.prompt = prompt.into();
+        Ok(query.execute().await?.text)
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_answers_in_order() {
+        let path = path("cassette_replay");
+        let live = MockLlm::new().reply("first").reply("second").reply("third");
+        let recorder = Cassette::record(&path, LLM::Dummy(live.clone()));
+        for (prompt, answer) in [("A", "first"), ("B", "second"), ("A", "third")] {
+            assert_eq!(ask(&recorder, prompt).await.unwrap(), answer);
+        }
+
+        let offline = MockLlm::new();
+        let player = Cassette::replay(&path, LLM::Dummy(offline.clone())).unwrap();
+        // Repeats of a request are served in recording order, then the last one again.
+        for (prompt, answer) in [("A", "first"), ("B", "second"), ("A", "third"), ("A", "third")] {
+            assert_eq!(ask(&player, prompt).await.unwrap(), answer);
+        }
+        assert!(offline.requests().is_empty());
+
+        let err = ask(&player, "C").await.unwrap_err();
+        assert!(err.to_string().contains("No recorded interaction"), "{err}");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn options_are_part_of_the_fingerprint() {
+        let path = path("cassette_options");
+        let recorder = Cassette::record(&path, LLM::Dummy(MockLlm::new().reply("warm")));
+        assert_eq!(ask(&recorder, "A").await.unwrap(), "warm");
+
+        let player = Cassette::replay(&path, LLM::Dummy(MockLlm::new())).unwrap();
+        let mut query = Query::new(player, HistoryConfig::None);
+        query.setup.prompt = "A".into();
+        query.options = ModelOptions::default().temperature(0.1);
+        assert!(query.execute().await.is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(Cassette::replay(&path, LLM::Dummy(MockLlm::new())).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+/// Module for human-in-the-loop approval of tool calls.
+///
+/// Tools carry a [`RiskLevel`] and may carry an async [`ToolApproval`] hook.
+/// The hook sees every call before the tool runs and approves it, denies it
+/// with a reason that is handed back to the model, or replaces its
+/// arguments. Each decision is kept as a [`ToolApprovalRecord`] on the
+/// [`Response`](crate::Response) and stored with the exchange in history.
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+/// Async hook deciding on a [`ToolCallRequest`] before the tool runs.
+pub type ToolApproval =
+    Arc<dyn Fn(ToolCallRequest) -> Pin<Box<dyn Future<Output = ToolDecision> + Send + Sync>> + Send + Sync>;
+
+/// How much harm a tool can do, used to decide which calls need approval.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskLevel {
+    /// Reads data or computes; safe to run unattended.
+    #[default]
+    Low,
+    /// Has side effects that are easy to undo.
+    Medium,
+    /// Writes or deletes data, spends money or contacts people.
+    High,
+}
+
+/// A tool call awaiting approval.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCallRequest {
+    /// Name of the tool.
+    pub tool: String,
+    /// Risk level of the tool.
+    pub risk: RiskLevel,
+    /// Arguments the model called the tool with.
+    pub arguments: serde_json::Value,
+}
+
+/// Outcome of a [`ToolApproval`] hook.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolDecision {
+    /// Run the call as requested.
+    Approve,
+    /// Do not run the call; the reason is returned to the model.
+    Deny(String),
+    /// Run the call with these arguments instead.
+    Modify(serde_json::Value),
+}
+
+impl ToolDecision {
+    /// Creates a [`ToolDecision::Deny`].
+    pub fn deny(reason: impl Into<String>) -> Self {
+        ToolDecision::Deny(reason.into())
+    }
+}
+
+/// A decision taken on a tool call, as stored in history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolApprovalRecord {
+    /// Name of the tool.
+    pub tool: String,
+    /// Risk level of the tool.
+    pub risk: RiskLevel,
+    /// Arguments the model called the tool with.
+    pub arguments: serde_json::Value,
+    /// What the hook decided.
+    pub decision: ToolDecision,
+}
+
+impl ToolApprovalRecord {
+    /// Pairs `request` with the `decision` taken on it.
+    pub fn new(request: ToolCallRequest, decision: ToolDecision) -> Self {
+        ToolApprovalRecord {
+            tool: request.tool,
+            risk: request.risk,
+            arguments: request.arguments,
+            decision,
+        }
+    }
+}
@@ -1,35 +1,84 @@
+pub (crate) mod approval;
 pub (crate) mod prompt;
 pub (crate) mod resource;
 pub (crate) mod sampling;
+pub (crate) mod selection;
 pub (crate) mod tools;
 
+use std::{future::Future, sync::Arc, time::Duration};
+
 use ollama_rs::coordinator::Coordinator;
+use ollama_rs::generation::tools::{ToolFunctionInfo, ToolHolder, ToolInfo, ToolType};
 use ollama_rs::history::ChatHistory;
 use schemars::Schema;
 
+use crate::components::approval::{RiskLevel, ToolApproval, ToolCallRequest, ToolDecision};
 use crate::components::prompt::Prompt;
-use crate::components::resource::Resource;
+use crate::components::resource::{Resource, ResourceContents, ResourceSubscription};
 use crate::components::sampling::Sampling;
 use crate::components::tools::Tool;
 
-#[derive(Clone,Default)]
+/// [`ComponentRegistry::tool_timeout`] of new registries.
+pub const DEFAULT_TOOL_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Clone)]
 pub struct ComponentRegistry {
     pub components: Vec<Component>,
+    /// Longest a call of a tool, or resource offered as a tool, may take when
+    /// it sets no timeout of its own; `None` leaves such calls unlimited.
+    /// Defaults to [`DEFAULT_TOOL_TIMEOUT`].
+    pub tool_timeout: Option<Duration>,
 }
 
-#[derive(Clone)]
+impl Default for ComponentRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where the contents of a [`Component`] come from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ComponentSource {
+    /// Implemented in this process.
+    #[default]
+    Local,
+    /// Proxied from a Model Context Protocol server; holds the server name it
+    /// reported during initialisation.
+    Mcp(String),
+}
+
+#[derive(Clone, Default)]
 pub struct Component {
-    //pub source: ComponentSource,
+    pub source: ComponentSource,
     pub tools: Vec<Tool>,
     pub resources: Vec<Resource>,
     pub prompts: Vec<Prompt>,
     pub samplings: Vec<Sampling>,
 }
 
+/// JSON Schema for tools that accept a single string parameter.
+///
+/// Schema::default() serialises as `{}` which Ollama cannot parse; we need
+/// a proper JSON-Schema object with type+properties so Ollama accepts the
+/// tool definition and knows how to call it.
+pub(crate) fn single_string_schema() -> serde_json::Value {
+    serde_json::json!({
+        "type": "object",
+        "properties": {
+            "param": {
+                "type": "string",
+                "description": "The input parameter for this tool."
+            }
+        },
+        "required": ["param"]
+    })
+}
+
 impl ComponentRegistry {
     /// Creates a new empty ComponentRegistry.
     ///
-    /// Initializes with an empty components vector.
+    /// Initializes with an empty components vector and
+    /// [`DEFAULT_TOOL_TIMEOUT`] as tool timeout.
     ///
     /// Returns:
     ///     Self: A new ComponentRegistry instance with empty components.
@@ -39,6 +88,7 @@ impl ComponentRegistry {
     pub fn new() -> Self {
         Self {
             components: Vec::new(),
+            tool_timeout: Some(DEFAULT_TOOL_TIMEOUT),
         }
     }
 
@@ -75,7 +125,193 @@ impl ComponentRegistry {
         self.components.push(component);
     }
 
-    /// Adds all tools and resources from registry components to a coordinator.
+    /// Looks up a prompt template by name.
+    ///
+    /// Parameters:
+    ///     name: Name of the prompt
+    ///
+    /// Returns:
+    ///     Option<&Prompt>: The first prompt registered under `name`, if any
+    pub fn prompt(&self, name: &str) -> Option<&Prompt> {
+        self.prompts().find(|p| p.name == name)
+    }
+
+    /// Iterates over the prompt templates of all components, e.g. to list
+    /// them in a UI.
+    ///
+    /// Returns:
+    ///     impl Iterator<Item = &Prompt>: Prompts in registration order
+    pub fn prompts(&self) -> impl Iterator<Item = &Prompt> {
+        self.components.iter().flat_map(|c| c.prompts.iter())
+    }
+
+    /// Renders the prompt template `name` with `input`.
+    ///
+    /// Parameters:
+    ///     name: Name of the prompt
+    ///     input: Input passed to the prompt function
+    ///
+    /// Returns:
+    ///     Option<String>: The rendered text, or None if no prompt has that name
+    pub fn render_prompt(&self, name: &str, input: &str) -> Option<String> {
+        self.prompt(name).map(|p| p.render(input))
+    }
+
+    /// Iterates over the resources of all components, e.g. to list them in a UI.
+    ///
+    /// Returns:
+    ///     impl Iterator<Item = &Resource>: Resources in registration order
+    pub fn resources(&self) -> impl Iterator<Item = &Resource> {
+        self.components.iter().flat_map(|c| c.resources.iter())
+    }
+
+    /// Finds the resource addressed by `uri`.
+    ///
+    /// Parameters:
+    ///     uri: A concrete URI, matched against resource URIs and URI templates
+    ///
+    /// Returns:
+    ///     Option<&Resource>: The first matching resource, if any
+    pub fn resource(&self, uri: &str) -> Option<&Resource> {
+        self.resources().find(|r| r.matches(uri))
+    }
+
+    /// Reads the resource at `uri`.
+    ///
+    /// Parameters:
+    ///     uri: A concrete URI
+    ///
+    /// Returns:
+    ///     Option<ResourceContents>: Content and MIME type, or None if no
+    ///     resource matches
+    pub async fn read_resource(&self, uri: &str) -> Option<ResourceContents> {
+        self.resource(uri)?.read(uri).await
+    }
+
+    /// Subscribes to updates of the resource at `uri`.
+    ///
+    /// Returns:
+    ///     Option<ResourceSubscription>: The subscription, or None if no
+    ///     resource matches
+    pub fn subscribe(&self, uri: &str) -> Option<ResourceSubscription> {
+        self.resource(uri)?.subscribe(uri)
+    }
+
+    /// The sampling policy applied to sub-completions requested by tools.
+    ///
+    /// Returns:
+    ///     Option<&Sampling>: The first registered policy, if any
+    pub fn sampling(&self) -> Option<&Sampling> {
+        self.components.iter().flat_map(|c| c.samplings.iter()).next()
+    }
+
+    /// Sets the approval hook of every tool at or above `min_risk` that has
+    /// none of its own (see [`Tool::approve`]). Only tools registered so
+    /// far are affected.
+    ///
+    /// Parameters:
+    ///     min_risk: Lowest risk level that requires approval
+    ///     approve: Async hook returning the decision on a call
+    ///
+    /// Example:
+    ///     registry.approve_tools(RiskLevel::High, |call: ToolCallRequest| async move {
+    ///         if confirm(&call).await { ToolDecision::Approve } else { ToolDecision::deny("rejected by operator") }
+    ///     });
+    pub fn approve_tools<F, Fut>(&mut self, min_risk: RiskLevel, approve: F)
+    where
+        F: Fn(ToolCallRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ToolDecision> + Send + Sync + 'static,
+    {
+        let approve: ToolApproval = Arc::new(move |request| Box::pin(approve(request)));
+        for tool in self.components.iter_mut().flat_map(|c| c.tools.iter_mut()) {
+            if tool.risk >= min_risk && tool.approve.is_none() {
+                log::debug!("Requiring approval for tool: {}", tool.name);
+                tool.approve = Some(approve.clone());
+            }
+        }
+    }
+
+    /// Definitions of all tools, and resources offered as tools, as sent to the model.
+    ///
+    /// Returns:
+    ///     Vec<ToolInfo>: One function definition per tool, with the tool's
+    ///     JSON schema (or a single string parameter when it has none), and
+    ///     one per resource with [`Resource::tool`] set
+    pub fn tool_infos(&self) -> Vec<ToolInfo> {
+        let single_string_schema = Schema::from(
+            single_string_schema().as_object().cloned().unwrap_or_default()
+        );
+        let info = |name: &str, description: String, parameters: Schema| ToolInfo {
+            tool_type: ToolType::Function,
+            function: ToolFunctionInfo { name: name.to_string(), description, parameters },
+        };
+
+        let mut infos = Vec::new();
+        for component in &self.components {
+            for tool in &component.tools {
+                let schema = match &tool.schema {
+                    Some(serde_json::Value::Object(schema)) => Schema::from(schema.clone()),
+                    _ => single_string_schema.clone(),
+                };
+                infos.push(info(&tool.name, tool.description.clone(), schema));
+            }
+            for resource in component.resources.iter().filter(|r| r.tool) {
+                let description = match &resource.uri {
+                    Some(uri) if resource.is_template() => format!("{} (pass a URI matching {uri})", resource.description),
+                    _ => resource.description.clone(),
+                };
+                infos.push(info(&resource.name, description, single_string_schema.clone()));
+            }
+        }
+        infos
+    }
+
+    /// Looks up the tool called `name`, with [`ComponentRegistry::tool_timeout`]
+    /// applied unless it has its own.
+    pub(crate) fn tool(&self, name: &str) -> Option<Tool> {
+        self.components.iter()
+            .flat_map(|c| c.tools.iter())
+            .find(|t| t.name == name)
+            .map(|t| {
+                let mut tool = t.clone();
+                tool.timeout = tool.timeout.or(self.tool_timeout);
+                tool
+            })
+    }
+
+    /// Looks up the tool, or resource offered as a tool, called `name`, with
+    /// [`ComponentRegistry::tool_timeout`] applied unless it has its own.
+    fn tool_holder(&self, name: &str) -> Option<Box<dyn ToolHolder>> {
+        let tool = self.tool(name).map(|tool| Box::new(tool) as Box<dyn ToolHolder>);
+        tool.or_else(|| self.components.iter()
+            .flat_map(|c| c.resources.iter())
+            .find(|r| r.tool && r.name == name)
+            .map(|r| {
+                let mut resource = r.clone();
+                resource.timeout = resource.timeout.or(self.tool_timeout);
+                Box::new(resource) as Box<dyn ToolHolder>
+            }))
+    }
+
+    /// Calls the tool, or resource offered as a tool, called `name`.
+    ///
+    /// Parameters:
+    ///     name: Name the model called the tool by
+    ///     arguments: JSON arguments of the call
+    ///
+    /// Returns:
+    ///     Option<Result<String, Error>>: `None` if no such tool is registered,
+    ///     otherwise the text handed back to the model or the resource's error
+    pub async fn call_tool(&self, name: &str, arguments: serde_json::Value) -> Option<Result<String, Box<dyn std::error::Error + Send + Sync>>> {
+        let mut holder = self.tool_holder(name)?;
+        log::debug!("Calling tool: {name}");
+        Some(holder.call(arguments).await)
+    }
+
+    /// Adds all tools, and resources offered as tools, from registry components to a coordinator.
+    ///
+    /// [`Query`](crate::Query) runs its own [`AgentLoop`](crate::AgentLoop);
+    /// this is kept for driving an `ollama_rs` coordinator directly.
     ///
     /// Parameters:
     ///     coordinator: Coordinator<T> to which tools will be added
@@ -88,45 +324,17 @@ impl ComponentRegistry {
     ///     - Modifies the provided coordinator by adding tools
     ///
     /// Process:
-    ///     1. Iterates through each component in the registry
-    ///     2. Adds all component tools to the coordinator
-    ///     3. Adds all component resources to the coordinator
-    ///     4. Returns the modified coordinator
+    ///     1. Builds the definitions of [`ComponentRegistry::tool_infos`]
+    ///     2. Adds each of them with the tool or resource implementing it
+    ///     3. Returns the modified coordinator
     pub fn add_tools<T: ChatHistory>(&mut self, coordinator : Coordinator<T>) -> Coordinator<T> {
         let mut cd = coordinator;
         log::debug!("Adding tools from ComponentRegistry with {} components", self.components.len());
-        // Build a reusable schema for tools that accept a single string parameter.
-        // Schema::default() serialises as `{}` which Ollama cannot parse; we need
-        // a proper JSON-Schema object with type+properties so Ollama accepts the
-        // tool definition and knows how to call it.
-        let single_string_schema = Schema::from(
-            serde_json::json!({
-                "type": "object",
-                "properties": {
-                    "param": {
-                        "type": "string",
-                        "description": "The input parameter for this tool."
-                    }
-                },
-                "required": ["param"]
-            })
-            .as_object()
-            .cloned()
-            .unwrap_or_default()
-        );
-
-        for component in &self.components {
-            for tool in &component.tools {
-                log::debug!("Adding tool: {}", tool.name);
-                cd = cd.add_tool_custom_schema(tool.name.as_str(), tool.description.as_str(), single_string_schema.clone(), Box::new(tool.clone()));
-
-            }
-            for resource in &component.resources {
-                log::debug!("Adding resource: {}", resource.name);
-                cd = cd.add_tool_custom_schema(resource.name.as_str(), resource.description.as_str(), single_string_schema.clone(), Box::new(resource.clone()));
-
-            }
-        };
+        for info in self.tool_infos() {
+            let Some(holder) = self.tool_holder(&info.function.name) else { continue };
+            log::debug!("Adding tool: {}", info.function.name);
+            cd = cd.add_tool_custom_schema(&info.function.name, &info.function.description, info.function.parameters, holder);
+        }
         cd
     }
 }
@@ -22,3 +22,86 @@ pub struct Prompt {
     /// |input: &str| -> String { ... }
     pub func: Arc<dyn Fn(&str) -> String + Send + Sync>,
 }
+
+impl Prompt {
+    /// Creates a new Prompt instance
+    ///
+    /// # Parameters
+    /// - `name`: The unique identifier for this prompt template
+    /// - `description`: Documentation describing the prompt's purpose
+    /// - `func`: The function that renders the template from its input
+    ///
+    /// # Returns
+    /// A new Prompt instance with the provided configuration
+    pub fn new<F>(name: &str, description: &str, func: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        Prompt {
+            name: name.to_string(),
+            description: description.to_string(),
+            func: Arc::new(func),
+        }
+    }
+
+    /// Renders the template with `input`.
+    ///
+    /// Templates taking several arguments conventionally receive them as a
+    /// JSON object, which is also how MCP prompt arguments are passed.
+    pub fn render(&self, input: &str) -> String {
+        (self.func)(input)
+    }
+}
+
+/// Where the output of a rendered [`Prompt`] goes in the composed prompt.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PromptSlot {
+    /// Replaces the user turn.
+    #[default]
+    User,
+    /// Prepended to the [`QuerySetup::constraint`](crate::QuerySetup::constraint)
+    /// (the system instruction).
+    Constraint,
+    /// Prepended to the [`QuerySetup::style`](crate::QuerySetup::style).
+    Style,
+}
+
+/// Selects a registry [`Prompt`] to render into a query.
+///
+/// ```rust,ignore
+/// query.setup.prompt = "The meeting moved to Friday.".into();
+/// query.setup.template = Some(PromptTemplate::new("translate").input(r#"{"lang": "fr"}"#).slot(PromptSlot::Constraint));
+/// let answer = query.execute().await?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptTemplate {
+    /// Name of the prompt in the component registry.
+    pub name: String,
+    /// Input passed to the prompt. Defaults to [`QuerySetup::prompt`](crate::QuerySetup::prompt).
+    pub input: Option<String>,
+    /// Where the rendered text goes.
+    pub slot: PromptSlot,
+}
+
+impl PromptTemplate {
+    /// Selects the prompt `name`, rendered from the query prompt into the user turn.
+    pub fn new(name: &str) -> Self {
+        PromptTemplate {
+            name: name.to_string(),
+            input: None,
+            slot: PromptSlot::default(),
+        }
+    }
+
+    /// Renders the prompt from `input` instead of the query prompt.
+    pub fn input(mut self, input: &str) -> Self {
+        self.input = Some(input.to_string());
+        self
+    }
+
+    /// Sets where the rendered text goes.
+    pub fn slot(mut self, slot: PromptSlot) -> Self {
+        self.slot = slot;
+        self
+    }
+}
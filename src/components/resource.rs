@@ -1,11 +1,34 @@
-use std::{pin::Pin, sync::Arc};
-use futures::Future;
+use std::{collections::BTreeMap, pin::Pin, sync::{Arc, Mutex}, time::{Duration, Instant}};
+use futures::{channel::mpsc, Future, StreamExt};
 use ollama_rs::generation::tools::ToolHolder;
 
+use crate::components::sampling::SamplingHandle;
+use crate::components::tools::{record_call, ToolCallRecord, ToolError};
+
+/// URI prefix under which resources without their own URI are addressed.
+pub(crate) const LOCAL_URI_PREFIX: &str = "erh://resource/";
+
+/// Variables captured from a URI by a resource's URI template.
+pub type UriVariables = BTreeMap<String, String>;
+
+/// Async function producing a resource's text from its parameter.
+pub type ResourceFn =
+    Arc<dyn for<'a> Fn(&'a String) -> Pin<Box<dyn Future<Output = String> + Send + Sync + 'a>> + Send + Sync>;
+
+/// Open subscriptions of a resource: the subscribed URI and its channel.
+type Subscribers = Arc<Mutex<Vec<(String, mpsc::UnboundedSender<String>)>>>;
+
 /// A reusable resource component with async execution capabilities.
 ///
 /// Resources encapsulate named functions that process string parameters asynchronously.
-/// They can be used as tools in the LLM coordination system by implementing ToolHolder.
+/// Resources created with [`Resource::new`] are offered to the model as tools
+/// (through ToolHolder) and addressed as `erh://resource/<name>`.
+///
+/// Resources created with [`Resource::with_uri`] are addressed by a URI or
+/// URI template such as `file:///{path}` or `db://table/{id}`. They are read
+/// through [`ComponentRegistry::read_resource`](crate::ComponentRegistry::read_resource)
+/// or attached to the prompt via [`QuerySetup::resources`](crate::QuerySetup::resources),
+/// and only offered as tools when [`Resource::tool`] is set.
 #[derive(Clone)]
 pub struct Resource {
     /// Unique identifier for this resource
@@ -14,11 +37,63 @@ pub struct Resource {
     /// Human-readable description explaining this resource's purpose and functionality
     pub description: String,
 
+    /// URI or URI template (`{name}` placeholders) addressing this resource
+    ///
+    /// A placeholder matches up to the next literal part of the template; a
+    /// trailing placeholder matches the rest of the URI.
+    pub uri: Option<String>,
+
+    /// MIME type of the content; `text/plain` when unset
+    pub mime_type: Option<String>,
+
+    /// Whether the resource is offered to the model as a callable tool
+    pub tool: bool,
+
+    /// Longest a call as a tool may take before it fails with
+    /// [`ToolError::Timeout`]; the registry's
+    /// [`tool_timeout`](crate::ComponentRegistry::tool_timeout) when unset
+    pub timeout: Option<Duration>,
+
     /// Asynchronous function that processes string parameters
     ///
     /// This is a thread-safe, cloneable function that takes a string reference and returns
     /// a future that resolves to a String. The function is wrapped in an Arc for safe sharing.
-    pub func: Arc<dyn for<'a> Fn(&'a String) -> Pin<Box<dyn Future<Output = String> + Send + Sync + 'a>> + Send + Sync>,
+    /// For resources with a URI the parameter is the URI being read.
+    pub func: ResourceFn,
+
+    /// Subscriptions waiting for [`Resource::notify_updated`]
+    subscribers: Subscribers,
+}
+
+/// The content of a resource read by URI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceContents {
+    /// The URI that was read.
+    pub uri: String,
+    /// MIME type of `text`.
+    pub mime_type: String,
+    /// The content.
+    pub text: String,
+}
+
+/// Receives the URIs of a resource whenever its owner reports them updated.
+#[derive(Debug)]
+pub struct ResourceSubscription {
+    uri: String,
+    receiver: mpsc::UnboundedReceiver<String>,
+}
+
+impl ResourceSubscription {
+    /// The subscribed URI.
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    /// Waits for the next update and returns the updated URI, or `None` once
+    /// the resource is gone.
+    pub async fn updated(&mut self) -> Option<String> {
+        self.receiver.next().await
+    }
 }
 
 impl Resource {
@@ -44,12 +119,155 @@ impl Resource {
         Resource {
             name: name.to_string(),
             description: description.to_string(),
+            uri: None,
+            mime_type: None,
+            tool: true,
+            timeout: None,
             func: Arc::new(move |param: &String| {
                 Box::pin(func(param))
             }),
+            subscribers: Subscribers::default(),
         }
     }
 
+    /// Creates a Resource addressed by a URI or URI template.
+    ///
+    /// Parameters:
+    ///     uri: URI or URI template, e.g. `db://table/{id}`
+    ///     name: Identifier for this resource
+    ///     description: Documentation string explaining the resource's behavior
+    ///     func: Asynchronous function that takes the URI being read and the
+    ///           variables captured by the template, and returns the content
+    ///
+    /// Returns:
+    ///     Resource: A new Resource, not offered as a tool
+    ///
+    /// Example:
+    ///     let orders = Resource::with_uri("db://orders/{id}", "orders", "One order as JSON", |_, vars| {
+    ///         let id = vars.get("id").cloned().unwrap_or_default();
+    ///         async move { load_order(&id).await }
+    ///     }).mime_type("application/json");
+    pub fn with_uri<F, Fut>(uri: &str, name: &str, description: &str, func: F) -> Self
+    where
+        F: for<'a> Fn(&'a String, UriVariables) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = String> + Send + Sync + 'static,
+    {
+        let template = uri.to_string();
+        Resource {
+            uri: Some(uri.to_string()),
+            tool: false,
+            ..Resource::new(name, description, move |param: &String| {
+                func(param, uri_variables(&template, param).unwrap_or_default())
+            })
+        }
+    }
+
+    /// Creates a Resource that can request sub-completions from the LLM.
+    ///
+    /// Parameters:
+    ///     name: Identifier for this resource
+    ///     description: Documentation string explaining the resource's behavior
+    ///     func: Asynchronous function that takes a String parameter and the
+    ///           [`SamplingHandle`] of the calling query, and returns a String
+    ///
+    /// Returns:
+    ///     Resource: A new Resource instance with the provided configuration
+    pub fn with_sampling<F, Fut>(name: &str, description: &str, func: F) -> Self
+    where
+        F: for<'a> Fn(&'a String, SamplingHandle) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = String> + Send + Sync + 'static,
+    {
+        Resource::new(name, description, move |param: &String| func(param, SamplingHandle::current()))
+    }
+
+    /// Sets the MIME type of the content.
+    pub fn mime_type(mut self, mime_type: &str) -> Self {
+        self.mime_type = Some(mime_type.to_string());
+        self
+    }
+
+    /// Limits each call as a tool to `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets whether the resource is offered to the model as a callable tool.
+    pub fn as_tool(mut self, tool: bool) -> Self {
+        self.tool = tool;
+        self
+    }
+
+    /// The URI or URI template addressing this resource.
+    ///
+    /// Returns:
+    ///     String: [`Resource::uri`], or `erh://resource/<name>` when unset
+    pub fn address(&self) -> String {
+        self.uri.clone().unwrap_or_else(|| format!("{LOCAL_URI_PREFIX}{}", self.name))
+    }
+
+    /// Returns `true` if the resource is addressed by a URI template rather
+    /// than a single URI.
+    pub fn is_template(&self) -> bool {
+        self.uri.as_deref().is_some_and(|uri| uri.contains('{'))
+    }
+
+    /// Returns `true` if `uri` addresses this resource.
+    pub fn matches(&self, uri: &str) -> bool {
+        uri_variables(&self.address(), uri).is_some()
+    }
+
+    /// Reads the resource at `uri`.
+    ///
+    /// Resources without their own URI are called with an empty parameter.
+    ///
+    /// Returns:
+    ///     Option<ResourceContents>: The content, or None if `uri` does not
+    ///     address this resource
+    pub async fn read(&self, uri: &str) -> Option<ResourceContents> {
+        if !self.matches(uri) {
+            return None;
+        }
+        let param = if self.uri.is_some() { uri.to_string() } else { String::new() };
+        let text = self.execute(&param).await?;
+        Some(ResourceContents {
+            uri: uri.to_string(),
+            mime_type: self.mime_type.clone().unwrap_or_else(|| "text/plain".to_string()),
+            text,
+        })
+    }
+
+    /// Subscribes to updates of `uri`, reported by [`Resource::notify_updated`].
+    ///
+    /// Returns:
+    ///     Option<ResourceSubscription>: The subscription, or None if `uri`
+    ///     does not address this resource
+    pub fn subscribe(&self, uri: &str) -> Option<ResourceSubscription> {
+        if !self.matches(uri) {
+            return None;
+        }
+        let (sender, receiver) = mpsc::unbounded();
+        self.lock_subscribers().push((uri.to_string(), sender));
+        Some(ResourceSubscription { uri: uri.to_string(), receiver })
+    }
+
+    /// Tells subscribers of `uri` that its content changed. Call this on any
+    /// clone of the resource; clones share their subscribers.
+    pub fn notify_updated(&self, uri: &str) {
+        let mut subscribers = self.lock_subscribers();
+        subscribers.retain(|(_, sender)| !sender.is_closed());
+        log::debug!("Resource {} updated: {uri} ({} subscriptions)", self.name, subscribers.len());
+        for (subscribed, sender) in subscribers.iter() {
+            if subscribed == uri {
+                let _ = sender.unbounded_send(uri.to_string());
+            }
+        }
+    }
+
+    fn lock_subscribers(&self) -> std::sync::MutexGuard<'_, Vec<(String, mpsc::UnboundedSender<String>)>> {
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
     /// Executes the resource's async function with the given parameter.
     ///
     /// Parameters:
@@ -68,6 +286,57 @@ impl Resource {
         let fut = (self.func)(param).await;
         Some(fut)
     }
+
+    /// Answers a tool call with `parameters`; see [`ToolHolder::call`].
+    async fn answer(&self, parameters: &serde_json::Value) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let param_str = match &self.uri {
+            None => serde_json::to_string(parameters)?,
+            Some(_) if self.is_template() => match parameters {
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Object(map) => map.values().find_map(|v| v.as_str().map(str::to_string)).unwrap_or_default(),
+                _ => String::new(),
+            },
+            Some(uri) => uri.clone(),
+        };
+        if self.uri.is_some() {
+            return match self.read(&param_str).await {
+                Some(contents) => Ok(contents.text),
+                None => Err(format!("'{param_str}' is not a URI of resource {}", self.name).into()),
+            };
+        }
+        let result = self.execute(&param_str).await;
+        match result {
+            Some(res) => Ok(res),
+            None => Err("Tool execution failed".into()),
+        }
+    }
+}
+
+/// Variables of `uri` under `template`, or `None` if it does not match.
+///
+/// Adjacent placeholders (`{a}{b}`) cannot be told apart and never match.
+pub(crate) fn uri_variables(template: &str, uri: &str) -> Option<UriVariables> {
+    let mut variables = UriVariables::new();
+    let mut template = template;
+    let mut rest = uri;
+    while let Some(open) = template.find('{') {
+        rest = rest.strip_prefix(&template[..open])?;
+        let close = open + template[open..].find('}')?;
+        let name = &template[open + 1..close];
+        template = &template[close + 1..];
+        let literal = &template[..template.find('{').unwrap_or(template.len())];
+        let len = match (literal.is_empty(), template.is_empty()) {
+            (false, _) => rest.find(literal)?,
+            (true, true) => rest.len(),
+            (true, false) => return None,
+        };
+        if len == 0 {
+            return None;
+        }
+        variables.insert(name.to_string(), rest[..len].to_string());
+        rest = &rest[len..];
+    }
+    (rest == template).then_some(variables)
 }
 
 impl ToolHolder for Resource {
@@ -83,9 +352,12 @@ impl ToolHolder for Resource {
     ///     - Returns the result or an error if serialization failed
     ///
     /// This implementation:
-    /// 1. Converts input parameters to JSON string
+    /// 1. Converts input parameters to JSON string (resources without a URI)
+    ///    or reads the resource's URI; templated resources take the URI to
+    ///    read as their string parameter
     /// 2. Executes the resource with the serialized parameters
     /// 3. Returns successful result or error message
+    /// 4. Records the call in the running turn's history (see [`ToolCallRecord`])
     ///
     /// Note: This must be awaited to get the final result
     fn call(
@@ -93,12 +365,24 @@ impl ToolHolder for Resource {
         parameters: serde_json::Value,
     ) -> Pin<Box<dyn Future<Output = Result<String, Box<dyn std::error::Error + Send + Sync>>> + '_ + Send + Sync>> {
         Box::pin(async move {
-            let param_str = serde_json::to_string(&parameters)?;
-            let result = self.execute(&param_str).await;
-            match result {
-                Some(res) => Ok(res),
-                None => Err("Tool execution failed".into()),
-            }
+            let start = Instant::now();
+            let result = match self.timeout {
+                Some(limit) => tokio::time::timeout(limit, self.answer(&parameters))
+                    .await
+                    .unwrap_or_else(|_| Err(Box::new(ToolError::Timeout(limit)))),
+                None => self.answer(&parameters).await,
+            };
+            record_call(ToolCallRecord {
+                name: self.name.clone(),
+                result: match &result {
+                    Ok(text) => text.clone(),
+                    Err(e) => e.to_string(),
+                },
+                arguments: parameters,
+                duration: start.elapsed(),
+                error: result.as_ref().err().map(ToString::to_string),
+            });
+            result
         })
     }
 }
@@ -1,31 +1,225 @@
-/// Module for defining sampling strategies used in the system.
-/// Each sampling strategy is encapsulated in a `Sampling` struct.
-use std::sync::Arc;
+/// Module for model-initiated sub-completions ("sampling").
+///
+/// Tools and resources can ask the LLM for a nested completion while they
+/// run, much like MCP servers ask their client via `sampling/createMessage`.
+/// The request goes through a [`SamplingHandle`] and is answered by the
+/// running [`Query`](crate::Query) under the [`Sampling`] policy registered
+/// in its [`ComponentRegistry`](crate::ComponentRegistry).
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+use futures::channel::{mpsc, oneshot};
+
+use crate::Response;
 
-/// Represents a sampling strategy with a name, description, and implementation.
+/// Async hook deciding on a [`SamplingRequest`]: `Ok` with the (possibly
+/// modified) request to run it, `Err` with a reason to deny it.
+pub type SamplingApproval =
+    Arc<dyn Fn(SamplingRequest) -> Pin<Box<dyn Future<Output = Result<SamplingRequest, String>> + Send>> + Send + Sync>;
+
+/// Policy under which sub-completions requested by tools are run.
+///
+/// Sampling is opt-in: without a `Sampling` in the registry every request is
+/// refused with [`SamplingError::Unavailable`]. The first registered policy
+/// applies to all tools of a query.
 ///
-/// This struct is used to encapsulate different sampling methods where:
-/// 1. `name` identifies the sampling type
-/// 2. `description` provides human-readable documentation
-/// 3. `func` contains the actual implementation logic
+/// Sub-completions are single turns without chat history and without tools,
+/// so they cannot recurse.
 #[derive(Clone)]
 pub struct Sampling {
-    /// Unique identifier for this sampling method (e.g., "random", "stratified")
+    /// Unique identifier for this sampling policy
     pub name: String,
 
-    /// Human-readable description explaining the sampling behavior
-    /// This should clarify what the sampling method does and any parameters it uses
+    /// Human-readable description of the policy
     pub description: String,
 
-    /// Sampling implementation function
+    /// Model profile (see [`ModelRegistry`](crate::ModelRegistry)) or model
+    /// name used for sub-completions.
+    ///
+    /// When unset, a request's model hint is honoured if it names a profile
+    /// in [`Query::models`](crate::Query::models); otherwise the query's
+    /// model is used.
+    pub model: Option<String>,
+
+    /// Upper bound on the tokens a sub-completion may generate, applied on
+    /// top of the request's own limit.
+    pub max_tokens: Option<u32>,
+
+    /// Optional hook that approves, modifies or denies each request before
+    /// it is sent.
+    pub approve: Option<SamplingApproval>,
+}
+
+impl Sampling {
+    /// Creates a policy that runs every request on the query's model
     ///
-    /// Takes input data as a string and returns sampled output as a string.
-    /// The function is wrapped in an `Arc` to enable:
-    /// - Thread-safe shared ownership (Send + Sync)
-    /// - Efficient cloning without data duplication
+    /// # Parameters
+    /// - `name`: The unique identifier for this policy
+    /// - `description`: Documentation describing the policy
+    pub fn new(name: &str, description: &str) -> Self {
+        Sampling {
+            name: name.to_string(),
+            description: description.to_string(),
+            model: None,
+            max_tokens: None,
+            approve: None,
+        }
+    }
+
+    /// Runs sub-completions on the model profile or model `model`.
+    pub fn model(mut self, model: &str) -> Self {
+        self.model = Some(model.to_string());
+        self
+    }
+
+    /// Caps sub-completions at `max_tokens` generated tokens.
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Sets the approval hook.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// let policy = Sampling::new("guarded", "Short answers only").approve(|request: SamplingRequest| async move {
+    ///     if request.prompt.len() > 4000 {
+    ///         return Err("prompt too long".to_string());
+    ///     }
+    ///     Ok(request.max_tokens(256))
+    /// });
+    /// ```
+    pub fn approve<F, Fut>(mut self, approve: F) -> Self
+    where
+        F: Fn(SamplingRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<SamplingRequest, String>> + Send + 'static,
+    {
+        self.approve = Some(Arc::new(move |request| Box::pin(approve(request))));
+        self
+    }
+}
+
+impl fmt::Debug for Sampling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sampling")
+            .field("name", &self.name)
+            .field("model", &self.model)
+            .field("max_tokens", &self.max_tokens)
+            .field("approve", &self.approve.is_some())
+            .finish()
+    }
+}
+
+/// A sub-completion requested by a tool.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SamplingRequest {
+    /// Optional system message.
+    pub system: Option<String>,
+    /// The user message.
+    pub prompt: String,
+    /// Maximum number of tokens to generate.
+    pub max_tokens: Option<u32>,
+    /// Preferred model profile; only a hint, see [`Sampling::model`].
+    pub model: Option<String>,
+}
+
+impl SamplingRequest {
+    /// Creates a request for a completion of `prompt`.
+    pub fn new(prompt: impl Into<String>) -> Self {
+        SamplingRequest { prompt: prompt.into(), ..Default::default() }
+    }
+
+    /// Sets the system message.
+    pub fn system(mut self, system: impl Into<String>) -> Self {
+        self.system = Some(system.into());
+        self
+    }
+
+    /// Limits the completion to `max_tokens` generated tokens.
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Hints at the model profile to use.
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+}
+
+/// Why a sampling request was not answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SamplingError {
+    /// The tool is not running inside a query with a [`Sampling`] policy.
+    Unavailable,
+    /// The approval hook denied the request; holds its reason.
+    Denied(String),
+    /// The completion failed; holds the backend error.
+    Failed(String),
+}
+
+impl fmt::Display for SamplingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SamplingError::Unavailable => f.write_str("sampling is not available"),
+            SamplingError::Denied(reason) => write!(f, "sampling request denied: {reason}"),
+            SamplingError::Failed(e) => write!(f, "sampling failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SamplingError {}
+
+/// A request on its way to the query, with the channel for its answer.
+pub(crate) type SamplingCall = (SamplingRequest, oneshot::Sender<Result<Response, SamplingError>>);
+
+tokio::task_local! {
+    static SAMPLING: SamplingHandle;
+}
+
+/// Lets a running tool request sub-completions from the query that called it.
+///
+/// Tools created with [`Tool::with_sampling`](crate::Tool::with_sampling) or
+/// [`Resource::with_sampling`](crate::Resource::with_sampling) receive one;
+/// others can use [`SamplingHandle::current`].
+#[derive(Debug, Clone, Default)]
+pub struct SamplingHandle {
+    sender: Option<mpsc::UnboundedSender<SamplingCall>>,
+}
+
+impl SamplingHandle {
+    /// The handle of the query running the current tool call. Outside of one
+    /// (or without a [`Sampling`] policy) the handle refuses every request.
+    pub fn current() -> Self {
+        SAMPLING.try_with(Clone::clone).unwrap_or_default()
+    }
+
+    /// Returns `true` if requests can be answered.
+    pub fn is_available(&self) -> bool {
+        self.sender.as_ref().is_some_and(|s| !s.is_closed())
+    }
+
+    /// Requests a completion and waits for it.
     ///
-    /// The signature Fn(&str) -> String means:
-    /// - Accepts input as a string slice
-    /// - Returns a new owned string as output
-    pub func: Arc<dyn Fn(&str) -> String + Send + Sync>,
+    /// # Errors
+    /// Returns [`SamplingError::Unavailable`] outside a query with a sampling
+    /// policy, [`SamplingError::Denied`] if the approval hook refuses, and
+    /// [`SamplingError::Failed`] if the backend call fails.
+    pub async fn sample(&self, request: SamplingRequest) -> Result<Response, SamplingError> {
+        let sender = self.sender.as_ref().ok_or(SamplingError::Unavailable)?;
+        let (reply, answer) = oneshot::channel();
+        sender.unbounded_send((request, reply)).map_err(|_| SamplingError::Unavailable)?;
+        answer.await.map_err(|_| SamplingError::Unavailable)?
+    }
+
+    /// A connected handle and the receiving end the query serves.
+    pub(crate) fn channel() -> (Self, mpsc::UnboundedReceiver<SamplingCall>) {
+        let (sender, receiver) = mpsc::unbounded();
+        (SamplingHandle { sender: Some(sender) }, receiver)
+    }
+
+    /// Runs `fut` with this handle as [`SamplingHandle::current`].
+    pub(crate) async fn scope<F: Future>(self, fut: F) -> F::Output {
+        SAMPLING.scope(self, fut).await
+    }
 }
@@ -0,0 +1,189 @@
+/// Module for offering only the tools relevant to a prompt.
+///
+/// With many tools registered, sending every schema on each turn can exceed
+/// the context of small models. A [`ToolSelection`] embeds the name and
+/// description of every tool once, embeds the prompt of each query, and
+/// offers only the `top_k` most similar tools together with the pinned ones,
+/// which are offered on every turn. Tools whose text cannot be embedded are
+/// offered as well rather than silently dropped.
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use log::{debug, warn};
+use ollama_rs::generation::tools::ToolInfo;
+
+use crate::components::ComponentRegistry;
+use crate::{router::cosine_similarity, ModelConfig, Query, RetryPolicy};
+
+/// Relevance-based choice of the tools offered to the model; see the
+/// [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct ToolSelection {
+    /// Ollama instance and embedding model: `(host, port, model_config)`.
+    pub config: (String, u16, ModelConfig),
+    /// How many tools to offer besides the pinned ones.
+    pub top_k: usize,
+    /// Names of the tools offered on every turn.
+    pub pinned: Vec<String>,
+    /// Policy for the embedding calls; `None` uses the [`Query::retry`] of
+    /// the query running the selection, or the default policy elsewhere.
+    pub retry: Option<RetryPolicy>,
+    /// Embeddings of the tool texts seen so far, shared between clones.
+    embeddings: Arc<Mutex<HashMap<String, Vec<f32>>>>,
+}
+
+impl ToolSelection {
+    /// Creates a selection offering the `top_k` tools most relevant to the
+    /// prompt, using the embedding model of `config`.
+    pub fn new(config: (String, u16, ModelConfig), top_k: usize) -> Self {
+        ToolSelection {
+            config,
+            top_k,
+            pinned: Vec::new(),
+            retry: None,
+            embeddings: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Always offers the tool called `name`.
+    pub fn pin(mut self, name: impl Into<String>) -> Self {
+        self.pinned.push(name.into());
+        self
+    }
+
+    /// Sets [`ToolSelection::retry`].
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Embeds the tools of `registry` now rather than on the first query.
+    ///
+    /// # Errors
+    /// Returns an error if the embedding backend reports a failure.
+    pub async fn prepare(&self, registry: &ComponentRegistry) -> Result<(), Box<dyn std::error::Error>> {
+        for tool in registry.tool_infos() {
+            self.embedding(&tool).await?;
+        }
+        Ok(())
+    }
+
+    /// Picks the tools to offer for `prompt` out of `tools`, keeping their order.
+    ///
+    /// # Parameters
+    /// - `tools`: Definitions of all available tools
+    /// - `prompt`: The user prompt the tools should help answer
+    ///
+    /// # Returns
+    /// The pinned tools and the `top_k` others whose name and description are
+    /// most similar to `prompt`, plus any tool whose own text could not be
+    /// embedded, since its relevance is unknown. All tools are returned when
+    /// there are no more than that, or when the prompt cannot be embedded.
+    /// Embedding failures are logged, never returned.
+    pub async fn select(&self, tools: Vec<ToolInfo>, prompt: &str) -> Vec<ToolInfo> {
+        let (pinned, candidates): (Vec<&ToolInfo>, Vec<&ToolInfo>) =
+            tools.iter().partition(|t| self.pinned.contains(&t.function.name));
+        if candidates.len() <= self.top_k {
+            return tools;
+        }
+        let query = match self.embed(prompt.to_string()).await {
+            Ok(query) => query,
+            Err(e) => {
+                warn!("Could not embed the prompt ({e}); offering all {} tools", tools.len());
+                return tools;
+            }
+        };
+
+        let mut scored = Vec::with_capacity(candidates.len());
+        let mut unscored = Vec::new();
+        for tool in candidates {
+            match self.embedding(tool).await {
+                Ok(embedding) => scored.push((cosine_similarity(&query, &embedding), tool.function.name.as_str())),
+                Err(e) => {
+                    warn!("Could not embed tool {} ({e}); offering it anyway", tool.function.name);
+                    unscored.push(tool.function.name.as_str());
+                }
+            }
+        }
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let chosen: Vec<String> = pinned.iter()
+            .map(|t| t.function.name.clone())
+            .chain(scored.iter().take(self.top_k).map(|(_, name)| name.to_string()))
+            .chain(unscored.into_iter().map(str::to_string))
+            .collect();
+        debug!("Offering tools {chosen:?} out of {}", tools.len());
+        tools.into_iter().filter(|t| chosen.contains(&t.function.name)).collect()
+    }
+
+    /// Embedding of the name and description of `tool`, computed on first use.
+    async fn embedding(&self, tool: &ToolInfo) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        let text = format!("{}: {}", tool.function.name, tool.function.description);
+        if let Some(embedding) = self.lock().get(&text) {
+            return Ok(embedding.clone());
+        }
+        // Failed embeddings are not cached, so they are retried on the next query.
+        let embedding = self.embed(text.clone()).await?;
+        self.lock().insert(text, embedding.clone());
+        Ok(embedding)
+    }
+
+    async fn embed(&self, text: String) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        let retry = self.retry.clone().unwrap_or_default();
+        Query::embed_with_retry(self.config.clone(), text, &retry).await
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Vec<f32>>> {
+        self.embeddings.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(all(test, feature="testing"))]
+mod tests {
+    use super::*;
+    use crate::{testing::FakeOllama, Component, Tool};
+
+    fn registry() -> ComponentRegistry {
+        let tool = |name: &str| Tool::new(name, &format!("The {name} tool"), |s: &String| {
+            let s = s.clone();
+            async move { s }
+        });
+        let mut registry = ComponentRegistry::new();
+        registry.register(Component {
+            tools: ["weather", "stock", "shout", "calc"].into_iter().map(tool).collect(),
+            ..Default::default()
+        });
+        registry
+    }
+
+    fn names(tools: &[ToolInfo]) -> Vec<&str> {
+        tools.iter().map(|t| t.function.name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn offers_the_most_similar_and_pinned_tools() {
+        let server = FakeOllama::start().await.unwrap();
+        let selection = ToolSelection::new(server.embed_config(ModelConfig::new("nomic-embed-text")), 1).pin("calc");
+        // The prompt first, then each unpinned tool in registry order.
+        server.embedding(vec![1.0, 0.0]).embedding(vec![0.0, 1.0]).embedding(vec![1.0, 0.1]).embedding(vec![0.5, 0.5]);
+        assert_eq!(names(&selection.select(registry().tool_infos(), "How is ACME trading?").await), ["stock", "calc"]);
+
+        // Tool embeddings are cached; only the new prompt is embedded.
+        server.embedding(vec![0.0, 1.0]);
+        assert_eq!(names(&selection.select(registry().tool_infos(), "Will it rain?").await), ["weather", "calc"]);
+        assert_eq!(server.requests().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn offers_everything_when_few_tools_or_embedding_fails() {
+        let server = FakeOllama::start().await.unwrap();
+        let selection = ToolSelection::new(server.embed_config(ModelConfig::new("nomic-embed-text")), 4);
+        assert_eq!(names(&selection.select(registry().tool_infos(), "anything").await).len(), 4);
+        assert!(server.requests().is_empty());
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let unreachable = ToolSelection::new(("http://127.0.0.1".into(), port, ModelConfig::new("nomic-embed-text")), 1)
+            .retry(RetryPolicy::new(1));
+        assert_eq!(names(&unreachable.select(registry().tool_infos(), "anything").await), ["weather", "stock", "shout", "calc"]);
+    }
+}
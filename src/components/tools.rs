@@ -1,13 +1,211 @@
-/// Module for defining and managing async tool components in the system.
-/// Contains the Tool struct implementation and integration with ollama_rs's ToolHolder trait.
-use std::{pin::Pin, sync::Arc};
+/// Module for defining async tools the model can call.
+///
+/// A [`Tool`] wraps an async function; [`Tool::run`] puts each call before the
+/// tool's approval hook and runs it, returning a [`ToolOutput`] on success or
+/// a [`ToolError`] describing why the call was rejected, failed, was denied
+/// or timed out. A call that outlives [`Tool::timeout`] (or, when that is
+/// unset, the registry's [`tool_timeout`](crate::ComponentRegistry::tool_timeout))
+/// is dropped and fails with [`ToolError::Timeout`]; the agent loop then
+/// hands the model [`ToolError::model_message`] so the chat can continue.
+use std::{fmt, pin::Pin, sync::{Arc, Mutex}, time::{Duration, Instant}};
 use futures::Future;
-use ollama_rs::generation::tools::ToolHolder;
+use ollama_rs::generation::{chat::{ChatMessage, MessageRole}, tools::{ToolCall, ToolCallFunction, ToolHolder}};
+
+use crate::components::approval::{RiskLevel, ToolApproval, ToolApprovalRecord, ToolCallRequest, ToolDecision};
+use crate::components::sampling::SamplingHandle;
+
+/// Async implementation of a [`Tool`], taking the call's parameter.
+pub type ToolFn =
+    Arc<dyn for<'a> Fn(&'a String) -> Pin<Box<dyn Future<Output = Result<ToolOutput, ToolError>> + Send + Sync + 'a>> + Send + Sync>;
+
+/// Successful result of a tool call.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ToolOutput {
+    /// Text handed back to the model.
+    pub text: String,
+}
+
+impl ToolOutput {
+    /// Creates an output carrying `text`.
+    pub fn text(text: impl Into<String>) -> Self {
+        ToolOutput { text: text.into() }
+    }
+
+    /// Creates an output carrying `value` serialised as JSON.
+    ///
+    /// # Errors
+    /// Returns [`ToolError::Failed`] if `value` cannot be serialised.
+    pub fn json<T: serde::Serialize>(value: &T) -> Result<Self, ToolError> {
+        serde_json::to_string(value)
+            .map(ToolOutput::text)
+            .map_err(|e| ToolError::Failed(format!("cannot serialise result: {e}")))
+    }
+}
+
+impl From<String> for ToolOutput {
+    fn from(text: String) -> Self {
+        ToolOutput { text }
+    }
+}
+
+impl From<&str> for ToolOutput {
+    fn from(text: &str) -> Self {
+        ToolOutput::text(text)
+    }
+}
+
+/// Why a tool call failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolError {
+    /// The arguments were missing or malformed.
+    InvalidArguments(String),
+    /// The tool ran and failed.
+    Failed(String),
+    /// The tool did not finish within its timeout.
+    Timeout(Duration),
+    /// The approval hook denied the call; holds its reason.
+    Denied(String),
+}
+
+impl ToolError {
+    /// Creates a [`ToolError::Failed`].
+    pub fn failed(message: impl Into<String>) -> Self {
+        ToolError::Failed(message.into())
+    }
+
+    /// Creates a [`ToolError::InvalidArguments`].
+    pub fn invalid_arguments(message: impl Into<String>) -> Self {
+        ToolError::InvalidArguments(message.into())
+    }
+
+    /// The message fed back to the model in place of the result of tool
+    /// `tool`, phrased so the model can correct its call or carry on.
+    pub fn model_message(&self, tool: &str) -> String {
+        match self {
+            ToolError::InvalidArguments(e) => format!(
+                "Error: tool '{tool}' rejected its arguments: {e}. Check them against the tool's parameters and call it again."
+            ),
+            ToolError::Failed(e) => format!("Error: tool '{tool}' failed: {e}. Retry if it looks transient, otherwise continue without it."),
+            ToolError::Timeout(limit) => format!(
+                "Error: tool '{tool}' timed out after {:.1}s. Retry if it looks transient, otherwise continue without it.",
+                limit.as_secs_f64()
+            ),
+            ToolError::Denied(reason) => format!(
+                "Error: tool '{tool}' was not run because the call was denied: {reason}. Do not repeat the call; continue without it."
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolError::InvalidArguments(e) => write!(f, "invalid arguments: {e}"),
+            ToolError::Failed(e) => write!(f, "tool failed: {e}"),
+            ToolError::Timeout(limit) => write!(f, "tool timed out after {limit:?}"),
+            ToolError::Denied(reason) => write!(f, "tool call denied: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ToolError {}
+
+impl From<String> for ToolError {
+    fn from(message: String) -> Self {
+        ToolError::Failed(message)
+    }
+}
+
+impl From<&str> for ToolError {
+    fn from(message: &str) -> Self {
+        ToolError::Failed(message.to_string())
+    }
+}
+
+/// A tool call made while answering a query, as stored in history.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolCallRecord {
+    /// Name of the tool.
+    pub name: String,
+    /// Arguments the tool ran with, after any change by an approval hook.
+    pub arguments: serde_json::Value,
+    /// Text handed back to the model: the tool's output, or the error
+    /// message when the call failed.
+    pub result: String,
+    /// How long the tool ran.
+    pub duration: Duration,
+    /// Why the call failed, if it did.
+    pub error: Option<String>,
+}
+
+impl ToolCallRecord {
+    /// Replays `calls` as Ollama chat turns: an assistant turn requesting
+    /// them, followed by one [`MessageRole::Tool`] turn per result.
+    pub(crate) fn to_ollama(calls: &[ToolCallRecord]) -> Vec<ChatMessage> {
+        if calls.is_empty() {
+            return Vec::new();
+        }
+        let mut request = ChatMessage::new(MessageRole::Assistant, String::new());
+        request.tool_calls = calls
+            .iter()
+            .map(|call| ToolCall {
+                function: ToolCallFunction {
+                    name: call.name.clone(),
+                    arguments: call.arguments.clone(),
+                },
+            })
+            .collect();
+        let mut messages = vec![request];
+        messages.extend(calls.iter().map(|call| ChatMessage::new(MessageRole::Tool, call.result.clone())));
+        messages
+    }
+}
+
+/// The tool calls made and the approval decisions taken while running a
+/// tool call of the model.
+#[derive(Debug, Default)]
+pub(crate) struct TurnRecords {
+    pub(crate) calls: Vec<ToolCallRecord>,
+    pub(crate) approvals: Vec<ToolApprovalRecord>,
+}
+
+tokio::task_local! {
+    static RECORDS: Mutex<TurnRecords>;
+}
+
+/// Applies `f` to the records of the running turn; outside of one (e.g.
+/// when served over MCP) nothing happens.
+fn with_records(f: impl FnOnce(&mut TurnRecords)) {
+    let _ = RECORDS.try_with(|records| f(&mut records.lock().unwrap_or_else(|e| e.into_inner())));
+}
+
+/// Adds `record` to the tool calls of the running turn.
+pub(crate) fn record_call(record: ToolCallRecord) {
+    with_records(|records| records.calls.push(record));
+}
+
+/// Logs `record` and adds it to the approval decisions of the running turn.
+pub(crate) fn record_approval(record: ToolApprovalRecord) {
+    log::info!("Tool call '{}' ({:?} risk): {:?}", record.tool, record.risk, record.decision);
+    with_records(|records| records.approvals.push(record));
+}
+
+/// Runs `turn` and returns its output with the records it left.
+pub(crate) async fn collect_records<F: Future>(turn: F) -> (F::Output, TurnRecords) {
+    RECORDS
+        .scope(Mutex::new(TurnRecords::default()), async {
+            let output = turn.await;
+            let records = RECORDS.with(|records| std::mem::take(&mut *records.lock().unwrap_or_else(|e| e.into_inner())));
+            (output, records)
+        })
+        .await
+}
 
 /// Represents an async tool with a name, description, and implementation.
 ///
 /// This struct encapsulates a tool that can be invoked by the system.
-/// The function field is an asynchronous operation that returns a String result.
+/// The function field is an asynchronous operation that returns a
+/// [`ToolOutput`] or a [`ToolError`].
 #[derive(Clone)]
 pub struct Tool  {
     /// Unique identifier for the tool (e.g., "search", "calculate")
@@ -16,11 +214,30 @@ pub struct Tool  {
     /// Human-readable description of the tool's purpose and behavior
     pub description: String,
 
+    /// JSON Schema of the tool's input, if it takes structured arguments.
+    ///
+    /// Tools without a schema are offered to the model with a single string
+    /// parameter; tools with one receive the model's JSON arguments verbatim
+    /// (serialised) as their parameter.
+    pub schema: Option<serde_json::Value>,
+
+    /// Longest a single call may take before it fails with
+    /// [`ToolError::Timeout`]; when unset, calls through a registry get its
+    /// [`tool_timeout`](crate::ComponentRegistry::tool_timeout).
+    pub timeout: Option<Duration>,
+
+    /// How much harm the tool can do; see [`Tool::approve`].
+    pub risk: RiskLevel,
+
+    /// Optional hook that approves, modifies or denies each call before the
+    /// tool runs.
+    pub approve: Option<ToolApproval>,
+
     /// Asynchronous implementation of the tool
     ///
-    /// Takes a String parameter and returns a boxed future that resolves to a String result.
+    /// Takes a String parameter and returns a boxed future that resolves to the tool's result.
     /// The function is wrapped in an Arc for shared ownership and thread safety.
-    pub func: Arc<dyn for<'a> Fn(&'a String) -> Pin<Box<dyn Future<Output = String> + Send + Sync + 'a>> + Send + Sync>
+    pub func: ToolFn
 }
 
 impl Tool {
@@ -42,50 +259,240 @@ impl Tool {
     where
         F: for<'a> Fn(&'a String) -> Fut + Send + Sync + 'static,
         Fut: Future<Output = String> + Send + Sync + 'static,
+    {
+        Tool::fallible(name, description, move |param: &String| {
+            let fut = func(param);
+            async move { Ok::<_, ToolError>(fut.await) }
+        })
+    }
+
+    /// Creates a Tool whose implementation can fail
+    ///
+    /// # Parameters
+    /// - `name`: The unique identifier for this tool
+    /// - `description`: Documentation describing the tool's purpose
+    /// - `func`: The implementation function that takes a String parameter
+    ///   and returns a Future resolving to a [`ToolOutput`] (or anything
+    ///   convertible into one) or a [`ToolError`]
+    ///
+    /// # Returns
+    /// A new Tool instance with the provided configuration
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// let weather = Tool::fallible("weather", "Current weather for a city", |city: &String| {
+    ///     let city = city.clone();
+    ///     async move {
+    ///         if city.is_empty() {
+    ///             return Err(ToolError::invalid_arguments("city is empty"));
+    ///         }
+    ///         let report = fetch_weather(&city).await.map_err(|e| ToolError::failed(e.to_string()))?;
+    ///         Ok(report)
+    ///     }
+    /// })
+    /// .timeout(Duration::from_secs(10));
+    /// ```
+    pub fn fallible<F, Fut, O>(name: &str, description: &str, func: F) -> Self
+    where
+        F: for<'a> Fn(&'a String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<O, ToolError>> + Send + Sync + 'static,
+        O: Into<ToolOutput>,
     {
         Tool {
             name: name.to_string(),
             description: description.to_string(),
+            schema: None,
+            timeout: None,
+            risk: RiskLevel::default(),
+            approve: None,
             func: Arc::new(move |param: &String| {
-                Box::pin(func(param))
+                let fut = func(param);
+                Box::pin(async move { fut.await.map(Into::into) })
             }),
         }
     }
 
+    /// Creates a Tool that takes structured arguments described by `schema`
+    ///
+    /// # Parameters
+    /// - `name`: The unique identifier for this tool
+    /// - `description`: Documentation describing the tool's purpose
+    /// - `schema`: JSON Schema (an object schema) of the tool's arguments
+    /// - `func`: The implementation function; it receives the arguments as a
+    ///   JSON string
+    ///
+    /// # Returns
+    /// A new Tool instance with the provided configuration
+    pub fn with_schema<F, Fut>(name: &str, description: &str, schema: serde_json::Value, func: F) -> Self
+    where
+        F: for<'a> Fn(&'a String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = String> + Send + Sync + 'static,
+    {
+        Tool::new(name, description, func).schema(schema)
+    }
+
+    /// Creates a Tool that can request sub-completions from the LLM
+    ///
+    /// # Parameters
+    /// - `name`: The unique identifier for this tool
+    /// - `description`: Documentation describing the tool's purpose
+    /// - `func`: The implementation function; it receives the parameter and
+    ///   a [`SamplingHandle`] of the query that called the tool
+    ///
+    /// # Returns
+    /// A new Tool instance with the provided configuration
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// let summarize = Tool::with_sampling("summarize", "Summarizes a web page", |url: &String, sampling| {
+    ///     let url = url.clone();
+    ///     async move {
+    ///         let page = fetch(&url).await;
+    ///         match sampling.sample(SamplingRequest::new(format!("Summarize:\n{page}")).max_tokens(200)).await {
+    ///             Ok(summary) => summary.text,
+    ///             Err(e) => format!("No summary: {e}"),
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn with_sampling<F, Fut>(name: &str, description: &str, func: F) -> Self
+    where
+        F: for<'a> Fn(&'a String, SamplingHandle) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = String> + Send + Sync + 'static,
+    {
+        Tool::new(name, description, move |param: &String| func(param, SamplingHandle::current()))
+    }
+
+    /// Sets the JSON Schema of the tool's arguments (see [`Tool::schema`]).
+    pub fn schema(mut self, schema: serde_json::Value) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Limits each call to `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Tags the tool with a risk level.
+    pub fn risk(mut self, risk: RiskLevel) -> Self {
+        self.risk = risk;
+        self
+    }
+
+    /// Sets the approval hook, invoked with each call before the tool runs.
+    ///
+    /// A denial is returned to the model as [`ToolError::Denied`]; every
+    /// decision is recorded in the query's history (see
+    /// [`ToolApprovalRecord`]). See also [`ComponentRegistry::approve_tools`](crate::ComponentRegistry::approve_tools).
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// let drop_table = Tool::new("drop_table", "Drops a table", drop_table)
+    ///     .risk(RiskLevel::High)
+    ///     .approve(|call: ToolCallRequest| async move {
+    ///         match ask_operator(&call).await {
+    ///             Some(true) => ToolDecision::Approve,
+    ///             Some(false) => ToolDecision::deny("the operator refused"),
+    ///             None => ToolDecision::deny("no operator answered"),
+    ///         }
+    ///     });
+    /// ```
+    pub fn approve<F, Fut>(mut self, approve: F) -> Self
+    where
+        F: Fn(ToolCallRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ToolDecision> + Send + Sync + 'static,
+    {
+        self.approve = Some(Arc::new(move |request| Box::pin(approve(request))));
+        self
+    }
+
+    /// Puts a call with `parameters` before the approval hook, if any.
+    ///
+    /// # Returns
+    /// The arguments to run the tool with
+    ///
+    /// # Errors
+    /// Returns [`ToolError::Denied`] if the hook denies the call.
+    async fn review(&self, parameters: &serde_json::Value) -> Result<serde_json::Value, ToolError> {
+        let Some(approve) = &self.approve else {
+            return Ok(parameters.clone());
+        };
+        let request = ToolCallRequest {
+            tool: self.name.clone(),
+            risk: self.risk,
+            arguments: parameters.clone(),
+        };
+        let decision = approve(request.clone()).await;
+        let result = match &decision {
+            ToolDecision::Approve => Ok(request.arguments.clone()),
+            ToolDecision::Deny(reason) => Err(ToolError::Denied(reason.clone())),
+            ToolDecision::Modify(arguments) => Ok(arguments.clone()),
+        };
+        record_approval(ToolApprovalRecord::new(request, decision));
+        result
+    }
+
     /// Executes the tool with the provided parameter
     ///
     /// # Parameters
     /// - `param`: The input parameter to pass to the tool's function
     ///
     /// # Returns
-    /// Some(String) if execution succeeds, None if it fails
-    pub async fn execute(&self, param: &String) -> Option<String> {
-        let fut = (self.func)(param).await;
-        Some(fut)
+    /// The tool's output, or the error it failed with
+    ///
+    /// # Errors
+    /// Returns the tool's own [`ToolError`], or [`ToolError::Timeout`] if
+    /// [`Tool::timeout`] elapses first.
+    pub async fn execute(&self, param: &String) -> Result<ToolOutput, ToolError> {
+        match self.timeout {
+            Some(limit) => tokio::time::timeout(limit, (self.func)(param))
+                .await
+                .unwrap_or(Err(ToolError::Timeout(limit))),
+            None => (self.func)(param).await,
+        }
     }
-}
 
-impl ToolHolder for Tool {
-    /// Invokes the tool with provided JSON parameters
+    /// Runs the tool on the model's JSON arguments, recording its span and
+    /// metrics, after putting the call before [`Tool::approve`].
     ///
     /// # Parameters
     /// - `parameters`: A JSON value containing the tool's input parameters
     ///
-    /// # Returns
-    /// A future that resolves to either:
-    /// - Ok(String): The tool's successful result
-    /// - Err(Box<dyn Error>): If serialization or execution fails
-    fn call(
-        &mut self,
-        parameters: serde_json::Value,
-    ) -> Pin<Box<dyn Future<Output = Result<String, Box<dyn std::error::Error + Send + Sync>>> + '_ + Send + Sync>> {
-        Box::pin(async move {
+    /// # Errors
+    /// Returns [`ToolError::Denied`] if the approval hook denies the call,
+    /// otherwise see [`Tool::execute`].
+    pub async fn run(&self, parameters: serde_json::Value) -> Result<ToolOutput, ToolError> {
+        use tracing::Instrument;
+
+        let span = tracing::info_span!(
+            "tool.call",
+            tool = %self.name,
+            arguments = tracing::field::Empty,
+            result = tracing::field::Empty,
+        );
+        crate::telemetry::record_content(&span, "arguments", &parameters.to_string());
+        let record = span.clone();
+        async move {
+            let parameters = match self.review(&parameters).await {
+                Ok(parameters) => parameters,
+                Err(e) => {
+                    crate::telemetry::record_content(&record, "result", &e.to_string());
+                    let result = Err(e);
+                    self.record_call(parameters, Duration::ZERO, &result);
+                    return result;
+                }
+            };
+
             // Extract the first string value from the JSON object, or fall back to
             // serialising the whole value. This handles models that wrap the single
             // string parameter in an object with an arbitrary key name, e.g.
             // {"query": "…"}, {"whereclause": "…"}, {"param": "…"}, etc.
-            log::debug!("Tool '{}' called with parameters: {}", self.name, parameters);
+            log::debug!("Tool '{}' called with parameters: {}", self.name, crate::telemetry::log_content(&parameters.to_string()));
             let param_str = match &parameters {
+                // Tools with a schema take the arguments as they are.
+                _ if self.schema.is_some() => parameters.to_string(),
                 serde_json::Value::String(s) => s.clone(),
                 serde_json::Value::Object(map) => {
                     // Pick the first string value; fall back to serialising the object.
@@ -97,12 +504,59 @@ impl ToolHolder for Tool {
             };
 
             // Execute the tool with the extracted parameter
+            let start = Instant::now();
             let result = self.execute(&param_str).await;
+            self.record_call(parameters, start.elapsed(), &result);
 
-            // Return the result or a failure error
-            match result {
-                Some(res) => Ok(res),
-                None => Err("Tool execution failed".into()),
+            match &result {
+                Ok(output) => {
+                    crate::telemetry::record_content(&record, "result", &output.text);
+                    crate::Metrics::global().record_tool_call(&self.name, true);
+                }
+                Err(e) => {
+                    log::warn!("Tool '{}' failed: {e}", self.name);
+                    crate::telemetry::record_content(&record, "result", &e.to_string());
+                    crate::Metrics::global().record_tool_call(&self.name, false);
+                }
+            }
+            result
+        }.instrument(span).await
+    }
+
+    /// Adds a call with `arguments` and its `result` to the running turn's
+    /// history (see [`ToolCallRecord`]).
+    fn record_call(&self, arguments: serde_json::Value, duration: Duration, result: &Result<ToolOutput, ToolError>) {
+        record_call(ToolCallRecord {
+            name: self.name.clone(),
+            arguments,
+            result: match result {
+                Ok(output) => output.text.clone(),
+                Err(e) => e.model_message(&self.name),
+            },
+            duration,
+            error: result.as_ref().err().map(ToString::to_string),
+        });
+    }
+}
+
+impl ToolHolder for Tool {
+    /// Invokes the tool with provided JSON parameters
+    ///
+    /// # Parameters
+    /// - `parameters`: A JSON value containing the tool's input parameters
+    ///
+    /// # Returns
+    /// A future that resolves to the tool's result. Failures and timeouts
+    /// also resolve to `Ok`, carrying [`ToolError::model_message`], so the
+    /// model sees what went wrong and the chat continues.
+    fn call(
+        &mut self,
+        parameters: serde_json::Value,
+    ) -> Pin<Box<dyn Future<Output = Result<String, Box<dyn std::error::Error + Send + Sync>>> + '_ + Send + Sync>> {
+        Box::pin(async move {
+            match self.run(parameters).await {
+                Ok(output) => Ok(output.text),
+                Err(e) => Ok(e.model_message(&self.name)),
             }
         })
     }
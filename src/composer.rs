@@ -33,6 +33,7 @@ pub struct ComposedPrompt {
 ///
 /// # Example
 /// ```rust
+/// # use erh_llm::PromptComposer;
 /// let composed = PromptComposer::new()
 ///     .constraint("You are a helpful assistant. Answer only with information found in the context.")
 ///     .context("The document states that the deadline is 2026-06-01.")
@@ -51,6 +52,10 @@ pub struct PromptComposer {
     constraint: Option<String>,
     /// Optional tone / style instruction (e.g. `"formal"`, `"concise"`).
     style: Option<String>,
+    /// Optional description of the required output format (e.g. a JSON schema).
+    output_format: Option<String>,
+    /// Attached resources as `(uri, mime_type, text)`.
+    resources: Vec<(String, String, String)>,
 }
 
 impl PromptComposer {
@@ -90,6 +95,23 @@ impl PromptComposer {
         self
     }
 
+    /// Sets the required output format, e.g. a JSON schema the answer must
+    /// conform to. Rendered as its own section after the style.
+    pub fn output_format(mut self, f: impl Into<String>) -> Self {
+        let fmt = f.into();
+        if !fmt.is_empty() {
+            self.output_format = Some(fmt);
+        }
+        self
+    }
+
+    /// Attaches the content of a resource. Attached resources are rendered
+    /// in their own section after the context, headed by URI and MIME type.
+    pub fn resource(mut self, uri: impl Into<String>, mime_type: impl Into<String>, text: impl Into<String>) -> Self {
+        self.resources.push((uri.into(), mime_type.into(), text.into()));
+        self
+    }
+
     /// Builds a [`ComposedPrompt`] from the configured parts and the given user query.
     ///
     /// The system message is structured as:
@@ -100,12 +122,29 @@ impl PromptComposer {
     /// ### Context           (omitted when empty)
     /// <rag context>
     ///
+    /// ### Resources         (omitted when none are attached)
+    /// #### <uri> (<mime type>)
+    /// <content>
+    ///
     /// ### Style             (omitted when empty)
     /// <style>
+    ///
+    /// ### Output format     (omitted when empty)
+    /// <output format>
     /// ```
     ///
     /// The user message is simply the raw `query` string.
     pub fn build(self, query: impl Into<String>) -> ComposedPrompt {
+        let span = tracing::debug_span!(
+            "prompt.build",
+            has_context = self.context.is_some(),
+            resources = self.resources.len(),
+            has_style = self.style.is_some(),
+            has_output_format = self.output_format.is_some(),
+            system_len = tracing::field::Empty,
+            system = tracing::field::Empty,
+        );
+        let _enter = span.enter();
         let mut system = String::new();
 
         // Constraint doubles as the role instruction.
@@ -124,6 +163,14 @@ impl PromptComposer {
             system.push('\n');
         }
 
+        // Attached resources, after the free-form context they complement.
+        if !self.resources.is_empty() {
+            system.push_str("\n### Resources\n");
+            for (uri, mime_type, text) in &self.resources {
+                system.push_str(&format!("#### {uri} ({mime_type})\n{text}\n"));
+            }
+        }
+
         // Style
         if let Some(style) = self.style {
             system.push_str("\n### Style\n");
@@ -131,6 +178,16 @@ impl PromptComposer {
             system.push('\n');
         }
 
+        // Output format
+        if let Some(fmt) = self.output_format {
+            system.push_str("\n### Output format\n");
+            system.push_str(&fmt);
+            system.push('\n');
+        }
+
+        span.record("system_len", system.len());
+        crate::telemetry::record_content(&span, "system", &system);
+
         ComposedPrompt {
             system,
             user: query.into(),
@@ -0,0 +1,245 @@
+//! Error types raised by `erh_llm` itself and classification of backend errors.
+//!
+//! Backend calls return `Box<dyn std::error::Error>` carrying whatever the
+//! underlying client produced (`OllamaError`, `reqwest::Error`,
+//! `std::io::Error`, …). [`classify`] maps those onto a small set of
+//! [`ErrorKind`]s so that retry and fallback decisions can be made without
+//! knowing the concrete type.
+
+use std::{fmt, sync::OnceLock, time::Duration};
+
+use ollama_rs::error::OllamaError;
+use regex::Regex;
+
+/// Coarse category of a backend failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The request (or a single attempt) took too long.
+    Timeout,
+    /// The backend could not be reached (refused, reset, DNS, …).
+    Connection,
+    /// The backend answered with a 5xx / overloaded error.
+    Server,
+    /// The backend rejected the request with HTTP 429.
+    RateLimited,
+    /// The request itself was invalid (4xx other than 429).
+    Client,
+    /// The caller cancelled the request.
+    Cancelled,
+    /// Anything that could not be classified.
+    Other,
+}
+
+impl ErrorKind {
+    /// Returns `true` for transient failures that are worth retrying.
+    pub fn is_transient(self) -> bool {
+        matches!(self, ErrorKind::Timeout | ErrorKind::Connection | ErrorKind::Server | ErrorKind::RateLimited)
+    }
+
+    /// Stable lowercase name, suitable for logs and metric labels.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Connection => "connection",
+            ErrorKind::Server => "server",
+            ErrorKind::RateLimited => "rate_limited",
+            ErrorKind::Client => "client",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Errors produced by `erh_llm` (as opposed to the backend clients).
+#[derive(Debug, Clone, PartialEq)]
+pub enum LlmError {
+    /// A single attempt exceeded its time limit.
+    Timeout(Duration),
+    /// The overall deadline for a call (including retries) was exceeded.
+    DeadlineExceeded(Duration),
+    /// The query was cancelled through its cancellation token.
+    Cancelled,
+    /// The backend answered with an HTTP error status.
+    Http {
+        /// The HTTP status code.
+        status: u16,
+        /// The body of the error response.
+        message: String,
+    },
+    /// Tools were requested for a model that lacks tool capability.
+    ToolsUnsupported(String),
+}
+
+impl LlmError {
+    /// Returns the [`ErrorKind`] this error belongs to.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            LlmError::Timeout(_) | LlmError::DeadlineExceeded(_) => ErrorKind::Timeout,
+            LlmError::Cancelled => ErrorKind::Cancelled,
+            LlmError::Http { status, .. } => classify_status(*status),
+            LlmError::ToolsUnsupported(_) => ErrorKind::Client,
+        }
+    }
+}
+
+impl fmt::Display for LlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LlmError::Timeout(d) => write!(f, "Backend call timed out after {d:?}"),
+            LlmError::DeadlineExceeded(d) => write!(f, "Backend call exceeded its deadline of {d:?}"),
+            LlmError::Cancelled => write!(f, "Query was cancelled"),
+            LlmError::Http { status, message } => write!(f, "Backend answered HTTP {status}: {message}"),
+            LlmError::ToolsUnsupported(model) => write!(f, "Model {model} does not support tools; refusing to attach them"),
+        }
+    }
+}
+
+impl std::error::Error for LlmError {}
+
+/// Classifies an error returned by a backend call.
+///
+/// Known error types are inspected directly; the `source()` chain is walked
+/// for wrapped errors, and as a last resort the message text is matched
+/// against common transport/HTTP failure phrases.
+pub fn classify(err: &(dyn std::error::Error + 'static)) -> ErrorKind {
+    let mut current: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(e) = current {
+        if let Some(kind) = classify_known(e) {
+            return kind;
+        }
+        current = e.source();
+    }
+    classify_message(&err.to_string())
+}
+
+/// Classifies the error types we know about; `None` for everything else.
+fn classify_known(err: &(dyn std::error::Error + 'static)) -> Option<ErrorKind> {
+    if let Some(e) = err.downcast_ref::<LlmError>() {
+        return Some(e.kind());
+    }
+    if let Some(e) = err.downcast_ref::<crate::MockError>() {
+        return Some(e.kind);
+    }
+    if let Some(e) = err.downcast_ref::<std::io::Error>() {
+        use std::io::ErrorKind as Io;
+        return Some(match e.kind() {
+            Io::TimedOut => ErrorKind::Timeout,
+            Io::ConnectionRefused | Io::ConnectionReset | Io::ConnectionAborted | Io::NotConnected | Io::BrokenPipe => ErrorKind::Connection,
+            _ => classify_message(&e.to_string()),
+        });
+    }
+    #[cfg(any(feature="tools", feature="admin"))]
+    if let Some(e) = err.downcast_ref::<reqwest::Error>() {
+        return Some(if e.is_timeout() {
+            ErrorKind::Timeout
+        } else if e.is_connect() {
+            ErrorKind::Connection
+        } else if let Some(status) = e.status() {
+            classify_status(status.as_u16())
+        } else {
+            classify_message(&e.to_string())
+        });
+    }
+    if let Some(e) = err.downcast_ref::<OllamaError>() {
+        return Some(match e {
+            OllamaError::ReqwestError(re) => {
+                if re.is_timeout() {
+                    ErrorKind::Timeout
+                } else if re.is_connect() {
+                    ErrorKind::Connection
+                } else if let Some(status) = re.status() {
+                    classify_status(status.as_u16())
+                } else {
+                    classify_message(&re.to_string())
+                }
+            }
+            OllamaError::InternalError(ie) => classify_message(&ie.message),
+            OllamaError::Other(msg) => classify_message(msg),
+            _ => ErrorKind::Other,
+        });
+    }
+    None
+}
+
+/// Maps an HTTP status code onto an [`ErrorKind`].
+fn classify_status(status: u16) -> ErrorKind {
+    match status {
+        408 => ErrorKind::Timeout,
+        429 => ErrorKind::RateLimited,
+        400..=499 => ErrorKind::Client,
+        500..=599 => ErrorKind::Server,
+        _ => ErrorKind::Other,
+    }
+}
+
+/// Heuristic classification of an error message.
+///
+/// Needed because some clients flatten HTTP failures into strings (e.g.
+/// `OllamaError::Other(body)`). Numbers only count as status codes where the
+/// text presents them as one (`status 503`, `HTTP/1.1 429`, a leading
+/// `502 Bad Gateway`), and phrases must stand as whole words, so model names
+/// like `foo:500m` or option names like `read_timeout` are not mistaken for
+/// failures.
+fn classify_message(msg: &str) -> ErrorKind {
+    static PATTERNS: OnceLock<[Regex; 5]> = OnceLock::new();
+    let [status, timeout, connection, rate_limited, server] = PATTERNS.get_or_init(|| [
+        Regex::new(r"\b(?:status(?: code)?|http(?:/\d(?:\.\d)?)?)\s*[:=]?\s*(\d{3})\b|^\s*(\d{3})\b").unwrap(),
+        Regex::new(r"\btimed out\b|\b(?:request|operation|connection|read|deadline) timeout\b|^timeout\b").unwrap(),
+        Regex::new(r"\bconnection (?:refused|reset|closed)\b|\berror sending request\b|\bdns error\b").unwrap(),
+        Regex::new(r"\brate.limit(?:ed)?\b|\btoo many requests\b").unwrap(),
+        Regex::new(r"\b(?:internal server error|bad gateway|service unavailable|gateway timeout|overloaded)\b").unwrap(),
+    ]);
+    let m = msg.to_lowercase();
+    if let Some(code) = status.captures(&m)
+        .and_then(|c| c.get(1).or_else(|| c.get(2)))
+        .and_then(|c| c.as_str().parse().ok())
+    {
+        return classify_status(code);
+    }
+    if server.is_match(&m) {
+        ErrorKind::Server
+    } else if rate_limited.is_match(&m) {
+        ErrorKind::RateLimited
+    } else if timeout.is_match(&m) {
+        ErrorKind::Timeout
+    } else if connection.is_match(&m) {
+        ErrorKind::Connection
+    } else {
+        ErrorKind::Other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_status_codes_need_http_context() {
+        assert_eq!(classify_message("status code: 503"), ErrorKind::Server);
+        assert_eq!(classify_message("HTTP/1.1 429 Too Many Requests"), ErrorKind::RateLimited);
+        assert_eq!(classify_message("502 Bad Gateway"), ErrorKind::Server);
+        assert_eq!(classify_message("model 'foo:500m' not found"), ErrorKind::Other);
+        assert_eq!(classify_message("invalid value 404 for num_ctx"), ErrorKind::Other);
+    }
+
+    #[test]
+    fn message_phrases_match_whole_words() {
+        assert_eq!(classify_message("operation timed out"), ErrorKind::Timeout);
+        assert_eq!(classify_message("unknown option read_timeout"), ErrorKind::Other);
+        assert_eq!(classify_message("error sending request for url"), ErrorKind::Connection);
+        assert_eq!(classify_message("server overloaded, try later"), ErrorKind::Server);
+    }
+
+    #[test]
+    fn http_errors_classify_by_status() {
+        let err = LlmError::Http { status: 429, message: "slow down".into() };
+        assert_eq!(classify(&err), ErrorKind::RateLimited);
+        assert_eq!(LlmError::Http { status: 400, message: "status 503".into() }.kind(), ErrorKind::Client);
+    }
+}
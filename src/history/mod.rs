@@ -39,6 +39,72 @@ pub(crate) trait HistoryTrait {
     fn read(&self, chatuuid: &str) -> Result<Vec<ChatMessage>, Box<dyn std::error::Error>>;
 }
 
+/// Serialises the tool approval decisions of `msg` as JSON for the
+/// `tool_approvals` column; `None` when there are none.
+#[cfg(any(feature="sqlite_hist", feature="mysql_hist", feature="mssql_hist"))]
+pub(crate) fn encode_tool_approvals(_msg: &ChatMessage) -> Option<String> {
+#[cfg(feature="tools")]
+    if !_msg.tool_approvals.is_empty() {
+        return serde_json::to_string(&_msg.tool_approvals).ok();
+    }
+    None
+}
+
+/// Restores the tool approval decisions written by [`encode_tool_approvals`]
+/// onto `msg`. Unreadable values are logged and skipped.
+#[cfg(any(feature="sqlite_hist", feature="mysql_hist", feature="mssql_hist"))]
+pub(crate) fn decode_tool_approvals(_msg: &mut ChatMessage, _column: Option<String>) {
+#[cfg(feature="tools")]
+    if let Some(json) = _column {
+        match serde_json::from_str(&json) {
+            Ok(approvals) => _msg.tool_approvals = approvals,
+            Err(e) => log::warn!("Ignoring unreadable tool approvals in history: {e}"),
+        }
+    }
+}
+
+/// A `chat_tool_calls` row without its keys:
+/// `(name, arguments, result, duration_ms, error)`.
+#[cfg(any(feature="sqlite_hist", feature="mysql_hist", feature="mssql_hist"))]
+pub(crate) type ToolCallRow = (String, String, String, i64, Option<String>);
+
+/// The tool calls of `msg` as `chat_tool_calls` rows, in call order.
+#[cfg(any(feature="sqlite_hist", feature="mysql_hist", feature="mssql_hist"))]
+pub(crate) fn encode_tool_calls(_msg: &ChatMessage) -> Vec<ToolCallRow> {
+#[cfg(feature="tools")]
+    let rows = _msg.tool_calls.iter()
+        .map(|call| (
+            call.name.clone(),
+            call.arguments.to_string(),
+            call.result.clone(),
+            i64::try_from(call.duration.as_millis()).unwrap_or(i64::MAX),
+            call.error.clone(),
+        ))
+        .collect();
+#[cfg(not(feature="tools"))]
+    let rows = Vec::new();
+    rows
+}
+
+/// Attaches `(message_id, row)` pairs read from `chat_tool_calls` to the
+/// messages with those ids, keeping the order of `rows`.
+#[cfg(any(feature="sqlite_hist", feature="mysql_hist", feature="mssql_hist"))]
+pub(crate) fn decode_tool_calls(_messages: &mut [ChatMessage], _rows: Vec<(i64, ToolCallRow)>) {
+#[cfg(feature="tools")]
+    for (message_id, (name, arguments, result, duration_ms, error)) in _rows {
+        let Some(msg) = _messages.iter_mut().find(|m| m.id == Some(message_id)) else {
+            continue;
+        };
+        msg.tool_calls.push(crate::ToolCallRecord {
+            name,
+            arguments: serde_json::from_str(&arguments).unwrap_or(serde_json::Value::String(arguments)),
+            result,
+            duration: std::time::Duration::from_millis(u64::try_from(duration_ms).unwrap_or_default()),
+            error,
+        });
+    }
+}
+
 #[derive(Debug, Default,Clone, PartialEq, Eq)]
 #[allow(unused)]
 pub enum HistoryConfig {
@@ -114,42 +180,69 @@ impl History {
             }
         }
     }
-}
-
-impl HistoryTrait for History {
-    /// Delegates to the active backend's [`HistoryTrait::store`] implementation.
-    ///
-    /// Iterates through each conditionally-compiled backend in priority order
-    /// (SQLite → MySQL → MSSQL) and forwards the call to the first one that
-    /// is present.
-    ///
-    /// # Errors
-    /// Returns `"No history backend configured"` if no backend is enabled at
-    /// compile time, or propagates the backend-specific error otherwise.
-    fn store(&mut self, msg: &mut ChatMessage) -> Result<(), Box<dyn std::error::Error>> {
-        debug!("Storing message in history: {msg:?}");
 
+    /// Forwards `msg` to the first configured backend.
+    fn store_backend(&mut self, _msg: &mut ChatMessage) -> Result<(), Box<dyn std::error::Error>> {
 #[cfg(feature="sqlite_hist")]
         if let Some(x) = &mut self.sqlite {
             debug!("Using sqlite history");
-            return x.store(msg);
+            return x.store(_msg);
         }
 
 #[cfg(feature="mysql_hist")]
         if let Some(x) = &mut self.mysql {
             debug!("Using mysql history");
-            return x.store(msg);
+            return x.store(_msg);
         }
 
 #[cfg(feature="mssql_hist")]
         if let Some(x) = &mut self.mssql {
             debug!("Using mssql history");
-            return x.store(msg);
+            return x.store(_msg);
         }
 
         Err("No history backend configured".into())
     }
 
+    /// Name of the active backend, used as the `store` label on history spans
+    /// and metrics.
+    fn kind(&self) -> &'static str {
+#[cfg(feature="sqlite_hist")]
+        if self.sqlite.is_some() {
+            return "sqlite";
+        }
+#[cfg(feature="mysql_hist")]
+        if self.mysql.is_some() {
+            return "mysql";
+        }
+#[cfg(feature="mssql_hist")]
+        if self.mssql.is_some() {
+            return "mssql";
+        }
+        "none"
+    }
+}
+
+impl HistoryTrait for History {
+    /// Delegates to the active backend's [`HistoryTrait::store`] implementation.
+    ///
+    /// Iterates through each conditionally-compiled backend in priority order
+    /// (SQLite → MySQL → MSSQL) and forwards the call to the first one that
+    /// is present. The latency is recorded in [`crate::Metrics`].
+    ///
+    /// # Errors
+    /// Returns `"No history backend configured"` if no backend is enabled at
+    /// compile time, or propagates the backend-specific error otherwise.
+    fn store(&mut self, msg: &mut ChatMessage) -> Result<(), Box<dyn std::error::Error>> {
+        let _span = tracing::info_span!("history.store", chatuuid = %msg.chatuuid, store = self.kind()).entered();
+        debug!("Storing message in history for chat {}", msg.chatuuid);
+
+        let start = std::time::Instant::now();
+        let result = self.store_backend(msg);
+        crate::Metrics::global().record_history_store(self.kind(), start.elapsed(), result.is_ok());
+        result
+    }
+
     /// Delegates to the active backend's [`HistoryTrait::read`] implementation.
     ///
     /// Iterates through each conditionally-compiled backend in priority order
@@ -159,22 +252,23 @@ impl HistoryTrait for History {
     /// # Errors
     /// Propagates any error returned by the active backend.
     fn read(&self, _chatuuid: &str) -> Result<Vec<ChatMessage>, Box<dyn std::error::Error>> {
+        let _span = tracing::info_span!("history.read", chatuuid = %_chatuuid, store = self.kind()).entered();
 #[cfg(feature="sqlite_hist")]
         if let Some(x) = &self.sqlite {
             debug!("Reading sqlite history");
-            return Ok(x.read(chatuuid)?);
+            return x.read(_chatuuid);
         }
 
 #[cfg(feature="mysql_hist")]
         if let Some(x) = &self.mysql {
             debug!("Reading mysql history");
-            return Ok(x.read(_chatuuid)?);
+            return x.read(_chatuuid);
         }
 
 #[cfg(feature="mssql_hist")]
         if let Some(x) = &self.mssql {
             debug!("Reading mssql history");
-            return Ok(x.read(_chatuuid)?);
+            return x.read(_chatuuid);
         }
 
         debug!("No history backend configured, returning empty vector.");
@@ -3,8 +3,8 @@ use tokio::net::TcpStream;
 use log::debug;
 use futures::stream::TryStreamExt;
 
-use crate::ChatMessage;
-use crate::history::HistoryTrait;
+use crate::{ChatMessage, Usage};
+use crate::history::{decode_tool_approvals, decode_tool_calls, encode_tool_approvals, encode_tool_calls, HistoryTrait};
 
 #[derive(Debug)]
 pub struct MsSqlHistory {
@@ -108,7 +108,9 @@ impl MsSqlHistory {
     /// 1. Calls [`Self::get_config`] to obtain connection parameters.
     /// 2. Opens a TCP connection with a **10-second timeout**.
     /// 3. Performs the TLS/login handshake with another **10-second timeout**.
-    /// 4. Creates the `chat_history` table if it does not yet exist.
+    /// 4. Creates the `chat_history` and `chat_tool_calls` tables if they do
+    ///    not yet exist and adds columns missing from tables created by older
+    ///    versions.
     ///
     /// # Errors
     /// Returns an error on TCP connection failure, authentication failure,
@@ -169,13 +171,36 @@ impl MsSqlHistory {
                 chatuuid NVARCHAR(40) NOT NULL,
                 user_message NTEXT NOT NULL,
                 bot_response NTEXT NOT NULL,
-                timestamp DATETIME DEFAULT GETDATE()
-            )
+                timestamp DATETIME DEFAULT GETDATE(),
+                backend NVARCHAR(255) NULL,
+                prompt_tokens INT NULL,
+                completion_tokens INT NULL,
+                tool_approvals NVARCHAR(MAX) NULL
+            );
+            IF COL_LENGTH('chat_history', 'backend') IS NULL
+            ALTER TABLE chat_history ADD backend NVARCHAR(255) NULL;
+            IF COL_LENGTH('chat_history', 'prompt_tokens') IS NULL
+            ALTER TABLE chat_history ADD prompt_tokens INT NULL;
+            IF COL_LENGTH('chat_history', 'completion_tokens') IS NULL
+            ALTER TABLE chat_history ADD completion_tokens INT NULL;
+            IF COL_LENGTH('chat_history', 'tool_approvals') IS NULL
+            ALTER TABLE chat_history ADD tool_approvals NVARCHAR(MAX) NULL;
+            IF NOT EXISTS (SELECT * FROM sysobjects WHERE name='chat_tool_calls' AND xtype='U')
+            CREATE TABLE chat_tool_calls (
+                id BIGINT IDENTITY(1,1) PRIMARY KEY,
+                message_id BIGINT NOT NULL,
+                chatuuid NVARCHAR(40) NOT NULL,
+                name NVARCHAR(255) NOT NULL,
+                arguments NVARCHAR(MAX) NOT NULL,
+                result NVARCHAR(MAX) NOT NULL,
+                duration_ms BIGINT NOT NULL,
+                error NVARCHAR(MAX) NULL
+            );
         "#;
 
         let r = client.execute(create_table_sql, &[]).await;
         if let Err(e) = &r {
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create table: {}", e))));
+            return Err(Box::new(std::io::Error::other(format!("Failed to create table: {}", e))));
         }
         debug!("Database initialized successfully.");
         
@@ -206,13 +231,14 @@ impl MsSqlHistory {
             })?;
             rt.block_on(f)
         }).join().map_err(|_| -> Box<dyn std::error::Error + Send + Sync> { 
-            Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Thread panicked"))
+            Box::new(std::io::Error::other("Thread panicked"))
         })?
     }
 }
 
 impl HistoryTrait for MsSqlHistory {
-    /// Validates and inserts a [`ChatMessage`] into the `chat_history` table.
+    /// Validates and inserts a [`ChatMessage`] into the `chat_history` table
+    /// and its tool calls into `chat_tool_calls`, in one transaction.
     ///
     /// The message is sanitised via [`ChatMessage::noemoji`] before insertion.
     /// The INSERT runs on a dedicated thread via [`Self::execute_with_runtime`]
@@ -220,34 +246,58 @@ impl HistoryTrait for MsSqlHistory {
     ///
     /// # Errors
     /// Returns an error if validation fails, if a database connection cannot be
-    /// established, or if the INSERT statement fails.
+    /// established, or if an INSERT statement fails.
     fn store(&mut self, msg: &mut ChatMessage) -> std::result::Result<(), Box<dyn std::error::Error>> {
         if !msg.validate() {
             return Err(anyhow::anyhow!("Invalid chat message data").into());
         }
         let msg = msg.noemoji();
+        let tool_approvals = encode_tool_approvals(&msg);
+        let tool_calls = encode_tool_calls(&msg);
         let config_string = self.config_string.clone();
         
         self.execute_with_runtime(async move {
             let history = MsSqlHistory::new(config_string);
             let mut client = history.get_client().await?;
             
-            let insert_sql = "INSERT INTO chat_history (username, chatuuid, user_message, bot_response) VALUES (@P1, @P2, @P3, @P4)";
-            
-            client.execute(
-                insert_sql,
-                &[&msg.user, &msg.chatuuid, &msg.user_message, &msg.bot_response],
-            ).await?;
+            let insert_sql = "INSERT INTO chat_history (username, chatuuid, user_message, bot_response, backend, prompt_tokens, completion_tokens, tool_approvals) OUTPUT INSERTED.id VALUES (@P1, @P2, @P3, @P4, @P5, @P6, @P7, @P8)";
+            // tiberius has no unsigned integer parameters.
+            let prompt_tokens = msg.usage.map(|u| u.prompt_tokens as i32);
+            let completion_tokens = msg.usage.map(|u| u.completion_tokens as i32);
             
+            // The message and its tool calls are stored together or not at all.
+            client.simple_query("BEGIN TRAN").await?.into_results().await?;
+            let inserted: Result<(), tiberius::error::Error> = async {
+                let row = client.query(
+                    insert_sql,
+                    &[&msg.user, &msg.chatuuid, &msg.user_message, &msg.bot_response, &msg.backend, &prompt_tokens, &completion_tokens, &tool_approvals],
+                ).await?.into_row().await?;
+                let message_id: i64 = row.and_then(|row| row.get(0)).unwrap_or_default();
+
+                for (name, arguments, result, duration_ms, error) in &tool_calls {
+                    client.execute(
+                        "INSERT INTO chat_tool_calls (message_id, chatuuid, name, arguments, result, duration_ms, error) VALUES (@P1, @P2, @P3, @P4, @P5, @P6, @P7)",
+                        &[&message_id, &msg.chatuuid, name, arguments, result, duration_ms, error],
+                    ).await?;
+                }
+                Ok(())
+            }.await;
+            if let Err(e) = inserted {
+                client.simple_query("IF @@TRANCOUNT > 0 ROLLBACK TRAN").await?.into_results().await?;
+                return Err(e.into());
+            }
+            client.simple_query("COMMIT TRAN").await?.into_results().await?;
+
             Ok(())
         }).map_err(|e| -> Box<dyn std::error::Error> { 
-            Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("MSSQL store error: {}", e)))
+            Box::new(std::io::Error::other(format!("MSSQL store error: {}", e)))
         })
     }
 
     /// Retrieves all [`ChatMessage`]s for the given `chatuuid` from MSSQL.
     ///
-    /// Results are ordered by `timestamp ASC`.  The query runs on a dedicated
+    /// Results are ordered by `timestamp ASC`; the rows of `chat_tool_calls`
+    /// are then attached to their messages.  The query runs on a dedicated
     /// thread via [`Self::execute_with_runtime`] to safely bridge sync and
     /// async contexts.
     ///
@@ -262,7 +312,7 @@ impl HistoryTrait for MsSqlHistory {
             let history = MsSqlHistory::new(config_string);
             let mut client = history.get_client().await?;
             
-            let select_sql = "SELECT username, user_message, bot_response, chatuuid FROM chat_history WHERE chatuuid = @P1 ORDER BY timestamp ASC";
+            let select_sql = "SELECT username, user_message, bot_response, chatuuid, backend, prompt_tokens, completion_tokens, tool_approvals, id FROM chat_history WHERE chatuuid = @P1 ORDER BY timestamp ASC";
             
             let mut stream = client.query(select_sql, &[&chatuuid]).await?;
             let mut messages = Vec::new();
@@ -273,20 +323,44 @@ impl HistoryTrait for MsSqlHistory {
                     let user_message: Option<&str> = row.get(1);
                     let bot_response: Option<&str> = row.get(2);
                     let chat_uuid: Option<&str> = row.get(3);
+                    let backend: Option<&str> = row.get(4);
+                    let prompt_tokens: Option<i32> = row.get(5);
+                    let completion_tokens: Option<i32> = row.get(6);
+                    let tool_approvals: Option<&str> = row.get(7);
+                    let id: Option<i64> = row.get(8);
                     
-                    let message = ChatMessage::from_tuple((
+                    let mut message = ChatMessage::from_tuple((
                         username.unwrap_or("").to_string(),
                         user_message.unwrap_or("").to_string(),
                         bot_response.unwrap_or("").to_string(),
                         chat_uuid.unwrap_or("").to_string(),
                     ));
+                    message.id = id;
+                    message.backend = backend.map(str::to_string);
+                    message.usage = prompt_tokens.zip(completion_tokens)
+                        .map(|(p, c)| Usage::new(p.max(0) as u32, c.max(0) as u32));
+                    decode_tool_approvals(&mut message, tool_approvals.map(str::to_string));
                     messages.push(message);
                 }
             }
+            drop(stream);
+
+            let select_sql = "SELECT message_id, name, arguments, result, duration_ms, error FROM chat_tool_calls WHERE chatuuid = @P1 ORDER BY id";
+            let rows = client.query(select_sql, &[&chatuuid]).await?.into_first_result().await?;
+            let tool_calls = rows.iter()
+                .map(|row| {
+                    let text = |i: usize| row.get::<&str, _>(i).unwrap_or_default().to_string();
+                    let message_id: i64 = row.get(0).unwrap_or_default();
+                    let duration_ms: i64 = row.get(4).unwrap_or_default();
+                    let error: Option<&str> = row.get(5);
+                    (message_id, (text(1), text(2), text(3), duration_ms, error.map(str::to_string)))
+                })
+                .collect();
+            decode_tool_calls(&mut messages, tool_calls);
             
             Ok(messages)
         }).map_err(|e| -> Box<dyn std::error::Error> { 
-            Box::new(std::io::Error::new(std::io::ErrorKind::Other, format!("MSSQL read error: {}", e)))
+            Box::new(std::io::Error::other(format!("MSSQL read error: {}", e)))
         })
     }
 }
\ No newline at end of file
@@ -2,8 +2,12 @@ use mysql::*;
 use mysql::prelude::*;
 use log::debug;
 
-use crate::ChatMessage;
-use crate::history::HistoryTrait;
+use crate::{ChatMessage, Usage};
+use crate::history::{decode_tool_approvals, decode_tool_calls, encode_tool_approvals, encode_tool_calls, HistoryTrait, ToolCallRow};
+
+/// A `chat_history` row as selected by [`MysqlHistory::read`]:
+/// `(id, username, user_message, bot_response, chatuuid, backend, prompt_tokens, completion_tokens, tool_approvals)`.
+type HistoryRow = (i64, String, String, String, String, Option<String>, Option<u32>, Option<u32>, Option<String>);
 
 #[derive(Debug)]
 pub struct MysqlHistory {
@@ -23,11 +27,12 @@ impl MysqlHistory {
         MysqlHistory { pool }
     }
 
-    /// Acquires a pooled connection and ensures the `chat_history` table exists.
+    /// Acquires a pooled connection and ensures the `chat_history` and
+    /// `chat_tool_calls` tables exist.
     ///
     /// The `CREATE TABLE IF NOT EXISTS` statement is executed on every call so
     /// that the schema is always present without requiring a separate migration
-    /// step.
+    /// step. Columns added in later versions are added to existing tables.
     ///
     /// # Errors
     /// Returns a [`mysql::Error`] if a connection cannot be obtained from the
@@ -42,56 +47,145 @@ impl MysqlHistory {
                 chatuuid VARCHAR(40) NOT NULL,
                 user_message TEXT NOT NULL,
                 bot_response TEXT NOT NULL,
-                timestamp DATETIME DEFAULT CURRENT_TIMESTAMP
+                timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+                backend VARCHAR(255) NULL,
+                prompt_tokens INT UNSIGNED NULL,
+                completion_tokens INT UNSIGNED NULL,
+                tool_approvals TEXT NULL
+            )"#
+        )?;
+        conn.query_drop(
+            r#"CREATE TABLE IF NOT EXISTS chat_tool_calls (
+                id BIGINT PRIMARY KEY AUTO_INCREMENT,
+                message_id BIGINT NOT NULL,
+                chatuuid VARCHAR(40) NOT NULL,
+                name VARCHAR(255) NOT NULL,
+                arguments MEDIUMTEXT NOT NULL,
+                result MEDIUMTEXT NOT NULL,
+                duration_ms BIGINT NOT NULL,
+                error MEDIUMTEXT NULL
             )"#
         )?;
+        Self::ensure_column(&mut conn, "backend", "VARCHAR(255) NULL")?;
+        Self::ensure_column(&mut conn, "prompt_tokens", "INT UNSIGNED NULL")?;
+        Self::ensure_column(&mut conn, "completion_tokens", "INT UNSIGNED NULL")?;
+        Self::ensure_column(&mut conn, "tool_approvals", "TEXT NULL")?;
+        Self::widen_tool_call_column(&mut conn, "arguments", "MEDIUMTEXT NOT NULL")?;
+        Self::widen_tool_call_column(&mut conn, "result", "MEDIUMTEXT NOT NULL")?;
+        Self::widen_tool_call_column(&mut conn, "error", "MEDIUMTEXT NULL")?;
         debug!("Database initialized successfully.");
         Ok(conn)
     }
 
+    /// Adds `column` with the given SQL `definition` to `chat_history` if a
+    /// table created by an older version lacks it.
+    fn ensure_column(conn: &mut PooledConn, column: &str, definition: &str) -> Result<(), mysql::Error> {
+        let count: Option<u64> = conn.exec_first(
+            "SELECT COUNT(*) FROM information_schema.COLUMNS WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = 'chat_history' AND COLUMN_NAME = ?",
+            (column,),
+        )?;
+        if count.unwrap_or(0) == 0 {
+            debug!("Adding column {column} to chat_history");
+            conn.query_drop(format!("ALTER TABLE chat_history ADD COLUMN {column} {definition}"))?;
+        }
+        Ok(())
+    }
+
+    /// Changes `column` of `chat_tool_calls` to the SQL `definition` if a
+    /// table created by an older version still has it as `TEXT`, which holds
+    /// only 64 KiB.
+    fn widen_tool_call_column(conn: &mut PooledConn, column: &str, definition: &str) -> Result<(), mysql::Error> {
+        let data_type: Option<String> = conn.exec_first(
+            "SELECT DATA_TYPE FROM information_schema.COLUMNS WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = 'chat_tool_calls' AND COLUMN_NAME = ?",
+            (column,),
+        )?;
+        if data_type.is_some_and(|t| t.eq_ignore_ascii_case("text")) {
+            debug!("Widening column {column} of chat_tool_calls");
+            conn.query_drop(format!("ALTER TABLE chat_tool_calls MODIFY {column} {definition}"))?;
+        }
+        Ok(())
+    }
+
 }
 
 impl HistoryTrait for MysqlHistory {
-    /// Validates and inserts a [`ChatMessage`] into the `chat_history` table.
+    /// Validates and inserts a [`ChatMessage`] into the `chat_history` table
+    /// and its tool calls into `chat_tool_calls`, in one transaction.
     ///
     /// The message is validated via [`ChatMessage::validate`] and sanitised
-    /// with [`ChatMessage::noemoji`] before insertion.  The INSERT uses a
-    /// parameterised query to prevent SQL injection.
+    /// with [`ChatMessage::noemoji`] before insertion.  The INSERTs use
+    /// parameterised queries to prevent SQL injection.
     ///
     /// # Errors
     /// Returns an error if validation fails, if a connection cannot be
-    /// obtained, or if the INSERT statement fails.
+    /// obtained, or if an INSERT statement fails.
     fn store(&mut self, msg: &mut ChatMessage) -> std::result::Result<(), Box<dyn std::error::Error>> {
         if !msg.validate() {
             return Err(anyhow::anyhow!("Invalid chat message data").into());
         }
         let msg = msg.noemoji();
+        let tool_approvals = encode_tool_approvals(&msg);
+        let tool_calls = encode_tool_calls(&msg);
+        let chatuuid = msg.chatuuid.clone();
         let mut conn = self.get_connection()?;
-        let params = (msg.user, msg.chatuuid, msg.user_message, msg.bot_response);
-        conn.exec_drop(
-            "INSERT INTO chat_history (username, chatuuid, user_message, bot_response) VALUES (?, ?, ?, ?)",
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+        let params = (
+            msg.user, msg.chatuuid, msg.user_message, msg.bot_response, msg.backend,
+            msg.usage.map(|u| u.prompt_tokens), msg.usage.map(|u| u.completion_tokens), tool_approvals,
+        );
+        tx.exec_drop(
+            "INSERT INTO chat_history (username, chatuuid, user_message, bot_response, backend, prompt_tokens, completion_tokens, tool_approvals) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
             params,
         )?; 
+        let message_id = tx.last_insert_id().unwrap_or_default();
+        tx.exec_batch(
+            "INSERT INTO chat_tool_calls (message_id, chatuuid, name, arguments, result, duration_ms, error) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            tool_calls.into_iter().map(|(name, arguments, result, duration_ms, error)| {
+                (message_id, &chatuuid, name, arguments, result, duration_ms, error)
+            }),
+        )?;
+        tx.commit()?;
         Ok(())
     }
 
     /// Retrieves all [`ChatMessage`]s for the given `chatuuid` from MySQL.
     ///
     /// Rows are fetched with a parameterised SELECT and mapped to
-    /// [`ChatMessage`] via [`ChatMessage::from_tuple`].
+    /// [`ChatMessage`] via [`ChatMessage::from_tuple`], with the `id`,
+    /// `backend`, token usage and tool approval columns filled in afterwards.
+    /// The rows of `chat_tool_calls` are then attached to their messages.
     ///
     /// # Errors
     /// Returns an error if a connection cannot be obtained or if the SELECT
     /// query fails.
     fn read(&self, chatuuid: &str) -> std::result::Result<Vec<crate::ChatMessage>, Box<dyn std::error::Error>> {
         let mut conn = self.get_connection()?;
-        let result: Vec<(String, String, String, String)> = conn.exec(
-            "SELECT username, user_message, bot_response, chatuuid FROM chat_history WHERE chatuuid = ?",
+        let result: Vec<HistoryRow> = conn.exec(
+            "SELECT id, username, user_message, bot_response, chatuuid, backend, prompt_tokens, completion_tokens, tool_approvals FROM chat_history WHERE chatuuid = ?",
+            (chatuuid,),
+        )?;
+        let mut result: Vec<ChatMessage> = result.into_iter()
+            .map(|(id, user, user_message, bot_response, chatuuid, backend, prompt_tokens, completion_tokens, tool_approvals)| {
+                let mut message = ChatMessage {
+                    id: Some(id),
+                    backend,
+                    usage: prompt_tokens.zip(completion_tokens).map(|(p, c)| Usage::new(p, c)),
+                    ..ChatMessage::from_tuple((user, user_message, bot_response, chatuuid))
+                };
+                decode_tool_approvals(&mut message, tool_approvals);
+                message
+            })
+            .collect();
+        let tool_calls: Vec<(i64, String, String, String, i64, Option<String>)> = conn.exec(
+            "SELECT message_id, name, arguments, result, duration_ms, error FROM chat_tool_calls WHERE chatuuid = ? ORDER BY id",
             (chatuuid,),
         )?;
-        let result: Vec<ChatMessage> = result.into_iter()
-            .map(ChatMessage::from_tuple)
+        let tool_calls = tool_calls.into_iter()
+            .map(|(message_id, name, arguments, result, duration_ms, error)| -> (i64, ToolCallRow) {
+                (message_id, (name, arguments, result, duration_ms, error))
+            })
             .collect();
+        decode_tool_calls(&mut result, tool_calls);
         Ok(result)
     }
 }
\ No newline at end of file
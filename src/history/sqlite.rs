@@ -2,7 +2,7 @@ use log::debug;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::{params, Result};
-use crate::{history::HistoryTrait, ChatMessage};
+use crate::{history::{decode_tool_approvals, decode_tool_calls, encode_tool_approvals, encode_tool_calls, HistoryTrait}, ChatMessage, Usage};
 use std::fs;
 use std::path::Path;
 
@@ -26,8 +26,9 @@ impl SqliteHistory {
     /// This method:
     /// 1. Calls [`Self::ensure_db_file_exists`] to create the file if absent.
     /// 2. Builds an r2d2 connection pool over the file.
-    /// 3. Executes `CREATE TABLE IF NOT EXISTS chat_history …` to initialise
-    ///    the schema.
+    /// 3. Executes `CREATE TABLE IF NOT EXISTS chat_history …` and
+    ///    `… chat_tool_calls …` to initialise the schema, then adds any
+    ///    columns missing from older databases.
     ///
     /// # Panics
     /// Panics if the database file cannot be created, if the pool cannot be
@@ -46,46 +47,100 @@ impl SqliteHistory {
                 chatuuid TEXT NOT NULL,
                 message TEXT NOT NULL,
                 timestamp INTEGER NOT NULL,
-                response TEXT
+                response TEXT,
+                backend TEXT,
+                prompt_tokens INTEGER,
+                completion_tokens INTEGER,
+                tool_approvals TEXT
             )",
             [],
         ).expect("Failed to create table");
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS chat_tool_calls (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                message_id INTEGER NOT NULL,
+                chatuuid TEXT NOT NULL,
+                name TEXT NOT NULL,
+                arguments TEXT NOT NULL,
+                result TEXT NOT NULL,
+                duration_ms INTEGER NOT NULL,
+                error TEXT
+            )",
+            [],
+        ).expect("Failed to create table");
+        Self::ensure_column(&conn, "backend", "TEXT").expect("Failed to migrate table");
+        Self::ensure_column(&conn, "prompt_tokens", "INTEGER").expect("Failed to migrate table");
+        Self::ensure_column(&conn, "completion_tokens", "INTEGER").expect("Failed to migrate table");
+        Self::ensure_column(&conn, "tool_approvals", "TEXT").expect("Failed to migrate table");
 
         SqliteHistory {
             pool,
         }
     }
+
+    /// Adds `column` with the given SQL `definition` to `chat_history` if a
+    /// database created by an older version lacks it.
+    fn ensure_column(conn: &rusqlite::Connection, column: &str, definition: &str) -> Result<()> {
+        let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('chat_history')")?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .filter_map(Result::ok)
+            .any(|name| name == column);
+        if !exists {
+            debug!("Adding column {column} to chat_history");
+            conn.execute(&format!("ALTER TABLE chat_history ADD COLUMN {column} {definition}"), [])?;
+        }
+        Ok(())
+    }
 }
 
 impl HistoryTrait for SqliteHistory {
-    /// Inserts a [`ChatMessage`] into the `chat_history` table.
+    /// Inserts a [`ChatMessage`] into the `chat_history` table and its tool
+    /// calls into `chat_tool_calls`, in one transaction.
     ///
     /// All fields of `msg` (`user`, `chatuuid`, `user_message`, `bot_response`,
-    /// `timestamp`) are written via a parameterised INSERT statement.
+    /// `timestamp`, `backend`, token `usage`, tool approvals as JSON) are
+    /// written via a parameterised INSERT statement.
     ///
     /// # Errors
     /// Returns an error if a connection cannot be obtained from the pool or
-    /// if the INSERT statement fails.
+    /// if an INSERT statement fails.
     fn store(&mut self, msg: &mut ChatMessage) -> Result<(), Box<dyn std::error::Error>> {
         let user = &msg.user;
         let message = &msg.user_message;
         let timestamp = msg.timestamp;
         let response = &msg.bot_response;
         let chatuuid = &msg.chatuuid;
-        let conn = self.pool.get()?;
-        conn.execute(
-            "INSERT INTO chat_history (user chatuuid, message, response, timestamp) VALUES (?1, ?2, ?3, ?4)",
-            params![user, chatuuid, message, response, timestamp],
+        let backend = &msg.backend;
+        let prompt_tokens = msg.usage.map(|u| u.prompt_tokens);
+        let completion_tokens = msg.usage.map(|u| u.completion_tokens);
+        let tool_approvals = encode_tool_approvals(msg);
+        let mut conn = self.pool.get()?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO chat_history (user, chatuuid, message, response, timestamp, backend, prompt_tokens, completion_tokens, tool_approvals) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![user, chatuuid, message, response, timestamp, backend, prompt_tokens, completion_tokens, tool_approvals],
         )?;
+        let id = tx.last_insert_rowid();
+        for (name, arguments, result, duration_ms, error) in encode_tool_calls(msg) {
+            tx.execute(
+                "INSERT INTO chat_tool_calls (message_id, chatuuid, name, arguments, result, duration_ms, error) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![id, chatuuid, name, arguments, result, duration_ms, error],
+            )?;
+        }
+        tx.commit()?;
+        msg.id = Some(id);
 
         Ok(())
     }
 
-    /// Retrieves up to 100 [`ChatMessage`]s for the given `chatuuid`.
+    /// Retrieves the latest 100 [`ChatMessage`]s for the given `chatuuid`.
     ///
-    /// Rows are ordered by `timestamp DESC` and mapped from the raw SQLite
-    /// columns (`id`, `user`, `message`, `timestamp`, `response`) into
-    /// [`ChatMessage`] structs.
+    /// The newest rows are selected and returned oldest-first, mapped from the
+    /// raw SQLite columns (`id`, `user`, `message`, `timestamp`, `response`,
+    /// `backend`, `prompt_tokens`, `completion_tokens`, `tool_approvals`) into
+    /// [`ChatMessage`] structs, which then receive their rows from
+    /// `chat_tool_calls`.
     ///
     /// # Errors
     /// Returns an error if a connection cannot be obtained from the pool,
@@ -94,22 +149,40 @@ impl HistoryTrait for SqliteHistory {
         let limit = 100; // Default limit for the number of messages to read
         let conn = self.pool.get()?;
         let mut stmt = conn.prepare(
-            "SELECT id, user, message, timestamp, response FROM chat_history WHERE chatuuid = '?1' ORDER BY timestamp DESC LIMIT ?2"
+            "SELECT id, user, message, timestamp, response, backend, prompt_tokens, completion_tokens, tool_approvals FROM chat_history WHERE chatuuid = ?1 ORDER BY id DESC LIMIT ?2"
         )?;
-        let rows = stmt.query_map(params![limit as i64, chatuuid], |row| {
-            Ok(ChatMessage {
+        let rows = stmt.query_map(params![chatuuid, limit as i64], |row| {
+            let prompt_tokens: Option<u32> = row.get(6)?;
+            let completion_tokens: Option<u32> = row.get(7)?;
+            let mut message = ChatMessage {
                 id: row.get(0)?,
                 user: row.get(1)?,
                 chatuuid: chatuuid.to_string(),
                 user_message: row.get(2)?,
                 timestamp: row.get(3)?,
                 bot_response: row.get(4).unwrap_or_default(),
-            })
+                backend: row.get(5)?,
+                usage: prompt_tokens.zip(completion_tokens).map(|(p, c)| Usage::new(p, c)),
+                ..Default::default()
+            };
+            decode_tool_approvals(&mut message, row.get(8)?);
+            Ok(message)
         })?;
         let mut messages = Vec::new();
         for msg in rows {
             messages.push(msg?);
         }
+        messages.reverse();
+
+        let mut stmt = conn.prepare(
+            "SELECT message_id, name, arguments, result, duration_ms, error FROM chat_tool_calls WHERE chatuuid = ?1 ORDER BY id"
+        )?;
+        let tool_calls = stmt
+            .query_map(params![chatuuid], |row| {
+                Ok((row.get(0)?, (row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?)))
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        decode_tool_calls(&mut messages, tool_calls);
         Ok(messages)
     }
 }
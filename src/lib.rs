@@ -2,31 +2,88 @@
 //!
 //! A unified abstraction layer for interacting with multiple LLM backends
 //! (Ollama, MistralAI) with support for chat history persistence, embeddings,
-//! optional tool/component registries, and query classification.
+//! optional tool/component registries, query classification, and routing of
+//! prompts to different models.
+//!
+//! The `tools` feature (on by default) is required: the chat backends are
+//! built on its HTTP and JSON dependencies.
+
+#[cfg(not(feature="tools"))]
+compile_error!("erh_llm requires the `tools` feature; do not build it with `default-features = false` without re-enabling `tools`");
 
 mod history;
+#[cfg(any(feature="testing", feature="mcp"))]
+mod http;
+#[cfg(feature="mcp")]
+mod mcp;
+#[cfg(feature="admin")]
+mod admin;
+#[cfg(feature="cassette")]
+mod cassette;
 mod composer;
+#[cfg(feature="config")]
+mod config;
+mod error;
+mod metrics;
+mod mock;
+mod registry;
+mod response;
+mod retry;
+mod router;
+mod telemetry;
+#[cfg(feature="tools")]
+mod agent;
 #[cfg(feature="tools")]
 mod components;
+#[cfg(feature="tools")]
+mod structured;
+#[cfg(feature="testing")]
+pub mod testing;
 
+#[cfg(feature="admin")]
+pub use admin::{LocalModel, ModelDetails, OllamaAdmin, PullProgress};
+#[cfg(feature="mcp")]
+pub use mcp::{McpClient, McpError, McpPrompt, McpPromptArgument, McpResource, McpServer, McpTool, McpTransport, DEFAULT_MCP_REQUEST_TIMEOUT};
+#[cfg(feature="cassette")]
+pub use cassette::{Cassette, CassetteMode};
 pub use composer::{ComposedPrompt, PromptComposer};
+#[cfg(feature="config")]
+pub use config::{BackendSettings, Defaults, ErhConfig, HistorySettings, ModelProfile, ToolSettings};
+pub use error::{classify as classify_error, ErrorKind, LlmError};
+pub use metrics::Metrics;
+pub use mock::{MockError, MockLlm, MockMessage, MockReply, MockRequest};
+pub use registry::{ModelCapabilities, ModelRegistry, RegisteredModel};
+pub use response::{Response, Usage};
+pub use retry::RetryPolicy;
+pub use tokio_util::sync::CancellationToken;
+pub use router::{Classifier, Route, Router, RoutingDecision, RoutingRule};
+pub use telemetry::{capture_content, set_capture_content};
 
 pub use history::HistoryConfig;
-use mistralai_client::v1::{chat::{ChatMessage as MistralChatMessage, ChatParams}, client::Client as MistralClient, constants::Model};
 use serde::{Deserialize, Serialize};
 
 use crate::history::HistoryTrait;
 
-use log::{debug, warn};
-use ollama_rs::{coordinator::Coordinator, generation::{chat, embeddings::request::{self, EmbeddingsInput}}};
+use log::{debug, info, warn};
+use tracing::{field, info_span, Instrument};
+use ollama_rs::{generation::{chat, embeddings::request::{self, EmbeddingsInput}, parameters::{FormatType, KeepAlive, TimeUnit}}};
 pub use ollama_rs::models::ModelOptions;
 
 use crate::history::History;
 #[cfg(feature="tools")]
-pub use crate::components::{ComponentRegistry, Component, tools::Tool as Tool, prompt::Prompt as Prompt, resource::Resource, sampling::Sampling};
+pub use crate::agent::{AgentBackend, AgentLoop, AgentReply, AgentStep, OpenAiCompatible, StepCallback, ToolTurnError};
+#[cfg(feature="tools")]
+pub use ollama_rs::generation::{chat::{ChatMessage as AgentMessage, MessageRole}, tools::{ToolCall, ToolCallFunction, ToolInfo}};
+#[cfg(feature="tools")]
+pub use crate::components::{ComponentRegistry, Component, ComponentSource, DEFAULT_TOOL_TIMEOUT, approval::{RiskLevel, ToolApproval, ToolApprovalRecord, ToolCallRequest, ToolDecision}, tools::{Tool, ToolCallRecord, ToolError, ToolFn, ToolOutput}, prompt::Prompt as Prompt, prompt::{PromptSlot, PromptTemplate}, resource::{Resource, ResourceContents, ResourceFn, ResourceSubscription, UriVariables}, sampling::{Sampling, SamplingApproval, SamplingError, SamplingHandle, SamplingRequest}, selection::ToolSelection};
 
 
 /// Configuration for an LLM model, including its identifier and generation parameters.
+///
+/// Generation parameters left as `None` fall back to [`Query::options`] and
+/// then to the backend's defaults. See [`ModelConfig::apply_to`] for how they
+/// map onto Ollama; MistralAI receives `temperature`, `top_p`, `max_tokens`
+/// (from `num_predict`) and `random_seed` (from `seed`).
 #[derive(Debug, Clone, Serialize, Deserialize, Default,PartialEq)]
 pub struct ModelConfig {
     /// The model identifier (e.g. `"mistral"`, `"llama3"`).
@@ -39,6 +96,37 @@ pub struct ModelConfig {
     pub temperature: Option<f32>,
     /// Maximum context window size in tokens.
     pub context_size: Option<u32>,
+    /// Sample only from the `top_k` most likely tokens.
+    pub top_k: Option<u32>,
+    /// Nucleus sampling: sample from the smallest token set whose cumulative
+    /// probability exceeds `top_p`.
+    pub top_p: Option<f32>,
+    /// Discard tokens whose probability is below `min_p` times that of the
+    /// most likely token.
+    pub min_p: Option<f32>,
+    /// Penalty applied to recently repeated tokens.
+    pub repeat_penalty: Option<f32>,
+    /// How many recent tokens [`ModelConfig::repeat_penalty`] looks back over.
+    pub repeat_last_n: Option<i32>,
+    /// Random seed for reproducible output.
+    pub seed: Option<i32>,
+    /// Sequences that end generation when produced.
+    pub stop: Option<Vec<String>>,
+    /// Maximum number of tokens to generate; `-1` is unlimited. Unset keeps
+    /// the backend default.
+    pub num_predict: Option<i32>,
+    /// Mirostat sampling mode: `0` disabled, `1` Mirostat, `2` Mirostat 2.0.
+    pub mirostat: Option<u8>,
+    /// Mirostat learning rate.
+    pub mirostat_eta: Option<f32>,
+    /// Mirostat target entropy.
+    pub mirostat_tau: Option<f32>,
+    /// How long Ollama keeps the model loaded after a request: `"-1"` for
+    /// ever, `"0"` to unload immediately, or a duration such as `"30s"`,
+    /// `"10m"` or `"2h"`.
+    pub keep_alive: Option<String>,
+    /// Whether reasoning models should think before answering.
+    pub think: Option<bool>,
 }
 
 impl ModelConfig {
@@ -50,6 +138,100 @@ impl ModelConfig {
             ..Default::default()
         }
     }
+
+    /// Applies the generation parameters set on this config on top of `base`.
+    ///
+    /// `context_size` maps to `num_ctx`; parameters left unset keep the value
+    /// of `base`. `min_p` is missing from the client library's `ModelOptions`,
+    /// so chat requests add it to the options themselves. `keep_alive` and
+    /// `think` are request-level settings and are applied separately.
+    pub fn apply_to(&self, base: ModelOptions) -> ModelOptions {
+        let mut options = base;
+        if let Some(ctx) = self.context_size {
+            options = options.num_ctx(ctx as u64);
+        }
+        if let Some(v) = self.temperature {
+            options = options.temperature(v);
+        }
+        if let Some(v) = self.top_k {
+            options = options.top_k(v);
+        }
+        if let Some(v) = self.top_p {
+            options = options.top_p(v);
+        }
+        if let Some(v) = self.repeat_penalty {
+            options = options.repeat_penalty(v);
+        }
+        if let Some(v) = self.repeat_last_n {
+            options = options.repeat_last_n(v);
+        }
+        if let Some(v) = self.seed {
+            options = options.seed(v);
+        }
+        if let Some(v) = &self.stop {
+            options = options.stop(v.clone());
+        }
+        if let Some(v) = self.mirostat {
+            options = options.mirostat(v);
+        }
+        if let Some(v) = self.mirostat_eta {
+            options = options.mirostat_eta(v);
+        }
+        if let Some(v) = self.mirostat_tau {
+            options = options.mirostat_tau(v);
+        }
+        if let Some(v) = self.num_predict {
+            options = options.num_predict(v);
+        }
+        options
+    }
+
+    /// The parameters MistralAI supports (`temperature`, `top_p`,
+    /// `num_predict` as `max_tokens`, `seed` as `random_seed`) as request body
+    /// fields, for [`OpenAiCompatible::param`]. Other parameters, `min_p`
+    /// among them, have no MistralAI equivalent and are ignored.
+    #[cfg(feature="tools")]
+    pub(crate) fn mistral_fields(&self) -> Vec<(&'static str, serde_json::Value)> {
+        let mut fields = Vec::new();
+        if let Some(v) = self.temperature {
+            fields.push(("temperature", serde_json::json!(v)));
+        }
+        if let Some(v) = self.top_p {
+            fields.push(("top_p", serde_json::json!(v)));
+        }
+        if let Some(v) = self.num_predict.filter(|n| *n > 0) {
+            fields.push(("max_tokens", serde_json::json!(v)));
+        }
+        if let Some(v) = self.seed.filter(|s| *s >= 0) {
+            fields.push(("random_seed", serde_json::json!(v)));
+        }
+        fields
+    }
+
+    /// Parses [`ModelConfig::keep_alive`] into Ollama's request parameter.
+    ///
+    /// Returns `None` (with a warning) if the value cannot be parsed.
+    pub(crate) fn ollama_keep_alive(&self) -> Option<KeepAlive> {
+        let value = self.keep_alive.as_deref()?.trim();
+        let parsed = match value {
+            "-1" | "forever" => Some(KeepAlive::Indefinitely),
+            "0" => Some(KeepAlive::UnloadOnCompletion),
+            _ => {
+                let (number, unit) = value.split_at(value.len().saturating_sub(1));
+                let unit = match unit {
+                    "s" => Some(TimeUnit::Seconds),
+                    "m" => Some(TimeUnit::Minutes),
+                    "h" => Some(TimeUnit::Hours),
+                    _ => None,
+                };
+                unit.zip(number.parse().ok()).map(|(unit, time)| KeepAlive::Until { time, unit })
+            }
+        };
+        if parsed.is_none() {
+            warn!("Ignoring invalid keep_alive '{value}' for model {}", self.model);
+        }
+        parsed
+    }
 }
 
 /// Represents a single exchange between a user and the bot, used for history persistence.
@@ -58,7 +240,7 @@ pub struct ChatMessage {
     /// The underlying Ollama chat message, if this message originated from Ollama.
     pub ollama: Option<chat::ChatMessage>,
     /// Optional database row ID assigned after persistence.
-    pub id: Option<i32>,
+    pub id: Option<i64>,
     /// Username or identifier of the human participant.
     pub user: String,
     /// The message sent by the user.
@@ -69,6 +251,18 @@ pub struct ChatMessage {
     pub timestamp: i64,
     /// UUID identifying the chat session this message belongs to.
     pub chatuuid: String,
+    /// Name of the backend that produced `bot_response` (see [`LLM::name`]).
+    pub backend: Option<String>,
+    /// Token usage of the exchange, if the backend reported it.
+    pub usage: Option<Usage>,
+    /// Decisions taken on the tool calls made for `bot_response` (see
+    /// [`Tool::approve`]).
+    #[cfg(feature="tools")]
+    pub tool_approvals: Vec<ToolApprovalRecord>,
+    /// Tool calls made for `bot_response`; replayed as tool turns with the
+    /// rest of the history.
+    #[cfg(feature="tools")]
+    pub tool_calls: Vec<ToolCallRecord>,
 }
 
 impl ChatMessage {
@@ -99,11 +293,13 @@ impl ChatMessage {
     }
 
     /// Returns a copy of this message with all emoji characters stripped from
-    /// both `user_message` and `bot_response`.
+    /// `user_message`, `bot_response` and the results of its tool calls.
     pub fn noemoji(&self) -> Self {
         let mut msg = self.clone();
         msg.user_message = demoji!(self.user_message);
         msg.bot_response = demoji!(self.bot_response);
+        #[cfg(feature="tools")]
+        msg.tool_calls.iter_mut().for_each(|call| call.result = demoji!(call.result));
         msg
     }
     
@@ -116,26 +312,102 @@ pub enum LLM {
     Ollama(String, u16, ModelConfig),
     /// The MistralAI cloud API. Contains the API key.
     MistralAI(String),
-    /// Placeholder variant for future backends; panics if used.
-    Dummy,
+    /// Tries each backend in order, moving on to the next one when a call
+    /// fails with a connection, server (5xx / overloaded), rate-limit or
+    /// timeout error. Other errors are returned immediately.
+    Fallback(Vec<LLM>),
+    /// Records exchanges with, or replays them in place of, the wrapped
+    /// backend (see [`Cassette`]). Requires the `cassette` feature.
+    #[cfg(feature="cassette")]
+    Cassette(Cassette),
+    /// Scriptable in-process mock for tests (see [`MockLlm`]).
+    Dummy(MockLlm),
+}
+
+/// MistralAI model used when neither the request nor a profile names one.
+const MISTRAL_DEFAULT_MODEL: &str = "mistral-medium-latest";
+
+impl LLM {
+    /// Returns a short, human-readable name for this backend, e.g.
+    /// `"ollama://localhost:11434/mistral"` or `"mistralai"`.
+    ///
+    /// This is the value recorded in [`ChatMessage::backend`].
+    pub fn name(&self) -> String {
+        match self {
+            LLM::Ollama(host, port, model) => {
+                let host = host.split("://").last().unwrap_or(host);
+                format!("ollama://{host}:{port}/{}", model.model)
+            }
+            LLM::MistralAI(_) => "mistralai".to_string(),
+            LLM::Fallback(list) => {
+                let names: Vec<String> = list.iter().map(LLM::name).collect();
+                format!("fallback[{}]", names.join(", "))
+            }
+            #[cfg(feature="cassette")]
+            LLM::Cassette(cassette) => cassette.backend().name(),
+            LLM::Dummy(_) => "dummy".to_string(),
+        }
+    }
+
+    /// The model a concrete backend is configured to use, for metric labels.
+    fn model_name(&self) -> String {
+        match self {
+            LLM::Ollama(_, _, model) => model.model.clone(),
+            LLM::MistralAI(_) => MISTRAL_DEFAULT_MODEL.to_string(),
+            #[cfg(feature="cassette")]
+            LLM::Cassette(cassette) => cassette.backend().model_name(),
+            LLM::Dummy(_) => "mock".to_string(),
+            LLM::Fallback(_) => String::new(),
+        }
+    }
+
+    /// This backend set up to serve `model`: Ollama backends, also inside
+    /// fallback chains and cassettes, get `model` in place of their own.
+    /// MistralAI and the mock carry no model; requests to them take it from
+    /// [`QuerySetup::model`].
+    pub(crate) fn with_model(&self, model: &ModelConfig) -> LLM {
+        match self {
+            LLM::Ollama(host, port, _) => LLM::Ollama(host.clone(), *port, model.clone()),
+            LLM::Fallback(list) => LLM::Fallback(list.iter().map(|backend| backend.with_model(model)).collect()),
+            #[cfg(feature="cassette")]
+            LLM::Cassette(cassette) => LLM::Cassette(cassette.with_backend(cassette.backend().with_model(model))),
+            LLM::MistralAI(_) | LLM::Dummy(_) => self.clone(),
+        }
+    }
+
+    /// Flattens (possibly nested) [`LLM::Fallback`] chains into the ordered
+    /// list of concrete backends to try.
+    fn backends(&self) -> Vec<&LLM> {
+        match self {
+            LLM::Fallback(list) => list.iter().flat_map(LLM::backends).collect(),
+            other => vec![other],
+        }
+    }
 }
 
 /// Wraps a prompt string with an optional model override.
+#[derive(Clone)]
 pub enum UserPrompt {
     /// Use the model already configured on the [`Query`].
     Default(String),
     /// Override the model for this single request. Fields: `(model_config, prompt)`.
+    ///
+    /// If `model_config.model` names a profile in [`Query::models`], that
+    /// profile is used instead.
     Model(ModelConfig, String),
 }
 
 /// Holds all per-request configuration that governs how a query is sent to the LLM.
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct QuerySetup {
     /// Username or identifier of the human sending the query.
     pub user: String,
     /// UUID of the chat session, used to look up and store history.
     pub chatuuid: String,
     /// The model configuration to use for this query.
+    ///
+    /// If `model.model` names a profile in [`Query::models`], that profile
+    /// replaces the model configured on the backend.
     pub model : ModelConfig,
     /// The raw user prompt text.
     pub prompt: String,
@@ -146,22 +418,16 @@ pub struct QuerySetup {
     pub style: Option<String>,
     /// Optional constraint appended to the prompt to restrict the model's output.
     pub constraint: Option<String>,
+    /// Optional prompt template from [`Query::components`] rendered into the
+    /// composed prompt by [`Query::execute`] (only available with the `tools` feature).
+    #[cfg(feature="tools")]
+    pub template: Option<PromptTemplate>,
+    /// URIs of resources from [`Query::components`] read and attached to the
+    /// system prompt by [`Query::execute`] (only available with the `tools` feature).
+    #[cfg(feature="tools")]
+    pub resources: Vec<String>,
 }
 
-impl Default for QuerySetup {
-    fn default() -> Self {
-        QuerySetup {
-            model: ModelConfig::default(),
-            user: String::new(),
-            chatuuid: String::new(),
-            prompt: String::new(),
-            style: None,
-            constraint: None,
-            #[cfg(feature="tools")]
-            components: None,
-        }
-    }
-}
 impl QuerySetup {
     /// Creates a new [`QuerySetup`] with all fields set to their defaults.
     pub fn new() -> Self {
@@ -182,7 +448,6 @@ impl Default for LLM {
 /// optional chat history, optional RAG context, and model options. Use
 /// [`Query::new`] to create an instance, populate [`Query::setup`] and
 /// [`Query::context`], then call [`Query::execute`].
-#[derive(Default)]
 pub struct Query {
     /// The LLM backend this query will be sent to.
     connection: LLM,
@@ -196,9 +461,48 @@ pub struct Query {
     pub options: ModelOptions,
     /// Optional classification criteria used by [`Query::classify_query`].
     classification: Option<String>,
+    /// Optional catalog of named model profiles, see [`ModelRegistry`].
+    pub models: Option<ModelRegistry>,
     /// Optional component/tool registry (only available with the `tools` feature).
     #[cfg(feature="tools")]
     pub components: Option<ComponentRegistry>,
+    /// How many times [`Query::execute_typed`] re-asks the model after an answer
+    /// fails schema validation. Defaults to `2`.
+    pub structured_retries: u32,
+    /// Retry, backoff and timeout policy applied to every backend round trip;
+    /// its deadline bounds a whole turn, fallbacks included.
+    pub retry: RetryPolicy,
+    /// Optional token that aborts in-flight backend calls when cancelled.
+    ///
+    /// Cancelling drops the pending generation together with any tool calls
+    /// it is running; nothing is written to history and the call returns
+    /// [`LlmError::Cancelled`].
+    pub cancellation: Option<CancellationToken>,
+    /// Step limit, parallelism and step callback of the tool-calling loop
+    /// run when tools are attached (only available with the `tools` feature).
+    #[cfg(feature="tools")]
+    pub agent: AgentLoop,
+}
+
+impl Default for Query {
+    fn default() -> Self {
+        Query {
+            connection: LLM::default(),
+            setup: QuerySetup::default(),
+            history: None,
+            context: String::new(),
+            options: ModelOptions::default(),
+            classification: None,
+            models: None,
+            #[cfg(feature="tools")]
+            components: None,
+            structured_retries: 2,
+            retry: RetryPolicy::default(),
+            cancellation: None,
+            #[cfg(feature="tools")]
+            agent: AgentLoop::default(),
+        }
+    }
 }
 
 impl Query {
@@ -211,24 +515,35 @@ impl Query {
     /// * `chunk` – The text to embed.
     ///
     /// # Returns
-    /// A vector of `f32` values representing the embedding, or an empty vector if
-    /// the Ollama request fails.
+    /// A vector of `f32` values representing the embedding.
+    ///
+    /// # Errors
+    /// Returns the error of the final attempt if the Ollama request fails, or
+    /// an error if the response carries no embedding.
     pub async fn embed(config:(String,u16,ModelConfig),chunk:String) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        Self::embed_with_retry(config, chunk, &RetryPolicy::default()).await
+    }
+
+    /// Like [`Query::embed`] but runs the Ollama request under the given
+    /// [`RetryPolicy`].
+    ///
+    /// # Errors
+    /// Returns the policy's final error (including
+    /// [`LlmError::DeadlineExceeded`]) once it gives up, or an error if the
+    /// response carries no embedding.
+    pub async fn embed_with_retry(config:(String,u16,ModelConfig),chunk:String, retry: &RetryPolicy) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
         let (url, port, model) = config;
         let ollama = ollama_rs::Ollama::new(url.as_str(), port);
-        let e = EmbeddingsInput::Single(chunk);
         let options = ModelOptions::default().num_ctx(model.context_size.unwrap_or(2048) as u64);
-        let x  = ollama.generate_embeddings(request::GenerateEmbeddingsRequest::new(model.model.clone(), e).options(options)).await;
-        let y = match x {
-            Ok(response) => response,
-            Err(e) => {
-                debug!("Error generating embeddings: {e:?}");
-                return Ok(vec![]); // Return an empty vector on error
-            }
-        };
-        //println!("Embeddings: {:?}", y);
-        debug!("VectorCount: {:?}", y.embeddings[0].len());
-        Ok(y.embeddings[0].clone())
+        let y = retry.run("Ollama embeddings", || async {
+            let e = EmbeddingsInput::Single(chunk.clone());
+            let req = request::GenerateEmbeddingsRequest::new(model.model.clone(), e).options(options.clone());
+            ollama.generate_embeddings(req).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error>)
+        }).await.inspect_err(|e| debug!("Error generating embeddings: {e:?}"))?;
+        let embedding = y.embeddings.into_iter().next()
+            .ok_or_else(|| format!("Ollama returned no embedding for model {}", model.model))?;
+        debug!("VectorCount: {:?}", embedding.len());
+        Ok(embedding)
     }
 
     /// Retrieves all stored [`ChatMessage`]s for the given session UUID from the
@@ -280,28 +595,88 @@ impl Query {
     /// user query, and style) and sends it to the configured LLM backend.
     ///
     /// The response is stored in the history backend (if configured) and returned
-    /// as a `String`. Returns an empty string on send failure.
-    pub async fn execute(&mut self) -> Result<String, Box<dyn std::error::Error>> {
-        debug!("Running query with message: {}", self.setup.prompt);
+    /// as a [`Response`] carrying token usage, latency and the answering
+    /// backend.
+    ///
+    /// # Errors
+    /// Returns [`LlmError::Cancelled`] if [`Query::cancellation`] is triggered
+    /// before the response arrives, [`LlmError::ToolsUnsupported`] if a
+    /// registry is attached but [`Query::models`] lists the model without
+    /// tool capability, and otherwise the final error of [`Query::retry`]
+    /// (e.g. [`LlmError::DeadlineExceeded`]) once it gives up.
+    #[tracing::instrument(name = "query.execute", skip_all, fields(chatuuid = %self.setup.chatuuid, user = %self.setup.user))]
+    pub async fn execute(&mut self) -> Result<Response, Box<dyn std::error::Error>> {
+        debug!("Running query with message: {}", telemetry::log_content(&self.setup.prompt));
         debug!("ComponentRegistry: {:?}", self.components.as_ref().map(|c| c.components.len()));
 
-        let qsetup = self.setup.clone();
+        let (composer, user) = self.composer().await?;
+        let composed = composer.build(user);
 
-        let composed = PromptComposer::new()
-            .context(self.context.clone())
-            .constraint(qsetup.constraint.as_deref().unwrap_or(""))
-            .style(qsetup.style.as_deref().unwrap_or(""))
-            .build(&qsetup.prompt);
-
-        debug!("System prompt: {}", composed.system);
+        debug!("System prompt: {}", telemetry::log_content(&composed.system));
 
         // Send the system prompt as a dedicated system turn so Ollama keeps
         // context, constraints and style clearly separated from the user query.
-        let x = self.send_with_system(composed.system, composed.user).await.unwrap_or_default();
-        log::debug!("Query result: {x:?}");
+        let x = self.send_with_system(composed.system, composed.user).await?;
+        log::debug!("Query result from {}: {}", x.backend, telemetry::log_content(&x.text));
         Ok(x)
     }
 
+    /// Like [`Query::execute`] but constrains the answer to the JSON schema of
+    /// `T` and deserialises it.
+    ///
+    /// The schema is sent as Ollama's `format` parameter (MistralAI gets its
+    /// JSON response format) and is also described in the system prompt. The
+    /// answer is validated against the schema; on failure the validation error
+    /// is fed back to the model and the request is retried up to
+    /// [`Query::structured_retries`] times. Only the accepted answer is stored
+    /// in history.
+    ///
+    /// # Errors
+    /// Returns an error if the backend call fails, if history storage fails, or
+    /// if no valid answer was produced within the retry budget.
+    #[cfg(feature="tools")]
+    #[tracing::instrument(name = "query.execute", skip_all, fields(chatuuid = %self.setup.chatuuid, user = %self.setup.user))]
+    pub async fn execute_typed<T>(&mut self) -> Result<T, Box<dyn std::error::Error>>
+    where
+        T: schemars::JsonSchema + serde::de::DeserializeOwned,
+    {
+        use ollama_rs::generation::parameters::JsonStructure;
+
+        let schema = structured::schema_for::<T>();
+        let schema_value = schema.as_value().clone();
+
+        let (composer, user) = self.composer().await?;
+        let composed = composer
+            .output_format(format!(
+                "Respond only with a JSON value matching this JSON schema:\n{}",
+                serde_json::to_string_pretty(&schema_value)?
+            ))
+            .build(user);
+
+        let mut user = composed.user.clone();
+        let mut last_error = String::new();
+        for attempt in 0..=self.structured_retries {
+            let format = FormatType::StructuredJson(Box::new(JsonStructure::new_for_schema(schema.clone())));
+            let reply = self.chat(Some(composed.system.clone()), user.clone(), Some(format)).await?;
+            let resp = reply.text.clone();
+            match structured::parse::<T>(&resp, &schema_value) {
+                Ok(value) => {
+                    self.store_exchange(&reply)?;
+                    return Ok(value);
+                }
+                Err(e) => {
+                    warn!("Structured response rejected (attempt {}): {e}", attempt + 1);
+                    user = format!(
+                        "{}\n\nYour previous answer was invalid: {e}\nPrevious answer:\n{resp}\n\nRespond again with JSON that matches the schema.",
+                        composed.user
+                    );
+                    last_error = e;
+                }
+            }
+        }
+        Err(format!("No valid structured response after {} attempts: {last_error}", self.structured_retries + 1).into())
+    }
+
     /// Summarises the stored chat history for the current session into a compact
     /// paragraph, using the `mistral` model.
     ///
@@ -331,7 +706,7 @@ impl Query {
             let prompt = format!(
                 "QUERY: Summarize the following chat history in a concise paragraph:\n\nCHAT_HISTORY: {history_text}\n",
             );
-            self.send_raw(UserPrompt::Model(ModelConfig::new("mistral"), prompt)).await?
+            self.send_raw(UserPrompt::Default(prompt)).await?
         } else {
             String::new()
         };
@@ -352,24 +727,13 @@ impl Query {
     /// # Errors
     /// Returns an error if the LLM request fails or if history storage fails.
     pub async fn send(&mut self, prompt: String) -> Result<String, Box<dyn std::error::Error>> {
-        let resp = self.send_raw(UserPrompt::Default(prompt)).await?;
-        let mut msg =ChatMessage { id: None, user: self.setup.user.clone(), user_message: self.setup.prompt.clone(), bot_response: resp.clone(), timestamp: 0 , chatuuid: self.setup.chatuuid.clone(),..Default::default() };
-        debug!("Storing message in history: {msg:?}");
-        let x = if let Some(history) = &mut self.history {
-            history.store(&mut msg)
-        } else {
-            Ok(())
-        };
-        if let Err(e) = x {
-            warn!("Error storing message in history: {e}");
-            Err(e)
-        } else {
-            Ok(resp)
-        }
+        let reply = self.chat(None, prompt, None).await?;
+        self.store_exchange(&reply)?;
+        Ok(reply.text)
     }
 
     /// Sends a system message and a user query to the LLM as separate role turns,
-    /// then persists the exchange to history.
+    /// then persists the exchange (including token usage) to history.
     ///
     /// This is the preferred path from [`Query::execute`]: the system message carries
     /// role, context, constraints and style; the user message contains only the raw
@@ -379,18 +743,33 @@ impl Query {
     ///
     /// # Errors
     /// Returns an error if the LLM request or history storage fails.
-    pub async fn send_with_system(&mut self, system: String, user_query: String) -> Result<String, Box<dyn std::error::Error>> {
-        let resp = self.send_raw_with_system(system, user_query).await?;
+    pub async fn send_with_system(&mut self, system: String, user_query: String) -> Result<Response, Box<dyn std::error::Error>> {
+        let reply = self.chat(Some(system), user_query, None).await?;
+        self.store_exchange(&reply)?;
+        Ok(reply)
+    }
+
+    /// Persists the current [`QuerySetup::prompt`] together with the reply as a
+    /// [`ChatMessage`] in the history backend, if one is configured.
+    ///
+    /// # Errors
+    /// Returns an error if history storage fails.
+    fn store_exchange(&mut self, reply: &Response) -> Result<(), Box<dyn std::error::Error>> {
         let mut msg = ChatMessage {
             id: None,
             user: self.setup.user.clone(),
             user_message: self.setup.prompt.clone(),
-            bot_response: resp.clone(),
+            bot_response: reply.text.clone(),
             timestamp: 0,
             chatuuid: self.setup.chatuuid.clone(),
+            backend: Some(reply.backend.clone()),
+            usage: Some(reply.usage),
+            #[cfg(feature="tools")]
+            tool_approvals: reply.tool_approvals.clone(),
+            #[cfg(feature="tools")]
+            tool_calls: reply.tool_calls.clone(),
             ..Default::default()
         };
-        debug!("Storing message in history: {msg:?}");
         let x = if let Some(history) = &mut self.history {
             history.store(&mut msg)
         } else {
@@ -400,173 +779,555 @@ impl Query {
             warn!("Error storing message in history: {e}");
             Err(e)
         } else {
-            Ok(resp)
+            Ok(())
         }
     }
 
-    /// Like [`Query::send_raw`] but prepends a `system` role message before the
-    /// user turn. History is **not** written by this method.
-    async fn send_raw_with_system(&self, system: String, user_query: String) -> Result<String, Box<dyn std::error::Error>> {
-        let resp = match &self.connection {
-            LLM::Ollama(host, port, model) => {
-                let history = if let Some(history) = &self.history {
-                    let msgs = history.read(&self.setup.chatuuid)?;
-                    let mut chat_history = vec![];
-                    for msg in msgs {
-                        let user_cm = chat::ChatMessage::new(chat::MessageRole::User, msg.user_message.clone());
-                        chat_history.push(user_cm);
-                        let bot_cm = chat::ChatMessage::new(chat::MessageRole::Assistant, msg.bot_response.clone());
-                        chat_history.push(bot_cm);
-                    }
-                    chat_history
-                } else {
-                    vec![]
-                };
-
-                let ollama = ollama_rs::Ollama::new(host.as_str(), *port);
-                let options = if let Some(ctx) = model.context_size {
-                    self.options.clone().num_ctx(ctx as u64)
-                } else {
-                    self.options.clone()
-                }.num_predict(-1);
-                let mut coordinator = Coordinator::new(ollama, model.model.to_string(), history)
-                    .options(options);
-
-                #[cfg(feature = "tools")]
-                if model.tool.unwrap_or(false) {
-                    if let Some(components) = &self.components {
-                        debug!("Adding components/tools to Ollama coordinator");
-                        coordinator = components.clone().add_tools(coordinator);
-                    }
-                }
-
-                let messages = vec![
-                    chat::ChatMessage::new(chat::MessageRole::System, system),
-                    chat::ChatMessage::new(chat::MessageRole::User, user_query),
-                ];
-
-                debug!("Sending composed prompt to Ollama");
-                let resp = coordinator.chat(messages).await;
-                match resp {
-                    Ok(response) => response.message.content,
-                    Err(e) => {
-                        debug!("Error communicating with Ollama: {e:?}");
-                        return Err(Box::new(e));
-                    }
-                }
-            }
-            // For non-Ollama backends fall back to a single concatenated prompt.
-            _ => {
-                let fallback = format!("{system}\n\n{user_query}");
-                self.send_raw(UserPrompt::Default(fallback)).await?
-            }
-        };
-        debug!("Received response: {resp}");
-        Ok(resp)
-    }
-
-
     ///
     /// Chat history is read from the persistence layer and injected into the
-    /// request (Ollama coordinator) so the model retains conversational context,
+    /// request so the model retains conversational context,
     /// but the new exchange is **not** stored. Use [`Query::send`] if you want
     /// automatic history persistence.
     ///
     /// # Errors
     /// Returns an error if the underlying LLM client reports a failure.
     pub async fn send_raw(&self, prompt: UserPrompt) -> Result<String, Box<dyn std::error::Error>> {
-        let (text,_model) = match prompt {
-            UserPrompt::Default(p) => (p,ModelConfig::default()),
+        let (text, model) = match prompt {
+            UserPrompt::Default(p) => (p, None),
             UserPrompt::Model(model, p) => {
-                (p, model)
+                (p, Some(model))
             }
         };
-        //debug!("Sending prompt!!: {text}");
-        let resp = match &self.connection {
-            LLM::Ollama(host, port, model) => {
-                //load local chat history into ollama coordinator
-                let history = if let Some(history) = &self.history {
-                    let msgs = history.read(&self.setup.chatuuid)?;
-                    let mut chat_history = vec![];
-                    for msg in msgs {
-                        let user_cm = chat::ChatMessage::new(chat::MessageRole::User, msg.user_message.clone());
-                        chat_history.push(user_cm);
-                        let bot_cm = chat::ChatMessage::new(chat::MessageRole::Assistant, msg.bot_response.clone());
-                        chat_history.push(bot_cm);
-                    }
-                    chat_history
-                } else {
-                    vec![]
-                };
-                let ollama = ollama_rs::Ollama::new(host.as_str(), *port);
-                let options = if let Some(ctx) = model.context_size {
-                    self.options.clone().num_ctx(ctx as u64)
-                } else {
-                    self.options.clone()
-                }.num_predict(-1);
-                let mut coordinator = Coordinator::new(ollama, model.model.to_string(), history)
-                    .options(options);
+        Ok(self.chat_as(model, None, text, None).await?.text)
+    }
 
-                let cm = chat::ChatMessage::new(chat::MessageRole::User, text);
+    /// Sends a single turn to the configured backend inside an `llm.chat`
+    /// span (see [`telemetry`]) and records usage and timing on it.
+    ///
+    /// # Errors
+    /// See [`Query::chat_cancellable`].
+    async fn chat(&self, system: Option<String>, text: String, format: Option<FormatType>) -> Result<Response, Box<dyn std::error::Error>> {
+        self.chat_as(None, system, text, format).await
+    }
 
-                #[cfg(feature="tools")]
-                if model.tool.unwrap_or(false){
-                    if let Some(components) = &self.components {
-                        debug!("Adding components/tools to Ollama coordinator");
-                        coordinator = components.clone().add_tools(coordinator);
-                    }
+    /// Like [`Query::chat`] with an optional per-request model override.
+    ///
+    /// # Errors
+    /// See [`Query::chat_cancellable`].
+    async fn chat_as(&self, model: Option<ModelConfig>, system: Option<String>, text: String, format: Option<FormatType>) -> Result<Response, Box<dyn std::error::Error>> {
+        let span = info_span!(
+            "llm.chat",
+            chatuuid = %self.setup.chatuuid,
+            model = field::Empty,
+            backend = field::Empty,
+            prompt_tokens = field::Empty,
+            completion_tokens = field::Empty,
+            finish_reason = field::Empty,
+            latency_ms = field::Empty,
+            prompt = field::Empty,
+            response = field::Empty,
+        );
+        telemetry::record_content(&span, "prompt", &text);
+        let result = self.chat_cancellable(model, system, text, format).instrument(span.clone()).await;
+        if let Ok(response) = &result {
+            span.record("model", response.model.as_str());
+            span.record("backend", response.backend.as_str());
+            span.record("prompt_tokens", response.usage.prompt_tokens);
+            span.record("completion_tokens", response.usage.completion_tokens);
+            span.record("latency_ms", response.latency.as_millis() as u64);
+            if let Some(reason) = &response.finish_reason {
+                span.record("finish_reason", reason.as_str());
+            }
+            telemetry::record_content(&span, "response", &response.text);
+        }
+        result
+    }
+
+    /// Sends a single turn to the configured backend, aborting early when
+    /// [`Query::cancellation`] fires.
+    ///
+    /// # Errors
+    /// Returns [`LlmError::Cancelled`] on cancellation, otherwise see
+    /// [`Query::chat_backends`].
+    async fn chat_cancellable(&self, model: Option<ModelConfig>, system: Option<String>, text: String, format: Option<FormatType>) -> Result<Response, Box<dyn std::error::Error>> {
+        let Some(token) = &self.cancellation else {
+            return self.chat_backends(model, system, text, format).await;
+        };
+        if token.is_cancelled() {
+            return Err(Box::new(LlmError::Cancelled));
+        }
+        let call = Box::pin(self.chat_backends(model, system, text, format));
+        match futures::future::select(call, Box::pin(token.cancelled())).await {
+            futures::future::Either::Left((result, _)) => result,
+            futures::future::Either::Right(_) => {
+                info!("Query for chat {} cancelled", self.setup.chatuuid);
+                Err(Box::new(LlmError::Cancelled))
+            }
+        }
+    }
+
+    /// Sends a single turn, walking [`LLM::Fallback`] chains within the
+    /// [`RetryPolicy::deadline`] of [`Query::retry`].
+    ///
+    /// # Errors
+    /// Returns the error of the last backend tried, the first error that is
+    /// not eligible for fallback, a [`ToolTurnError`] once tools have run, or
+    /// [`LlmError::DeadlineExceeded`].
+    async fn chat_backends(&self, model: Option<ModelConfig>, system: Option<String>, text: String, format: Option<FormatType>) -> Result<Response, Box<dyn std::error::Error>> {
+        let Some(deadline) = self.retry.deadline else {
+            return self.chat_fallback(model, system, text, format).await;
+        };
+        match tokio::time::timeout(deadline, self.chat_fallback(model, system, text, format)).await {
+            Ok(result) => result,
+            Err(_) => Err(Box::new(LlmError::DeadlineExceeded(deadline))),
+        }
+    }
+
+    /// Sends a single turn to each backend of [`LLM::Fallback`] chains in
+    /// turn until one succeeds; see [`Query::chat_backends`].
+    async fn chat_fallback(&self, model: Option<ModelConfig>, system: Option<String>, text: String, format: Option<FormatType>) -> Result<Response, Box<dyn std::error::Error>> {
+        let start = std::time::Instant::now();
+        let model = self.request_model(model);
+        let backends = self.connection.backends();
+        let mut last_err: Option<Box<dyn std::error::Error>> = None;
+        for (i, backend) in backends.iter().enumerate() {
+            let name = backend.name();
+            let backend_start = std::time::Instant::now();
+            let result = self.chat_once(backend, model.as_ref(), system.clone(), text.clone(), format.clone())
+                .instrument(info_span!("llm.request", backend = %name))
+                .await;
+            match result {
+                Ok(mut response) => {
+                    debug!("Received response from {name}: {}", telemetry::log_content(&response.text));
+                    Metrics::global().record_request(&name, &backend.model_name(), backend_start.elapsed(), Ok(&response.usage));
+                    response.latency = start.elapsed();
+                    response.backend = name;
+                    return Ok(response);
                 }
-                
-                debug!("Sending prompt to Ollama: {:?}", cm);
-                let resp = coordinator.chat(vec![cm]).await;
-                match resp {
-                    Ok(response) => response.message.content,
-                    Err(e) => {
-                        debug!("Error communicating with Ollama: {e:?}");
-                        return Err(Box::new(e));
+                Err(e) => {
+                    let kind = error::classify(e.as_ref());
+                    Metrics::global().record_request(&name, &backend.model_name(), backend_start.elapsed(), Err(kind));
+                    if !kind.is_transient() {
+                        return Err(e);
+                    }
+                    // Tools ran against this backend; another one would run them again.
+                    #[cfg(feature="tools")]
+                    if e.is::<ToolTurnError>() {
+                        return Err(e);
                     }
+                    if i + 1 < backends.len() {
+                        warn!("Backend {name} failed ({kind}), falling back to {}: {e}", backends[i + 1].name());
+                    }
+                    last_err = Some(e);
                 }
             }
+        }
+        Err(last_err.unwrap_or_else(|| "No backend configured".into()))
+    }
+
+    /// Sends a single turn to `connection`, retrying each round trip under
+    /// [`Query::retry`].
+    ///
+    /// Ollama receives the optional `system` message as a dedicated system turn
+    /// after the replayed history; backends without system-turn support get
+    /// `system` and `text` concatenated into a single user prompt.
+    ///
+    /// `model` overrides the backend's own model configuration (see
+    /// [`Query::request_model`]).
+    ///
+    /// The returned [`Response`] carries text, usage, model and finish reason;
+    /// `latency` and `backend` are filled in by [`Query::chat_backends`].
+    async fn chat_once(&self, connection: &LLM, model: Option<&ModelConfig>, system: Option<String>, text: String, format: Option<FormatType>) -> Result<Response, Box<dyn std::error::Error>> {
+        let resp = match connection {
+            LLM::Ollama(host, port, backend_model) => {
+                let model = model.unwrap_or(backend_model);
+                let ollama = ollama_rs::Ollama::new(host.as_str(), *port);
+                let options = model.apply_to(self.options.clone());
+                let messages = self.turn_messages(system.as_deref(), &text)?;
+
+                let backend = agent::OllamaAgent {
+                    ollama,
+                    model: model.model.to_string(),
+                    options,
+                    format,
+                    keep_alive: model.ollama_keep_alive(),
+                    think: model.think,
+                    min_p: model.min_p,
+                };
+                let backend = self.retrying(connection, &backend);
+                if let Some(components) = &self.components
+                    && self.tools_allowed(model)?
+                {
+                    return self.run_agent(&backend, components, messages).await;
+                }
+                backend.send(&messages, &[]).await?.into_response()?
+            }
             LLM::MistralAI(apikey) => {
-                let client = MistralClient::new(Some(apikey.clone()), None, None, None).unwrap();
-                let model = Model::MistralMediumLatest;
-                let messages = vec![
-                    MistralChatMessage {
-                        role:mistralai_client::v1::chat::ChatMessageRole::User,
-                        content:text.clone(),
-                        tool_calls: None, }
-                ];
-                let options = Some(ChatParams {
-                    ..Default::default()
-                });
-
-                /* TODO: Add tool support for MistralAI                 
-                #[cfg(feature = "tools")]
+                let text = match system {
+                    Some(system) => format!("{system}\n\n{text}"),
+                    None => text,
+                };
+                let model = model.unwrap_or(&self.setup.model);
+                let backend = Self::mistral_backend(apikey, model, format.is_some());
+                let messages = vec![chat::ChatMessage::new(chat::MessageRole::User, text)];
+                let backend = self.retrying(connection, &backend);
+                if let Some(components) = &self.components
+                    && self.tools_allowed(model)?
                 {
-                    match &self.components {
-                        Some(comp) => {
-                            use mistralai_client::v1::{client, tool::{self, Tool}};
-
-                            debug!("Using tools/components in query");
-                            options
-                        },
-                        None =>  {
-                            debug!("No tools/components in query");
-                        }
+                    return self.run_agent(&backend, components, messages).await;
+                }
+                backend.send(&messages, &[]).await?.into_response()?
+            }
+            #[cfg(feature="cassette")]
+            LLM::Cassette(cassette) => {
+                let request = self.cassette_request(cassette.backend(), model, system.as_deref(), &text, format.as_ref())?;
+                match cassette.mode() {
+                    CassetteMode::Replay => cassette.replay_response(&request)?,
+                    CassetteMode::Record => {
+                        let response = Box::pin(self.chat_once(cassette.backend(), model, system, text, format)).await?;
+                        cassette.record_response(request, &response)?;
+                        response
                     }
-                } */
-
-                debug!("Sending prompt to MistralAI: {text}");
-                let response = client.chat(model, messages, options)?;
-                response.object
+                }
+            }
+            LLM::Dummy(mock) => {
+                let messages = self.turn_messages(system.as_deref(), &text)?;
+                let mock = mock::MockAgent { mock, structured: format.is_some() };
+                let backend = self.retrying(connection, &mock);
+                // The mock sees the registry's tools whatever the model config says.
+                if let Some(components) = &self.components {
+                    return self.run_agent(&backend, components, messages).await;
+                }
+                backend.send(&messages, &[]).await?.into_response()?
             }
-            // Add other LLMs here as needed
-            _ => panic!("Not possible"),
+            LLM::Fallback(_) => return Err("Fallback chains must be flattened before dispatch".into()),
         };
-        debug!("Received response: {resp}");
         Ok(resp)
     }
 
+    /// Describes the request `chat_once` would send to `backend`, for
+    /// [`Cassette`] fingerprinting.
+    ///
+    /// # Errors
+    /// Returns an error if the history backend fails or options cannot be serialised.
+    #[cfg(feature="cassette")]
+    fn cassette_request(&self, backend: &LLM, model: Option<&ModelConfig>, system: Option<&str>, text: &str, format: Option<&FormatType>) -> Result<cassette::CassetteRequest, Box<dyn std::error::Error>> {
+        let messages = self.transcript(system, text)?
+            .into_iter()
+            .map(|(role, content)| cassette::CassetteMessage { role: role.to_string(), content })
+            .collect();
+        let model = match backend {
+            LLM::Ollama(_, _, backend_model) => Some(model.unwrap_or(backend_model)),
+            _ => None,
+        };
+        let tools = match model {
+            Some(model) if model.tool.unwrap_or(false) => self.tool_names(),
+            _ => Vec::new(),
+        };
+
+        Ok(cassette::CassetteRequest {
+            backend: match (backend, model) {
+                (LLM::Ollama(host, port, _), Some(model)) => LLM::Ollama(host.clone(), *port, model.clone()).name(),
+                _ => backend.name(),
+            },
+            messages,
+            options: match model {
+                Some(model) => serde_json::to_value(model.apply_to(self.options.clone()))?,
+                None => serde_json::to_value(&self.options)?,
+            },
+            format: format.map(serde_json::to_value).transpose()?,
+            tools,
+        })
+    }
+
+    /// The model to use for one request, overriding the backend's own.
+    ///
+    /// The per-request `model` (or, without one, [`QuerySetup::model`]) is
+    /// looked up by name in [`Query::models`]; a matching profile wins.
+    /// Otherwise an explicit per-request `model` is used as given and `None`
+    /// keeps each backend's configuration.
+    fn request_model(&self, model: Option<ModelConfig>) -> Option<ModelConfig> {
+        let requested = model.as_ref().unwrap_or(&self.setup.model);
+        if let Some(registry) = &self.models
+            && let Some(profile) = registry.get(&requested.model)
+        {
+            debug!("Using model profile '{}' ({})", requested.model, profile.config.model);
+            return Some(profile.config.clone());
+        }
+        model
+    }
+
+    /// The MistralAI backend serving `model`, or [`MISTRAL_DEFAULT_MODEL`] if
+    /// `model` names none.
+    #[cfg(feature="tools")]
+    fn mistral_backend(apikey: &str, model: &ModelConfig, json: bool) -> OpenAiCompatible {
+        let name = if model.model.is_empty() { MISTRAL_DEFAULT_MODEL } else { model.model.as_str() };
+        let mut backend = OpenAiCompatible::mistral(apikey, name).json(json);
+        for (key, value) in model.mistral_fields() {
+            backend = backend.param(key, value);
+        }
+        backend
+    }
+
+    /// `backend` with each of its round trips retried under [`Query::retry`].
+    #[cfg(feature="tools")]
+    fn retrying<'a, B>(&'a self, connection: &LLM, backend: &'a B) -> agent::Retrying<'a, B> {
+        agent::Retrying { backend, policy: &self.retry, what: format!("{} chat", connection.name()) }
+    }
+
+    /// Whether tools may be attached to a request for `model`.
+    ///
+    /// # Errors
+    /// Returns [`LlmError::ToolsUnsupported`] if `model` enables tools but
+    /// [`Query::models`] lists it without tool capability.
+    #[cfg(feature="tools")]
+    fn tools_allowed(&self, model: &ModelConfig) -> Result<bool, Box<dyn std::error::Error>> {
+        if !model.tool.unwrap_or(false) {
+            return Ok(false);
+        }
+        if let Some(capabilities) = self.models.as_ref().and_then(|r| r.capabilities(model))
+            && !capabilities.tools
+        {
+            return Err(Box::new(LlmError::ToolsUnsupported(model.model.clone())));
+        }
+        Ok(true)
+    }
+
+    /// Runs [`Query::agent`] on `backend` with the tools of `components`.
+    ///
+    /// A [`ToolSelection`] without its own policy embeds under [`Query::retry`].
+    #[cfg(feature="tools")]
+    async fn run_agent<B: AgentBackend>(&self, backend: &B, components: &ComponentRegistry, messages: Vec<chat::ChatMessage>) -> Result<Response, Box<dyn std::error::Error>> {
+        let mut agent = std::borrow::Cow::Borrowed(&self.agent);
+        if let Some(selection) = &self.agent.selection
+            && selection.retry.is_none()
+        {
+            agent.to_mut().selection = Some(selection.clone().retry(self.retry.clone()));
+        }
+        self.with_sampling(Box::pin(agent.run(backend, components, messages))).await
+    }
+
+    /// Runs `turn`, a backend call that may invoke tools, while answering
+    /// the sampling requests those tools make (see [`SamplingHandle`]).
+    ///
+    /// Without a [`Sampling`] policy in [`Query::components`] the tools get a
+    /// handle that refuses every request.
+    async fn with_sampling<F: std::future::Future>(&self, turn: F) -> F::Output {
+        #[cfg(feature="tools")]
+        if let Some(policy) = self.components.as_ref().and_then(ComponentRegistry::sampling) {
+            use futures::StreamExt;
+
+            let (handle, mut requests) = SamplingHandle::channel();
+            let turn = Box::pin(handle.scope(turn));
+            let serve = Box::pin(async {
+                while let Some((request, reply)) = requests.next().await {
+                    // The tool may have given up waiting; nothing to do then.
+                    let _ = reply.send(self.sample(policy, request).await);
+                }
+                futures::future::pending::<std::convert::Infallible>().await
+            });
+            return match futures::future::select(turn, serve).await {
+                futures::future::Either::Left((output, _)) => output,
+                futures::future::Either::Right((never, _)) => match never {},
+            };
+        }
+        turn.await
+    }
+
+    /// Answers one sampling request under `policy` with a single turn that
+    /// carries neither chat history nor tools.
+    ///
+    /// # Errors
+    /// Returns [`SamplingError::Denied`] if the approval hook refuses and
+    /// [`SamplingError::Failed`] if the backend call fails.
+    #[cfg(feature="tools")]
+    async fn sample(&self, policy: &Sampling, request: SamplingRequest) -> Result<Response, SamplingError> {
+        let request = match &policy.approve {
+            Some(approve) => approve(request).await.map_err(|reason| {
+                info!("Sampling request denied by policy '{}': {reason}", policy.name);
+                SamplingError::Denied(reason)
+            })?,
+            None => request,
+        };
+        let hint = request.model.clone()
+            .filter(|hint| self.models.as_ref().is_some_and(|models| models.get(hint).is_some()));
+        let mut model = match policy.model.clone().or(hint) {
+            Some(name) => self.request_model(Some(ModelConfig::new(&name))),
+            None => self.request_model(None).or_else(|| {
+                self.connection.backends().into_iter().find_map(|backend| match backend {
+                    LLM::Ollama(_, _, model) => Some(model.clone()),
+                    _ => None,
+                })
+            }),
+        }
+        .unwrap_or_else(|| self.setup.model.clone());
+        let limit = match (request.max_tokens, policy.max_tokens) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        if let Some(limit) = limit {
+            let limit = i32::try_from(limit).unwrap_or(i32::MAX);
+            model.num_predict = Some(model.num_predict.filter(|n| *n >= 0).map_or(limit, |n| n.min(limit)));
+        }
+        model.tool = Some(false);
+        debug!("Sampling request under policy '{}' on model {}", policy.name, model.model);
+
+        let sub = Query {
+            connection: self.connection.clone(),
+            setup: QuerySetup {
+                user: self.setup.user.clone(),
+                chatuuid: self.setup.chatuuid.clone(),
+                ..Default::default()
+            },
+            options: self.options.clone(),
+            retry: self.retry.clone(),
+            cancellation: self.cancellation.clone(),
+            ..Default::default()
+        };
+        Box::pin(sub.chat_as(Some(model), request.system, request.prompt, None))
+            .await
+            .map_err(|e| SamplingError::Failed(e.to_string()))
+    }
+
+    /// The full message list of a turn: replayed history, the optional
+    /// system turn and the user turn.
+    ///
+    /// # Errors
+    /// Returns an error if the history backend fails.
+    fn turn_messages(&self, system: Option<&str>, text: &str) -> Result<Vec<chat::ChatMessage>, Box<dyn std::error::Error>> {
+        let mut messages = self.ollama_history()?;
+        if let Some(system) = system {
+            messages.push(chat::ChatMessage::new(chat::MessageRole::System, system.to_string()));
+        }
+        messages.push(chat::ChatMessage::new(chat::MessageRole::User, text.to_string()));
+        Ok(messages)
+    }
+
+    /// [`Query::turn_messages`] as `(role, content)` pairs.
+    ///
+    /// # Errors
+    /// Returns an error if the history backend fails.
+    #[cfg(feature="cassette")]
+    fn transcript(&self, system: Option<&str>, text: &str) -> Result<Vec<(&'static str, String)>, Box<dyn std::error::Error>> {
+        Ok(self.turn_messages(system, text)?
+            .into_iter()
+            .map(|m| (role_name(&m.role), m.content))
+            .collect())
+    }
+
+    /// Starts a [`PromptComposer`] with the context, constraint and style of
+    /// the query and returns it together with the user turn, after rendering
+    /// [`QuerySetup::template`] into them and attaching [`QuerySetup::resources`].
+    ///
+    /// # Errors
+    /// Returns an error if the selected template or a resource is not in
+    /// [`Query::components`].
+    async fn composer(&self) -> Result<(PromptComposer, String), Box<dyn std::error::Error>> {
+        let constraint = self.setup.constraint.clone().unwrap_or_default();
+        let style = self.setup.style.clone().unwrap_or_default();
+        let user = self.setup.prompt.clone();
+        #[cfg(feature="tools")]
+        let (constraint, style, user) = match &self.setup.template {
+            Some(template) => {
+                let input = template.input.as_deref().unwrap_or(&self.setup.prompt);
+                let rendered = self.render_prompt(&template.name, input)?;
+                debug!("Rendered prompt template {} into {:?}", template.name, template.slot);
+                let prepend = |existing: String| if existing.is_empty() { rendered.clone() } else { format!("{rendered}\n{existing}") };
+                match template.slot {
+                    PromptSlot::User => (constraint, style, rendered.clone()),
+                    PromptSlot::Constraint => (prepend(constraint), style, user),
+                    PromptSlot::Style => (constraint, prepend(style), user),
+                }
+            }
+            None => (constraint, style, user),
+        };
+        let composer = PromptComposer::new()
+            .context(self.context.clone())
+            .constraint(constraint)
+            .style(style);
+        #[cfg(feature="tools")]
+        let composer = {
+            let mut composer = composer;
+            for uri in &self.setup.resources {
+                let contents = self.read_resource(uri).await?;
+                composer = composer.resource(contents.uri, contents.mime_type, contents.text);
+            }
+            composer
+        };
+        Ok((composer, user))
+    }
+
+    /// Resources available in [`Query::components`], e.g. for a UI to offer
+    /// as [`QuerySetup::resources`].
+    #[cfg(feature="tools")]
+    pub fn resources(&self) -> Vec<&Resource> {
+        self.components.iter().flat_map(ComponentRegistry::resources).collect()
+    }
+
+    /// Reads the resource at `uri` from [`Query::components`].
+    ///
+    /// # Errors
+    /// Returns an error if no resource matches `uri`.
+    #[cfg(feature="tools")]
+    pub async fn read_resource(&self, uri: &str) -> Result<ResourceContents, Box<dyn std::error::Error>> {
+        let resource = self.components.as_ref().and_then(|components| components.resource(uri));
+        match resource {
+            Some(resource) => resource.read(uri).await.ok_or_else(|| format!("Reading resource {uri} failed").into()),
+            None => Err(format!("Unknown resource: {uri}").into()),
+        }
+    }
+
+    /// Prompt templates available in [`Query::components`], e.g. for a UI to
+    /// offer as [`QuerySetup::template`].
+    #[cfg(feature="tools")]
+    pub fn prompt_templates(&self) -> Vec<&Prompt> {
+        self.components.iter().flat_map(ComponentRegistry::prompts).collect()
+    }
+
+    /// Renders the prompt template `name` from [`Query::components`] with `input`.
+    ///
+    /// # Errors
+    /// Returns an error if no prompt of that name is registered.
+    #[cfg(feature="tools")]
+    pub fn render_prompt(&self, name: &str, input: &str) -> Result<String, Box<dyn std::error::Error>> {
+        self.components
+            .as_ref()
+            .and_then(|components| components.render_prompt(name, input))
+            .ok_or_else(|| format!("Unknown prompt template: {name}").into())
+    }
+
+    /// Names of all tools in [`Query::components`].
+    #[cfg(feature="cassette")]
+    fn tool_names(&self) -> Vec<String> {
+        #[cfg(feature="tools")]
+        if let Some(components) = &self.components {
+            return components.components.iter()
+                .flat_map(|c| c.tools.iter().map(|t| t.name.clone()))
+                .collect();
+        }
+        Vec::new()
+    }
+
+    /// Loads the stored history for the current session as Ollama chat turns.
+    ///
+    /// Stored tool calls are replayed between the user and assistant turns of
+    /// their exchange (see [`ToolCallRecord`]).
+    ///
+    /// # Errors
+    /// Returns an error if the history backend fails.
+    fn ollama_history(&self) -> Result<Vec<chat::ChatMessage>, Box<dyn std::error::Error>> {
+        let mut chat_history = vec![];
+        if let Some(history) = &self.history {
+            for msg in history.read(&self.setup.chatuuid)? {
+                chat_history.push(chat::ChatMessage::new(chat::MessageRole::User, msg.user_message.clone()));
+                #[cfg(feature="tools")]
+                chat_history.extend(ToolCallRecord::to_ollama(&msg.tool_calls));
+                chat_history.push(chat::ChatMessage::new(chat::MessageRole::Assistant, msg.bot_response.clone()));
+            }
+        }
+        Ok(chat_history)
+    }
+
     /// Classifies [`QuerySetup::prompt`] against the criteria stored in
     /// `self.classification`.
     ///
@@ -598,6 +1359,16 @@ impl Query {
 
 
 
+/// Lower-case name of `role`, as used on the wire.
+pub(crate) fn role_name(role: &chat::MessageRole) -> &'static str {
+    match role {
+        chat::MessageRole::User => "user",
+        chat::MessageRole::Assistant => "assistant",
+        chat::MessageRole::System => "system",
+        chat::MessageRole::Tool => "tool",
+    }
+}
+
 /// Strips all emoji characters from a string expression and returns a `String`.
 ///
 /// Uses a regex that covers the most common Unicode emoji ranges: emoticons,
@@ -605,6 +1376,7 @@ impl Query {
 ///
 /// # Example
 /// ```rust
+/// # use erh_llm::demoji;
 /// let clean = demoji!("Hello 🌍!");
 /// assert_eq!(clean, "Hello !");
 /// ```
@@ -624,4 +1396,97 @@ macro_rules! demoji {
         )).unwrap();
         regex.replace_all(&$string, "").to_string()
     }};
-}
\ No newline at end of file
+}
+#[cfg(all(test, feature="tools"))]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn query(connection: LLM) -> Query {
+        let mut query = Query::new(connection, HistoryConfig::None);
+        query.setup.prompt = "Hi".into();
+        query.retry = RetryPolicy::new(1);
+        query
+    }
+
+    #[test]
+    fn default_and_new_agree_on_structured_retries() {
+        assert_eq!(Query::default().structured_retries, 2);
+        assert_eq!(Query::new(LLM::default(), HistoryConfig::None).structured_retries, 2);
+    }
+
+    #[tokio::test]
+    async fn fallback_moves_on_after_transient_errors_only() {
+        let first = MockLlm::new().error(ErrorKind::Server, "overloaded").error(ErrorKind::Client, "bad request");
+        let second = MockLlm::new().reply("from the second").reply("unused");
+        let mut fallback = query(LLM::Fallback(vec![LLM::Dummy(first.clone()), LLM::Fallback(vec![LLM::Dummy(second.clone())])]));
+        assert_eq!(fallback.execute().await.unwrap().text, "from the second");
+        assert_eq!((first.requests().len(), second.requests().len()), (1, 1));
+
+        let err = fallback.execute().await.unwrap_err();
+        assert_eq!(classify_error(&*err), ErrorKind::Client);
+        assert_eq!(second.remaining(), 1);
+    }
+
+    #[cfg(feature="testing")]
+    #[tokio::test]
+    async fn fallback_reports_the_answering_backend() {
+        let down = testing::FakeOllama::start().await.unwrap();
+        down.error(503, "overloaded");
+        let up = testing::FakeOllama::start().await.unwrap();
+        up.reply("ok");
+        let backup = up.llm(ModelConfig::new("gemma3:4b"));
+        let response = query(LLM::Fallback(vec![down.llm(ModelConfig::new("llama3.2:1b")), backup.clone()])).execute().await.unwrap();
+        assert_eq!((response.text.as_str(), response.backend), ("ok", backup.name()));
+    }
+
+    #[tokio::test]
+    async fn fallback_stops_once_tools_have_run() {
+        let first = MockLlm::new().tool_call("shout", serde_json::json!({ "param": "hi" })).error(ErrorKind::Server, "overloaded");
+        let second = MockLlm::new().reply("unused");
+        let mut registry = ComponentRegistry::new();
+        registry.register(Component {
+            tools: vec![Tool::new("shout", "Upper-cases the text", |s: &String| {
+                let s = s.to_uppercase();
+                async move { s }
+            })],
+            ..Default::default()
+        });
+        let mut fallback = query(LLM::Fallback(vec![LLM::Dummy(first), LLM::Dummy(second.clone())]));
+        fallback.components = Some(registry);
+        assert!(fallback.execute().await.unwrap_err().is::<ToolTurnError>());
+        assert!(second.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancellation_aborts_before_and_during_a_call() {
+        let mock = MockLlm::new().latency(Duration::from_secs(5)).reply("too late");
+        let token = CancellationToken::new();
+        let mut cancelled = query(LLM::Dummy(mock.clone()));
+        cancelled.cancellation = Some(token.clone());
+
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            canceller.cancel();
+        });
+        let err = tokio::time::timeout(Duration::from_secs(1), cancelled.execute()).await
+            .expect("the call was not aborted")
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<LlmError>(), Some(&LlmError::Cancelled));
+        assert_eq!(mock.requests().len(), 1);
+
+        let err = cancelled.execute().await.unwrap_err();
+        assert_eq!(err.downcast_ref::<LlmError>(), Some(&LlmError::Cancelled));
+        assert_eq!(mock.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn an_untriggered_token_does_not_interfere() {
+        let mock = MockLlm::new().reply("done");
+        let mut query = query(LLM::Dummy(mock));
+        query.cancellation = Some(CancellationToken::new());
+        assert_eq!(query.execute().await.unwrap().text, "done");
+    }
+}
@@ -0,0 +1,624 @@
+//! MCP client over stdio or streamable HTTP.
+
+use std::{
+    process::Stdio,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::Future;
+use log::{debug, info, warn};
+use serde_json::{json, Map, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    sync::Mutex,
+};
+
+use super::{content_text, rpc_request, rpc_result, McpError, McpPrompt, McpResource, McpTool, PROTOCOL_VERSION};
+use crate::{Component, ComponentSource, Prompt, Resource, Tool, ToolError};
+
+/// How to reach an MCP server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum McpTransport {
+    /// Spawn `command` with `args` (and extra environment variables) and
+    /// speak newline-delimited JSON-RPC over its stdin/stdout. The process is
+    /// killed when the last handle to the client is dropped.
+    Stdio {
+        command: String,
+        args: Vec<String>,
+        env: Vec<(String, String)>,
+    },
+    /// POST JSON-RPC messages to a streamable HTTP endpoint, with extra
+    /// request headers (e.g. `Authorization`).
+    Http {
+        url: String,
+        headers: Vec<(String, String)>,
+    },
+}
+
+/// Time allowed for one request to an MCP server, unless changed with
+/// [`McpClient::request_timeout`].
+pub const DEFAULT_MCP_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+struct StdioPipes {
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+struct StdioProcess {
+    _child: Child,
+    pipes: Mutex<StdioPipes>,
+}
+
+enum Connection {
+    Stdio(Box<StdioProcess>),
+    Http {
+        client: reqwest::Client,
+        url: String,
+        headers: Vec<(String, String)>,
+        session: std::sync::Mutex<Option<String>>,
+    },
+}
+
+struct Inner {
+    connection: Connection,
+    next_id: AtomicU64,
+}
+
+/// A connection to an MCP server; see the [module documentation](super).
+///
+/// Clones share the connection.
+#[derive(Clone)]
+pub struct McpClient {
+    inner: Arc<Inner>,
+    timeout: Duration,
+    server_name: String,
+    server_version: String,
+    instructions: Option<String>,
+    capabilities: Value,
+}
+
+impl McpClient {
+    /// Spawns `command` with `args` and connects to it over stdio.
+    ///
+    /// # Errors
+    /// Returns an error if the process cannot be started or initialisation fails.
+    pub async fn stdio(command: &str, args: &[&str]) -> Result<Self, McpError> {
+        Self::connect(McpTransport::Stdio {
+            command: command.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            env: Vec::new(),
+        }).await
+    }
+
+    /// Connects to the streamable HTTP endpoint at `url`.
+    ///
+    /// # Errors
+    /// Returns an error if the server cannot be reached or initialisation fails.
+    pub async fn http(url: &str) -> Result<Self, McpError> {
+        Self::connect(McpTransport::Http { url: url.to_string(), headers: Vec::new() }).await
+    }
+
+    /// Connects over `transport` and performs the MCP initialisation handshake.
+    ///
+    /// # Errors
+    /// Returns an error if the transport cannot be opened or the server
+    /// rejects initialisation.
+    pub async fn connect(transport: McpTransport) -> Result<Self, McpError> {
+        let connection = match transport {
+            McpTransport::Stdio { command, args, env } => {
+                debug!("Spawning MCP server: {command} {args:?}");
+                let mut child = Command::new(&command)
+                    .args(&args)
+                    .envs(env)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::inherit())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .map_err(|e| McpError::new(format!("failed to spawn {command}: {e}")))?;
+                let stdin = child.stdin.take().ok_or_else(|| McpError::new("MCP server stdin unavailable"))?;
+                let stdout = child.stdout.take().ok_or_else(|| McpError::new("MCP server stdout unavailable"))?;
+                Connection::Stdio(Box::new(StdioProcess {
+                    _child: child,
+                    pipes: Mutex::new(StdioPipes { stdin, stdout: BufReader::new(stdout) }),
+                }))
+            }
+            McpTransport::Http { url, headers } => Connection::Http {
+                client: reqwest::Client::new(),
+                url,
+                headers,
+                session: std::sync::Mutex::new(None),
+            },
+        };
+        let inner = Arc::new(Inner { connection, next_id: AtomicU64::new(1) });
+
+        let init = inner.request("initialize", json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+        }), DEFAULT_MCP_REQUEST_TIMEOUT).await?;
+        inner.notify("notifications/initialized", json!({})).await?;
+
+        let client = McpClient {
+            inner,
+            timeout: DEFAULT_MCP_REQUEST_TIMEOUT,
+            server_name: init.pointer("/serverInfo/name").and_then(Value::as_str).unwrap_or("mcp").to_string(),
+            server_version: init.pointer("/serverInfo/version").and_then(Value::as_str).unwrap_or_default().to_string(),
+            instructions: init.get("instructions").and_then(Value::as_str).map(str::to_string),
+            capabilities: init.get("capabilities").cloned().unwrap_or_default(),
+        };
+        info!(
+            "Connected to MCP server {} {} (protocol {})",
+            client.server_name,
+            client.server_version,
+            init.get("protocolVersion").and_then(Value::as_str).unwrap_or("unknown")
+        );
+        Ok(client)
+    }
+
+    /// Sets the time allowed for each request made through this client
+    /// (default [`DEFAULT_MCP_REQUEST_TIMEOUT`]), over either transport. Clones
+    /// made afterwards, such as the proxies of [`McpClient::component`], keep it.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Name the server reported during initialisation.
+    pub fn server_name(&self) -> &str {
+        &self.server_name
+    }
+
+    /// Version the server reported during initialisation.
+    pub fn server_version(&self) -> &str {
+        &self.server_version
+    }
+
+    /// Usage instructions the server provided, if any.
+    pub fn instructions(&self) -> Option<&str> {
+        self.instructions.as_deref()
+    }
+
+    /// Returns `true` if the server announced the capability `name`
+    /// (`"tools"`, `"resources"`, `"prompts"`, …).
+    pub fn supports(&self, name: &str) -> bool {
+        self.capabilities.get(name).is_some()
+    }
+
+    /// Lists the server's tools.
+    ///
+    /// # Errors
+    /// Returns an error if the request fails.
+    pub async fn list_tools(&self) -> Result<Vec<McpTool>, McpError> {
+        self.list("tools/list", "tools").await
+    }
+
+    /// Lists the server's resources.
+    ///
+    /// # Errors
+    /// Returns an error if the request fails.
+    pub async fn list_resources(&self) -> Result<Vec<McpResource>, McpError> {
+        self.list("resources/list", "resources").await
+    }
+
+    /// Lists the server's prompt templates.
+    ///
+    /// # Errors
+    /// Returns an error if the request fails.
+    pub async fn list_prompts(&self) -> Result<Vec<McpPrompt>, McpError> {
+        self.list("prompts/list", "prompts").await
+    }
+
+    /// Calls the tool `name` with `arguments` and returns its text output.
+    ///
+    /// # Errors
+    /// Returns an error if the request fails or the tool reports an error.
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Result<String, McpError> {
+        let result = self.inner.request("tools/call", json!({ "name": name, "arguments": arguments }), self.timeout).await?;
+        let text = content_text(result.get("content").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default());
+        if result.get("isError").and_then(Value::as_bool).unwrap_or(false) {
+            return Err(McpError::new(text));
+        }
+        Ok(text)
+    }
+
+    /// Reads the resource at `uri` and returns its text.
+    ///
+    /// # Errors
+    /// Returns an error if the request fails.
+    pub async fn read_resource(&self, uri: &str) -> Result<String, McpError> {
+        let result = self.inner.request("resources/read", json!({ "uri": uri }), self.timeout).await?;
+        let contents = result.get("contents").and_then(Value::as_array).cloned().unwrap_or_default();
+        Ok(contents
+            .iter()
+            .map(|c| match c.get("text").and_then(Value::as_str) {
+                Some(text) => text.to_string(),
+                None => format!("[binary content: {}]", c.get("mimeType").and_then(Value::as_str).unwrap_or("unknown type")),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// Renders the prompt `name` with `arguments` and returns the text of its
+    /// messages, separated by blank lines.
+    ///
+    /// # Errors
+    /// Returns an error if the request fails.
+    pub async fn get_prompt(&self, name: &str, arguments: Map<String, Value>) -> Result<String, McpError> {
+        let result = self.inner.request("prompts/get", json!({ "name": name, "arguments": arguments }), self.timeout).await?;
+        let contents: Vec<Value> = result.get("messages")
+            .and_then(Value::as_array)
+            .map(|msgs| msgs.iter().filter_map(|m| m.get("content").cloned()).collect())
+            .unwrap_or_default();
+        Ok(content_text(&contents).replace('\n', "\n\n"))
+    }
+
+    /// Builds a [`Component`] proxying the server's tools, resources and
+    /// prompts.
+    ///
+    /// Tools keep the server's input schemas; arguments that are not a JSON
+    /// object are rejected as [`ToolError::InvalidArguments`] without calling
+    /// the server. Each resource keeps its URI and
+    /// MIME type and is also offered as a parameterless tool that reads it. Prompts take either a JSON object of
+    /// arguments or plain text for their first argument. Rendering blocks the
+    /// calling thread and needs a multi-threaded Tokio runtime; elsewhere a
+    /// prompt renders as an error message instead of panicking.
+    ///
+    /// # Errors
+    /// Returns an error if listing fails.
+    pub async fn component(&self) -> Result<Component, McpError> {
+        let tools = if self.supports("tools") { self.list_tools().await? } else { Vec::new() };
+        let resources = if self.supports("resources") { self.list_resources().await? } else { Vec::new() };
+        let prompts = if self.supports("prompts") { self.list_prompts().await? } else { Vec::new() };
+        debug!(
+            "MCP server {} offers {} tools, {} resources, {} prompts",
+            self.server_name, tools.len(), resources.len(), prompts.len()
+        );
+
+        let tools = tools.into_iter().map(|tool| {
+            let client = self.clone();
+            let name = tool.name.clone();
+            Tool::fallible(&tool.name, tool.description.as_deref().unwrap_or_default(), move |args: &String| {
+                let client = client.clone();
+                let name = name.clone();
+                // No arguments at all means an empty object; anything else must be a JSON object.
+                let arguments = if args.trim().is_empty() {
+                    Ok(json!({}))
+                } else {
+                    match serde_json::from_str::<Value>(args) {
+                        Ok(arguments @ Value::Object(_)) => Ok(arguments),
+                        Ok(other) => Err(format!("expected a JSON object, got {other}")),
+                        Err(e) => Err(e.to_string()),
+                    }
+                };
+                let call = arguments.map(|arguments| proxy(async move { client.call_tool(&name, arguments).await }));
+                async move {
+                    match call {
+                        Ok(call) => call.await.map_err(|e| ToolError::Failed(e.message)),
+                        Err(e) => Err(ToolError::invalid_arguments(e)),
+                    }
+                }
+            })
+            .schema(tool.input_schema)
+        }).collect();
+
+        let resources = resources.into_iter().map(|resource| {
+            let client = self.clone();
+            let uri = resource.uri.clone();
+            let description = match &resource.description {
+                Some(d) => format!("{d} (reads {})", resource.uri),
+                None => format!("Reads {}", resource.uri),
+            };
+            let proxied = Resource::with_uri(&resource.uri, &tool_name(&resource.name), &description, move |_, _| {
+                let client = client.clone();
+                let uri = uri.clone();
+                let read = proxy(async move { client.read_resource(&uri).await });
+                async move { read.await.unwrap_or_else(|e| format!("Error: {e}")) }
+            })
+            .as_tool(true);
+            match &resource.mime_type {
+                Some(mime_type) => proxied.mime_type(mime_type),
+                None => proxied,
+            }
+        }).collect();
+
+        let prompts = prompts.into_iter().map(|prompt| {
+            let client = self.clone();
+            let description = prompt.description.clone().unwrap_or_default();
+            let name = prompt.name.clone();
+            Prompt {
+                name: prompt.name.clone(),
+                description,
+                func: Arc::new(move |input: &str| {
+                    let client = client.clone();
+                    let name = name.clone();
+                    let arguments = prompt_arguments(&prompt, input);
+                    block_on(async move { client.get_prompt(&name, arguments).await })
+                        .unwrap_or_else(|e| format!("Error: {e}"))
+                }),
+            }
+        }).collect();
+
+        Ok(Component {
+            source: ComponentSource::Mcp(self.server_name.clone()),
+            tools,
+            resources,
+            prompts,
+            samplings: Vec::new(),
+        })
+    }
+
+    /// Collects all pages of a `*/list` request.
+    async fn list<T: serde::de::DeserializeOwned>(&self, method: &str, key: &str) -> Result<Vec<T>, McpError> {
+        let mut items = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let mut result = self.inner.request(method, params, self.timeout).await?;
+            let page: Vec<T> = serde_json::from_value(result.get_mut(key).map(Value::take).unwrap_or_else(|| json!([])))?;
+            items.extend(page);
+            cursor = result.get("nextCursor").and_then(Value::as_str).map(str::to_string);
+            if cursor.is_none() {
+                return Ok(items);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for McpClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("McpClient")
+            .field("server_name", &self.server_name)
+            .field("server_version", &self.server_version)
+            .finish()
+    }
+}
+
+impl Inner {
+    /// Sends a request and waits up to `timeout` for its response.
+    async fn request(&self, method: &str, params: Value, timeout: Duration) -> Result<Value, McpError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        debug!("MCP request {id}: {method}");
+        // A late stdio response is skipped by the next request, which only
+        // accepts its own id.
+        tokio::time::timeout(timeout, self.send(Some(id), rpc_request(Some(id), method, params)))
+            .await
+            .unwrap_or_else(|_| Err(McpError::new(format!("MCP request {method} timed out after {timeout:?}"))))
+    }
+
+    /// Sends a notification.
+    async fn notify(&self, method: &str, params: Value) -> Result<(), McpError> {
+        self.send(None, rpc_request(None, method, params)).await.map(|_| ())
+    }
+
+    async fn send(&self, id: Option<u64>, msg: Value) -> Result<Value, McpError> {
+        match &self.connection {
+            Connection::Stdio(process) => {
+                let mut pipes = process.pipes.lock().await;
+                write_line(&mut pipes.stdin, &msg).await?;
+                let Some(id) = id else {
+                    return Ok(Value::Null);
+                };
+                loop {
+                    let mut line = String::new();
+                    if pipes.stdout.read_line(&mut line).await? == 0 {
+                        return Err(McpError::new("MCP server closed the connection"));
+                    }
+                    let Ok(msg) = serde_json::from_str::<Value>(line.trim()) else {
+                        debug!("Ignoring non-JSON output from MCP server: {}", line.trim());
+                        continue;
+                    };
+                    if msg.get("method").is_none() && msg.get("id") == Some(&json!(id)) {
+                        return rpc_result(msg);
+                    }
+                    if let Some(reply) = reply_to_server(&msg) {
+                        write_line(&mut pipes.stdin, &reply).await?;
+                    }
+                }
+            }
+            Connection::Http { client, url, headers, session } => {
+                let mut request = client.post(url)
+                    .header(reqwest::header::ACCEPT, "application/json, text/event-stream")
+                    .header("MCP-Protocol-Version", PROTOCOL_VERSION)
+                    .json(&msg);
+                for (name, value) in headers {
+                    request = request.header(name.as_str(), value.as_str());
+                }
+                let current = session.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                if let Some(current) = current {
+                    request = request.header("Mcp-Session-Id", current);
+                }
+                let response = request.send().await?;
+                if let Some(id) = response.headers().get("mcp-session-id").and_then(|v| v.to_str().ok()) {
+                    *session.lock().unwrap_or_else(|e| e.into_inner()) = Some(id.to_string());
+                }
+                let status = response.status();
+                if !status.is_success() {
+                    return Err(McpError::new(format!("HTTP {status}: {}", response.text().await.unwrap_or_default())));
+                }
+                let Some(id) = id else {
+                    return Ok(Value::Null);
+                };
+                let event_stream = response.headers()
+                    .get(reqwest::header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .is_some_and(|v| v.starts_with("text/event-stream"));
+                let body = response.text().await?;
+                if !event_stream {
+                    return rpc_result(serde_json::from_str(&body)?);
+                }
+                for data in sse_data(&body) {
+                    let msg: Value = serde_json::from_str(&data)?;
+                    if msg.get("method").is_none() && msg.get("id") == Some(&json!(id)) {
+                        return rpc_result(msg);
+                    }
+                }
+                Err(McpError::new("event stream ended without a response"))
+            }
+        }
+    }
+}
+
+/// Writes one newline-delimited JSON message.
+async fn write_line(stdin: &mut ChildStdin, msg: &Value) -> Result<(), McpError> {
+    let mut line = msg.to_string();
+    line.push('\n');
+    stdin.write_all(line.as_bytes()).await?;
+    stdin.flush().await?;
+    Ok(())
+}
+
+/// The reply to a request the server sent to us, if `msg` is one. Only
+/// `ping` is supported; anything else is answered with "method not found".
+fn reply_to_server(msg: &Value) -> Option<Value> {
+    let id = msg.get("id")?;
+    let method = msg.get("method")?.as_str()?;
+    if method == "ping" {
+        return Some(json!({ "jsonrpc": "2.0", "id": id, "result": {} }));
+    }
+    warn!("Unsupported request from MCP server: {method}");
+    Some(json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32601, "message": format!("Method not found: {method}") } }))
+}
+
+/// The `data` payloads of a server-sent event stream.
+fn sse_data(body: &str) -> Vec<String> {
+    body.replace("\r\n", "\n")
+        .split("\n\n")
+        .filter_map(|event| {
+            let data: Vec<&str> = event.lines()
+                .filter_map(|l| l.strip_prefix("data:"))
+                .map(|d| d.strip_prefix(' ').unwrap_or(d))
+                .collect();
+            (!data.is_empty()).then(|| data.join("\n"))
+        })
+        .collect()
+}
+
+/// Runs an MCP call on the Tokio runtime. Spawning keeps the returned
+/// future `Sync`, as [`Tool`] requires.
+fn proxy<F>(call: F) -> impl Future<Output = Result<String, McpError>> + Send + Sync + 'static
+where
+    F: Future<Output = Result<String, McpError>> + Send + 'static,
+{
+    let handle = tokio::spawn(call);
+    async move {
+        handle.await.unwrap_or_else(|e| Err(McpError::new(e.to_string())))
+    }
+}
+
+/// Runs `call` to completion from synchronous code.
+///
+/// # Errors
+/// Returns an error outside a multi-threaded Tokio runtime.
+fn block_on<F>(call: F) -> Result<String, McpError>
+where
+    F: Future<Output = Result<String, McpError>> + Send + 'static,
+{
+    let handle = tokio::runtime::Handle::try_current()
+        .map_err(|_| McpError::new("MCP prompts must be rendered inside a Tokio runtime"))?;
+    if handle.runtime_flavor() != tokio::runtime::RuntimeFlavor::MultiThread {
+        return Err(McpError::new("MCP prompts require a multi-threaded Tokio runtime"));
+    }
+    tokio::task::block_in_place(|| handle.block_on(call))
+}
+
+/// Prompt arguments from prompt input: a JSON object is used as is, other
+/// text fills the prompt's first argument.
+fn prompt_arguments(prompt: &McpPrompt, input: &str) -> Map<String, Value> {
+    if let Ok(Value::Object(arguments)) = serde_json::from_str(input) {
+        return arguments;
+    }
+    let mut arguments = Map::new();
+    if let Some(first) = prompt.arguments.first() {
+        arguments.insert(first.name.clone(), Value::String(input.to_string()));
+    }
+    arguments
+}
+
+/// A tool-safe name: ASCII letters, digits, `_` and `-`.
+fn tool_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+    use crate::{ComponentRegistry, McpServer};
+
+    async fn serve(shutdown: &CancellationToken) -> String {
+        let mut registry = ComponentRegistry::new();
+        registry.tool_timeout = None;
+        registry.register(Component {
+            tools: vec![
+                Tool::new("shout", "Upper-cases the text", |s: &String| {
+                    let s = s.to_uppercase();
+                    async move { s }
+                }),
+                Tool::new("stall", "Never answers in time", |_: &String| async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    String::new()
+                }),
+            ],
+            prompts: vec![Prompt::new("greet", "Greets someone", |name| format!("Hello, {name}!"))],
+            ..Default::default()
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/mcp", listener.local_addr().unwrap());
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move { McpServer::new(registry).serve_http(listener, shutdown).await });
+        url
+    }
+
+    #[tokio::test]
+    async fn proxied_tools_take_only_json_objects() {
+        let shutdown = CancellationToken::new();
+        let client = McpClient::http(&serve(&shutdown).await).await.unwrap();
+        let component = client.component().await.unwrap();
+        let shout = component.tools.iter().find(|t| t.name == "shout").unwrap();
+
+        assert_eq!(shout.execute(&r#"{"input": "hi"}"#.to_string()).await.unwrap().text, "HI");
+        for args in ["[1]", "\"hi\"", "not json"] {
+            assert!(matches!(shout.execute(&args.to_string()).await, Err(ToolError::InvalidArguments(_))), "{args}");
+        }
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn requests_time_out() {
+        let shutdown = CancellationToken::new();
+        let client = McpClient::http(&serve(&shutdown).await).await.unwrap().request_timeout(Duration::from_millis(50));
+        let err = tokio::time::timeout(Duration::from_secs(2), client.call_tool("stall", json!({ "input": "x" })))
+            .await
+            .expect("the request timeout was not applied")
+            .unwrap_err();
+        assert!(err.message.contains("timed out"), "{err}");
+        shutdown.cancel();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn prompts_render_on_a_multi_threaded_runtime() {
+        let shutdown = CancellationToken::new();
+        let component = McpClient::http(&serve(&shutdown).await).await.unwrap().component().await.unwrap();
+        assert_eq!((component.prompts[0].func)("Ada"), "Hello, Ada!");
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn prompts_report_a_current_thread_runtime_instead_of_panicking() {
+        let shutdown = CancellationToken::new();
+        let component = McpClient::http(&serve(&shutdown).await).await.unwrap().component().await.unwrap();
+        assert_eq!((component.prompts[0].func)("Ada"), "Error: MCP error: MCP prompts require a multi-threaded Tokio runtime");
+        shutdown.cancel();
+    }
+}
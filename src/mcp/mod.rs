@@ -0,0 +1,180 @@
+//! Model Context Protocol (MCP) support.
+//!
+//! [`McpServer`] exposes a [`ComponentRegistry`](crate::ComponentRegistry) to
+//! other MCP clients over stdio or HTTP.
+//!
+//! [`McpClient`] connects to an MCP server over stdio (a spawned subprocess)
+//! or streamable HTTP, lists its tools, resources and prompts, and turns them
+//! into a [`Component`](crate::Component) whose entries proxy to the server:
+//!
+//! ```rust,ignore
+//! let files = McpClient::stdio("npx", &["-y", "@modelcontextprotocol/server-filesystem", "/srv/docs"]).await?;
+//! let search = McpClient::http("http://localhost:8000/mcp").await?;
+//!
+//! let mut registry = ComponentRegistry::new();
+//! registry.register(files.component().await?);
+//! registry.register(search.component().await?);
+//! query.components = Some(registry);
+//! ```
+//!
+//! Requires the `mcp` feature.
+
+pub(crate) mod client;
+pub(crate) mod server;
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+pub use client::{McpClient, McpTransport, DEFAULT_MCP_REQUEST_TIMEOUT};
+pub use server::McpServer;
+
+/// MCP protocol revision spoken by this crate.
+pub(crate) const PROTOCOL_VERSION: &str = "2025-03-26";
+
+/// An MCP or transport error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct McpError {
+    /// JSON-RPC error code, if the server returned one.
+    pub code: Option<i64>,
+    /// The error message.
+    pub message: String,
+}
+
+impl McpError {
+    pub(crate) fn new(message: impl Into<String>) -> Self {
+        McpError { code: None, message: message.into() }
+    }
+}
+
+impl fmt::Display for McpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "MCP error {code}: {}", self.message),
+            None => write!(f, "MCP error: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for McpError {}
+
+impl From<std::io::Error> for McpError {
+    fn from(e: std::io::Error) -> Self {
+        McpError::new(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for McpError {
+    fn from(e: serde_json::Error) -> Self {
+        McpError::new(format!("invalid JSON: {e}"))
+    }
+}
+
+impl From<reqwest::Error> for McpError {
+    fn from(e: reqwest::Error) -> Self {
+        McpError::new(e.to_string())
+    }
+}
+
+/// A tool offered by an MCP server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpTool {
+    /// Tool name.
+    pub name: String,
+    /// What the tool does.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON Schema of the tool's arguments.
+    #[serde(default = "empty_object_schema")]
+    pub input_schema: Value,
+}
+
+/// A resource offered by an MCP server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpResource {
+    /// Resource URI, e.g. `file:///srv/docs/readme.md`.
+    pub uri: String,
+    /// Resource name.
+    pub name: String,
+    /// What the resource contains.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// MIME type of the content, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+/// A prompt template offered by an MCP server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct McpPrompt {
+    /// Prompt name.
+    pub name: String,
+    /// What the prompt is for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Arguments the template accepts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arguments: Vec<McpPromptArgument>,
+}
+
+/// An argument of an [`McpPrompt`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct McpPromptArgument {
+    /// Argument name.
+    pub name: String,
+    /// What the argument means.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Whether the argument must be given.
+    #[serde(default)]
+    pub required: bool,
+}
+
+fn empty_object_schema() -> Value {
+    json!({ "type": "object", "properties": {} })
+}
+
+/// Builds a JSON-RPC request, or a notification when `id` is `None`.
+pub(crate) fn rpc_request(id: Option<u64>, method: &str, params: Value) -> Value {
+    let mut msg = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+    if let Some(id) = id {
+        msg["id"] = json!(id);
+    }
+    msg
+}
+
+/// Extracts the result of a JSON-RPC response.
+///
+/// # Errors
+/// Returns the error carried by the response, or an error if it has neither
+/// a result nor an error.
+pub(crate) fn rpc_result(mut msg: Value) -> Result<Value, McpError> {
+    if let Some(error) = msg.get("error") {
+        return Err(McpError {
+            code: error.get("code").and_then(Value::as_i64),
+            message: error.get("message").and_then(Value::as_str).unwrap_or("unknown error").to_string(),
+        });
+    }
+    msg.get_mut("result").map(Value::take).ok_or_else(|| McpError::new("response has neither result nor error"))
+}
+
+/// Joins the text of MCP content items (tool results, prompt messages).
+/// Non-text items are summarised by their type and MIME type.
+pub(crate) fn content_text(content: &[Value]) -> String {
+    content
+        .iter()
+        .map(|item| match item.get("type").and_then(Value::as_str) {
+            Some("text") => item.get("text").and_then(Value::as_str).unwrap_or_default().to_string(),
+            Some("resource") => item.pointer("/resource/text").and_then(Value::as_str).unwrap_or_default().to_string(),
+            other => format!(
+                "[{} content: {}]",
+                other.unwrap_or("unknown"),
+                item.get("mimeType").and_then(Value::as_str).unwrap_or("unknown type")
+            ),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
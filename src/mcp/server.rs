@@ -0,0 +1,555 @@
+//! MCP server exposing a [`ComponentRegistry`] over stdio or HTTP.
+
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, info, warn};
+use serde_json::{json, Map, Value};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tokio_util::sync::CancellationToken;
+
+use super::{McpError, PROTOCOL_VERSION};
+use crate::{http, ComponentRegistry};
+
+/// Protocol revisions the server accepts from clients.
+const SUPPORTED_VERSIONS: &[&str] = &["2024-11-05", "2025-03-26", "2025-06-18"];
+
+/// Most HTTP sessions kept at once; opening another drops the least recently
+/// used one.
+const MAX_SESSIONS: usize = 1024;
+
+/// HTTP sessions unused for this long are dropped.
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Serves the tools, resources and prompts of a [`ComponentRegistry`] to MCP
+/// clients.
+///
+/// - Tools keep their [`Tool::schema`](crate::Tool::schema); tools without
+///   one take a single string `param`.
+/// - Resources are exposed under their URI (resources without one as
+///   `erh://resource/<name>`); URI templates are listed as resource templates.
+/// - Prompts take one optional `input` argument that is passed to the
+///   prompt function; other arguments are passed as a JSON object.
+///
+/// ```rust,ignore
+/// let server = McpServer::new(registry).name("inventory", "1.0");
+/// // As a subprocess of an MCP client:
+/// server.serve_stdio().await?;
+/// // Or over HTTP, on every path of the listener:
+/// server.serve_http(TcpListener::bind("127.0.0.1:8000").await?, CancellationToken::new()).await?;
+/// ```
+///
+/// When serving stdio, log output must not go to stdout.
+#[derive(Clone)]
+pub struct McpServer {
+    registry: ComponentRegistry,
+    name: String,
+    version: String,
+    instructions: Option<String>,
+    allowed_origins: Vec<String>,
+    sessions: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl McpServer {
+    /// Creates a server for `registry`, announcing itself as this crate.
+    pub fn new(registry: ComponentRegistry) -> Self {
+        McpServer {
+            registry,
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            instructions: None,
+            allowed_origins: Vec::new(),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Sets the server name and version reported to clients.
+    pub fn name(mut self, name: &str, version: &str) -> Self {
+        self.name = name.to_string();
+        self.version = version.to_string();
+        self
+    }
+
+    /// Sets usage instructions reported to clients.
+    pub fn instructions(mut self, instructions: &str) -> Self {
+        self.instructions = Some(instructions.to_string());
+        self
+    }
+
+    /// Allows HTTP requests from browser pages served at `origin` (e.g.
+    /// `https://app.example.com`). Requests without an `Origin` header and
+    /// from `localhost`, `127.0.0.1` or `[::1]` are always allowed.
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        self.allowed_origins.push(origin.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Handles one JSON-RPC message (or batch) and returns the response, or
+    /// `None` for notifications. Useful for embedding the server in another
+    /// transport.
+    pub async fn handle(&self, msg: Value) -> Option<Value> {
+        match msg {
+            Value::Array(batch) => {
+                let mut responses = Vec::new();
+                for msg in batch {
+                    responses.extend(self.handle_one(msg).await);
+                }
+                (!responses.is_empty()).then_some(Value::Array(responses))
+            }
+            msg => self.handle_one(msg).await,
+        }
+    }
+
+    /// Serves newline-delimited JSON-RPC on this process' stdin and stdout
+    /// until stdin is closed.
+    ///
+    /// # Errors
+    /// Returns an error if reading or writing fails.
+    pub async fn serve_stdio(&self) -> Result<(), McpError> {
+        info!("Serving MCP server {} on stdio", self.name);
+        self.serve(BufReader::new(tokio::io::stdin()), tokio::io::stdout()).await
+    }
+
+    /// Serves newline-delimited JSON-RPC read from `reader` and written to
+    /// `writer` until `reader` is exhausted.
+    ///
+    /// # Errors
+    /// Returns an error if reading or writing fails.
+    pub async fn serve<R, W>(&self, mut reader: R, mut writer: W) -> Result<(), McpError>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        debug!("Serving MCP server {}", self.name);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).await? == 0 {
+                return Ok(());
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str(line.trim()) {
+                Ok(msg) => self.handle(msg).await,
+                Err(e) => Some(parse_error(&e)),
+            };
+            if let Some(response) = response {
+                let mut out = response.to_string();
+                out.push('\n');
+                writer.write_all(out.as_bytes()).await?;
+                writer.flush().await?;
+            }
+        }
+    }
+
+    /// Serves the streamable HTTP transport on `listener` until `shutdown`
+    /// is cancelled. Every path is accepted; responses are plain JSON (no
+    /// event streams) and sessions are tracked with `Mcp-Session-Id`.
+    ///
+    /// Requests from other origins than those allowed with
+    /// [`allow_origin`](Self::allow_origin) are refused with 403, so that
+    /// web pages cannot reach a local server through DNS rebinding. Headers
+    /// are limited to 16 KiB and bodies to 4 MiB. At most 1024 sessions are
+    /// kept, each for an hour after its last request.
+    ///
+    /// # Errors
+    /// Currently always returns `Ok`; accept errors are logged.
+    pub async fn serve_http(&self, listener: TcpListener, shutdown: CancellationToken) -> Result<(), McpError> {
+        info!("Serving MCP server {} on http://{}", self.name, listener.local_addr()?);
+        loop {
+            let accept = Box::pin(listener.accept());
+            let stop = Box::pin(shutdown.cancelled());
+            let (stream, _) = match futures::future::select(accept, stop).await {
+                futures::future::Either::Left((Ok(conn), _)) => conn,
+                futures::future::Either::Left((Err(e), _)) => {
+                    warn!("MCP server accept failed: {e}");
+                    continue;
+                }
+                futures::future::Either::Right(_) => return Ok(()),
+            };
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.handle_http(stream).await {
+                    debug!("MCP connection error: {e}");
+                }
+            });
+        }
+    }
+
+    /// Answers one HTTP request.
+    async fn handle_http(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let Some(request) = http::read_request(&mut stream).await? else {
+            return Ok(());
+        };
+        if let Some(origin) = request.header("Origin")
+            && !self.origin_allowed(origin)
+        {
+            warn!("MCP server refused a request from origin {origin}");
+            return http::write_response(&mut stream, 403, "application/json", &[], &error_body("Origin not allowed")).await;
+        }
+        let session = request.header("Mcp-Session-Id").map(str::to_string);
+        match request.method.as_str() {
+            "POST" => {}
+            "DELETE" => {
+                if let Some(session) = &session {
+                    self.lock_sessions().remove(session);
+                }
+                return http::write_response(&mut stream, 200, "application/json", &[], "").await;
+            }
+            _ => return http::write_response(&mut stream, 405, "application/json", &[("Allow", "POST, DELETE")], "").await,
+        }
+
+        let msg: Value = match serde_json::from_slice(&request.body) {
+            Ok(msg) => msg,
+            Err(e) => return http::write_response(&mut stream, 400, "application/json", &[], &parse_error(&e).to_string()).await,
+        };
+        let session = if msg.get("method").and_then(Value::as_str) == Some("initialize") {
+            self.open_session()
+        } else {
+            match session {
+                Some(id) if self.touch_session(&id) => id,
+                Some(_) => return http::write_response(&mut stream, 404, "application/json", &[], &error_body("Unknown session")).await,
+                None => return http::write_response(&mut stream, 400, "application/json", &[], &error_body("Missing Mcp-Session-Id header")).await,
+            }
+        };
+        let headers = [("Mcp-Session-Id", session.as_str())];
+        match self.handle(msg).await {
+            Some(response) => http::write_response(&mut stream, 200, "application/json", &headers, &response.to_string()).await,
+            None => http::write_response(&mut stream, 202, "application/json", &headers, "").await,
+        }
+    }
+
+    /// Handles a single message; responses from clients and notifications
+    /// yield `None`.
+    async fn handle_one(&self, msg: Value) -> Option<Value> {
+        let id = msg.get("id").cloned();
+        let Some(method) = msg.get("method").and_then(Value::as_str) else {
+            return id.map(|id| error_response(id, -32600, "Invalid request"));
+        };
+        let Some(id) = id else {
+            debug!("MCP notification: {method}");
+            return None;
+        };
+        debug!("MCP request {id}: {method}");
+        let params = msg.get("params").cloned().unwrap_or_else(|| json!({}));
+        Some(match self.dispatch(method, params).await {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => error_response(id, e.code.unwrap_or(-32603), &e.message),
+        })
+    }
+
+    async fn dispatch(&self, method: &str, params: Value) -> Result<Value, McpError> {
+        match method {
+            "initialize" => {
+                let requested = params.get("protocolVersion").and_then(Value::as_str).unwrap_or(PROTOCOL_VERSION);
+                let version = if SUPPORTED_VERSIONS.contains(&requested) { requested } else { PROTOCOL_VERSION };
+                info!(
+                    "MCP client {} connected (protocol {version})",
+                    params.pointer("/clientInfo/name").and_then(Value::as_str).unwrap_or("unknown")
+                );
+                let mut result = json!({
+                    "protocolVersion": version,
+                    "capabilities": { "tools": {}, "resources": {}, "prompts": {} },
+                    "serverInfo": { "name": self.name, "version": self.version },
+                });
+                if let Some(instructions) = &self.instructions {
+                    result["instructions"] = json!(instructions);
+                }
+                Ok(result)
+            }
+            "ping" => Ok(json!({})),
+            "tools/list" => {
+                let tools: Vec<Value> = self.registry.tool_infos().into_iter()
+                    .map(|info| json!({
+                        "name": info.function.name,
+                        "description": info.function.description,
+                        "inputSchema": info.function.parameters,
+                    }))
+                    .collect();
+                Ok(json!({ "tools": tools }))
+            }
+            "tools/call" => {
+                let name = required_str(&params, "name")?;
+                let arguments = params.get("arguments").cloned().unwrap_or_else(|| json!({}));
+                // Tools are run directly so that failures set `isError`; both
+                // paths apply the registry's default tool timeout.
+                let result = match self.registry.tool(name) {
+                    Some(tool) => tool.run(arguments).await.map(|output| output.text).map_err(|e| e.model_message(name)),
+                    None => self.registry.call_tool(name, arguments).await
+                        .ok_or_else(|| invalid_params(format!("Unknown tool: {name}")))?
+                        .map_err(|e| e.to_string()),
+                };
+                Ok(match result {
+                    Ok(text) => json!({ "content": [{ "type": "text", "text": text }], "isError": false }),
+                    Err(text) => json!({ "content": [{ "type": "text", "text": text }], "isError": true }),
+                })
+            }
+            "resources/list" => {
+                let resources: Vec<Value> = self.registry.resources()
+                    .filter(|resource| !resource.is_template())
+                    .map(|resource| json!({
+                        "uri": resource.address(),
+                        "name": resource.name,
+                        "description": resource.description,
+                        "mimeType": resource.mime_type.as_deref().unwrap_or("text/plain"),
+                    }))
+                    .collect();
+                Ok(json!({ "resources": resources }))
+            }
+            "resources/templates/list" => {
+                let templates: Vec<Value> = self.registry.resources()
+                    .filter(|resource| resource.is_template())
+                    .map(|resource| json!({
+                        "uriTemplate": resource.address(),
+                        "name": resource.name,
+                        "description": resource.description,
+                        "mimeType": resource.mime_type.as_deref().unwrap_or("text/plain"),
+                    }))
+                    .collect();
+                Ok(json!({ "resourceTemplates": templates }))
+            }
+            "resources/read" => {
+                let uri = required_str(&params, "uri")?;
+                let contents = self.registry.read_resource(uri).await
+                    .ok_or_else(|| McpError { code: Some(-32002), message: format!("Resource not found: {uri}") })?;
+                Ok(json!({ "contents": [{ "uri": contents.uri, "mimeType": contents.mime_type, "text": contents.text }] }))
+            }
+            "prompts/list" => {
+                let prompts: Vec<Value> = self.registry.prompts()
+                    .map(|prompt| json!({
+                        "name": prompt.name,
+                        "description": prompt.description,
+                        "arguments": [{ "name": "input", "description": "Text passed to the prompt template.", "required": false }],
+                    }))
+                    .collect();
+                Ok(json!({ "prompts": prompts }))
+            }
+            "prompts/get" => {
+                let name = required_str(&params, "name")?;
+                let prompt = self.registry.prompt(name)
+                    .ok_or_else(|| invalid_params(format!("Unknown prompt: {name}")))?;
+                let arguments = params.get("arguments").and_then(Value::as_object).cloned().unwrap_or_default();
+                let text = prompt.render(&prompt_input(arguments));
+                Ok(json!({
+                    "description": prompt.description,
+                    "messages": [{ "role": "user", "content": { "type": "text", "text": text } }],
+                }))
+            }
+            other => Err(McpError { code: Some(-32601), message: format!("Method not found: {other}") }),
+        }
+    }
+
+    /// Whether a request with `Origin: origin` may be served.
+    fn origin_allowed(&self, origin: &str) -> bool {
+        let origin = origin.trim_end_matches('/');
+        matches!(origin_host(origin), "localhost" | "127.0.0.1" | "::1")
+            || self.allowed_origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// Starts a new session, dropping expired ones and, at the limit, the
+    /// least recently used one.
+    fn open_session(&self) -> String {
+        let mut sessions = self.lock_sessions();
+        sessions.retain(|_, used| used.elapsed() < SESSION_IDLE_TIMEOUT);
+        if sessions.len() >= MAX_SESSIONS
+            && let Some(oldest) = sessions.iter().min_by_key(|(_, used)| **used).map(|(id, _)| id.clone())
+        {
+            sessions.remove(&oldest);
+        }
+        let id = new_session_id();
+        sessions.insert(id.clone(), Instant::now());
+        id
+    }
+
+    /// Marks session `id` as used; returns `false` if it is unknown or expired.
+    fn touch_session(&self, id: &str) -> bool {
+        let mut sessions = self.lock_sessions();
+        match sessions.get_mut(id) {
+            Some(used) if used.elapsed() < SESSION_IDLE_TIMEOUT => {
+                *used = Instant::now();
+                true
+            }
+            Some(_) => {
+                sessions.remove(id);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn lock_sessions(&self) -> std::sync::MutexGuard<'_, HashMap<String, Instant>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl std::fmt::Debug for McpServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("McpServer")
+            .field("name", &self.name)
+            .field("version", &self.version)
+            .field("components", &self.registry.components.len())
+            .finish()
+    }
+}
+
+/// Prompt function input from `prompts/get` arguments: the `input` argument
+/// if it is the only one, otherwise all arguments as a JSON object.
+fn prompt_input(arguments: Map<String, Value>) -> String {
+    match arguments.get("input").and_then(Value::as_str) {
+        Some(input) if arguments.len() == 1 => input.to_string(),
+        _ if arguments.is_empty() => String::new(),
+        _ => Value::Object(arguments).to_string(),
+    }
+}
+
+/// The host of an `Origin` header value such as `http://[::1]:8000`.
+fn origin_host(origin: &str) -> &str {
+    let authority = origin.split_once("://").map_or(origin, |(_, rest)| rest);
+    let authority = authority.split('/').next().unwrap_or_default();
+    match authority.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => authority.split(':').next().unwrap_or_default(),
+    }
+}
+
+fn required_str<'a>(params: &'a Value, key: &str) -> Result<&'a str, McpError> {
+    params.get(key).and_then(Value::as_str).ok_or_else(|| invalid_params(format!("Missing parameter: {key}")))
+}
+
+fn invalid_params(message: String) -> McpError {
+    McpError { code: Some(-32602), message }
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn parse_error(e: &serde_json::Error) -> Value {
+    error_response(Value::Null, -32700, &format!("Parse error: {e}"))
+}
+
+fn error_body(message: &str) -> String {
+    error_response(Value::Null, -32600, message).to_string()
+}
+
+/// A hard-to-guess session id from the process' random hasher keys and the
+/// current time.
+fn new_session_id() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+    let mut a = std::collections::hash_map::RandomState::new().build_hasher();
+    a.write_u128(nanos);
+    let mut b = std::collections::hash_map::RandomState::new().build_hasher();
+    b.write_u64(a.finish());
+    format!("{:016x}{:016x}", a.finish(), b.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Component, Resource, Tool};
+
+    fn server() -> McpServer {
+        let mut registry = ComponentRegistry::new();
+        registry.register(Component {
+            tools: vec![Tool::new("shout", "Upper-cases the text", |s: &String| {
+                let s = s.to_uppercase();
+                async move { s }
+            })],
+            ..Default::default()
+        });
+        McpServer::new(registry).name("inventory", "1.0").instructions("Ask about stock.")
+    }
+
+    fn request(id: i64, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    #[tokio::test]
+    async fn initializes_with_a_supported_protocol_version() {
+        let response = server().handle(request(1, "initialize", json!({ "protocolVersion": "2025-03-26" }))).await.unwrap();
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["protocolVersion"], "2025-03-26");
+        assert_eq!(response["result"]["serverInfo"], json!({ "name": "inventory", "version": "1.0" }));
+        assert_eq!(response["result"]["instructions"], "Ask about stock.");
+
+        let response = server().handle(request(2, "initialize", json!({ "protocolVersion": "1999-01-01" }))).await.unwrap();
+        assert_eq!(response["result"]["protocolVersion"], PROTOCOL_VERSION);
+    }
+
+    #[tokio::test]
+    async fn lists_and_calls_tools() {
+        let server = server();
+        let response = server.handle(request(1, "tools/list", json!({}))).await.unwrap();
+        let tools = response["result"]["tools"].as_array().unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0]["name"], "shout");
+        assert_eq!(tools[0]["inputSchema"]["required"][0], "param");
+
+        let response = server.handle(request(2, "tools/call", json!({ "name": "shout", "arguments": { "param": "hi" } }))).await.unwrap();
+        assert_eq!(response["result"], json!({ "content": [{ "type": "text", "text": "HI" }], "isError": false }));
+
+        let response = server.handle(request(3, "tools/call", json!({ "name": "whisper" }))).await.unwrap();
+        assert_eq!(response["error"]["code"], -32602);
+    }
+
+    #[tokio::test]
+    async fn calls_apply_the_registry_timeout_and_reach_resources() {
+        let mut registry = ComponentRegistry::new();
+        registry.tool_timeout = Some(Duration::from_millis(20));
+        registry.register(Component {
+            tools: vec![Tool::new("stall", "Never answers in time", |_: &String| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                String::new()
+            })],
+            resources: vec![Resource::new("stock", "Current stock level", |_: &String| async { "12".to_string() })],
+            ..Default::default()
+        });
+        let server = McpServer::new(registry);
+
+        let response = server.handle(request(1, "tools/list", json!({}))).await.unwrap();
+        let names: Vec<&str> = response["result"]["tools"].as_array().unwrap().iter().map(|t| t["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["stall", "stock"]);
+
+        let response = tokio::time::timeout(Duration::from_secs(1), server.handle(request(2, "tools/call", json!({ "name": "stall", "arguments": { "input": "x" } }))))
+            .await
+            .expect("the registry timeout was not applied")
+            .unwrap();
+        assert_eq!(response["result"]["isError"], true);
+        assert!(response["result"]["content"][0]["text"].as_str().unwrap().contains("timed out"));
+
+        let response = server.handle(request(3, "tools/call", json!({ "name": "stock", "arguments": { "input": "" } }))).await.unwrap();
+        assert_eq!(response["result"], json!({ "content": [{ "type": "text", "text": "12" }], "isError": false }));
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_methods_and_ignores_notifications() {
+        let server = server();
+        let response = server.handle(request(1, "tools/destroy", json!({}))).await.unwrap();
+        assert_eq!(response["error"]["code"], -32601);
+        assert_eq!(server.handle(json!({ "jsonrpc": "2.0", "method": "notifications/initialized" })).await, None);
+
+        let batch = json!([request(2, "ping", json!({})), { "jsonrpc": "2.0", "method": "notifications/cancelled" }]);
+        let response = server.handle(batch).await.unwrap();
+        assert_eq!(response, json!([{ "jsonrpc": "2.0", "id": 2, "result": {} }]));
+    }
+
+    #[test]
+    fn allows_local_and_listed_origins() {
+        let server = server().allow_origin("https://app.example.com/");
+        assert!(server.origin_allowed("http://localhost:3000"));
+        assert!(server.origin_allowed("http://127.0.0.1"));
+        assert!(server.origin_allowed("http://[::1]:8000"));
+        assert!(server.origin_allowed("https://app.example.com"));
+        assert!(!server.origin_allowed("https://evil.example.com"));
+        assert!(!server.origin_allowed("http://localhost.evil.example.com"));
+        assert!(!server.origin_allowed("null"));
+    }
+}
@@ -0,0 +1,208 @@
+//! Prometheus-style metrics for LLM traffic.
+//!
+//! Every [`Query`](crate::Query), tool invocation and history write updates the
+//! process-wide [`Metrics::global`] registry. Serve [`Metrics::render`] from an
+//! existing `/metrics` endpoint to expose it in the Prometheus text format:
+//!
+//! ```rust,ignore
+//! async fn metrics() -> String {
+//!     erh_llm::Metrics::global().render()
+//! }
+//! ```
+//!
+//! Exported series:
+//!
+//! | Metric | Type | Labels |
+//! |--------|------|--------|
+//! | `erh_llm_requests_total` | counter | `backend`, `model`, `status` |
+//! | `erh_llm_errors_total` | counter | `backend`, `kind` |
+//! | `erh_llm_request_duration_seconds` | histogram | `backend`, `model` |
+//! | `erh_llm_tokens_total` | counter | `backend`, `model`, `direction` (`prompt` / `completion`) |
+//! | `erh_llm_tool_calls_total` | counter | `tool`, `status` |
+//! | `erh_llm_history_store_duration_seconds` | histogram | `store`, `status` |
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use crate::{ErrorKind, Usage};
+
+/// Upper bounds (in seconds) of the latency histogram buckets.
+const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// Name, help text and type of every metric, in rendering order.
+const METRICS: &[(&str, &str, &str)] = &[
+    ("erh_llm_requests_total", "Backend chat requests by outcome.", "counter"),
+    ("erh_llm_errors_total", "Failed backend chat requests by error kind.", "counter"),
+    ("erh_llm_request_duration_seconds", "Backend chat latency including retries.", "histogram"),
+    ("erh_llm_tokens_total", "Tokens sent to and generated by backends.", "counter"),
+    ("erh_llm_tool_calls_total", "Tool invocations by outcome.", "counter"),
+    ("erh_llm_history_store_duration_seconds", "Latency of history store operations.", "histogram"),
+];
+
+/// Label set of a single series, as `(name, value)` pairs.
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Cumulative counts per entry in [`LATENCY_BUCKETS`].
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; LATENCY_BUCKETS.len()];
+        }
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    counters: BTreeMap<(&'static str, Labels), u64>,
+    histograms: BTreeMap<(&'static str, Labels), Histogram>,
+}
+
+/// Thread-safe registry of counters and histograms.
+///
+/// Use [`Metrics::global`] for the instance updated by the crate; separate
+/// instances can be created with [`Metrics::new`] but are not updated
+/// automatically.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    registry: Mutex<Registry>,
+}
+
+impl Metrics {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Returns the process-wide registry updated by [`Query`](crate::Query),
+    /// tools and history backends.
+    pub fn global() -> &'static Metrics {
+        static GLOBAL: OnceLock<Metrics> = OnceLock::new();
+        GLOBAL.get_or_init(Metrics::new)
+    }
+
+    /// Clears all recorded series.
+    pub fn reset(&self) {
+        *self.lock() = Registry::default();
+    }
+
+    /// Renders all series in the Prometheus text exposition format (0.0.4).
+    pub fn render(&self) -> String {
+        let registry = self.lock();
+        let mut out = String::new();
+        for (name, help, kind) in METRICS {
+            let counters: Vec<_> = registry.counters.iter().filter(|((n, _), _)| n == name).collect();
+            let histograms: Vec<_> = registry.histograms.iter().filter(|((n, _), _)| n == name).collect();
+            if counters.is_empty() && histograms.is_empty() {
+                continue;
+            }
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            for ((_, labels), value) in counters {
+                let _ = writeln!(out, "{name}{} {value}", format_labels(labels, None));
+            }
+            for ((_, labels), h) in histograms {
+                for (bound, count) in LATENCY_BUCKETS.iter().zip(&h.buckets) {
+                    let _ = writeln!(out, "{name}_bucket{} {count}", format_labels(labels, Some(&bound.to_string())));
+                }
+                let _ = writeln!(out, "{name}_bucket{} {}", format_labels(labels, Some("+Inf")), h.count);
+                let _ = writeln!(out, "{name}_sum{} {}", format_labels(labels, None), h.sum);
+                let _ = writeln!(out, "{name}_count{} {}", format_labels(labels, None), h.count);
+            }
+        }
+        out
+    }
+
+    /// Records one backend chat call (including its retries).
+    pub(crate) fn record_request(&self, backend: &str, model: &str, latency: Duration, outcome: Result<&Usage, ErrorKind>) {
+        let mut registry = self.lock();
+        let status = if outcome.is_ok() { "ok" } else { "error" };
+        *registry.counters
+            .entry(("erh_llm_requests_total", labels(&[("backend", backend), ("model", model), ("status", status)])))
+            .or_default() += 1;
+        registry.histograms
+            .entry(("erh_llm_request_duration_seconds", labels(&[("backend", backend), ("model", model)])))
+            .or_default()
+            .observe(latency.as_secs_f64());
+        match outcome {
+            Ok(usage) => {
+                for (direction, tokens) in [("prompt", usage.prompt_tokens), ("completion", usage.completion_tokens)] {
+                    *registry.counters
+                        .entry(("erh_llm_tokens_total", labels(&[("backend", backend), ("model", model), ("direction", direction)])))
+                        .or_default() += u64::from(tokens);
+                }
+            }
+            Err(kind) => {
+                *registry.counters
+                    .entry(("erh_llm_errors_total", labels(&[("backend", backend), ("kind", kind.as_str())])))
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    /// Records one tool invocation.
+    #[cfg_attr(not(feature="tools"), allow(dead_code))]
+    pub(crate) fn record_tool_call(&self, tool: &str, ok: bool) {
+        let status = if ok { "ok" } else { "error" };
+        *self.lock().counters
+            .entry(("erh_llm_tool_calls_total", labels(&[("tool", tool), ("status", status)])))
+            .or_default() += 1;
+    }
+
+    /// Records one history store operation.
+    pub(crate) fn record_history_store(&self, store: &str, latency: Duration, ok: bool) {
+        let status = if ok { "ok" } else { "error" };
+        self.lock().histograms
+            .entry(("erh_llm_history_store_duration_seconds", labels(&[("store", store), ("status", status)])))
+            .or_default()
+            .observe(latency.as_secs_f64());
+    }
+
+    /// Locks the registry, recovering from a poisoned lock since the data is
+    /// only ever incremented.
+    fn lock(&self) -> std::sync::MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn labels(pairs: &[(&'static str, &str)]) -> Labels {
+    pairs.iter().map(|(k, v)| (*k, v.to_string())).collect()
+}
+
+/// Formats `{k="v",…}`, optionally appending the histogram `le` label.
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut parts: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{k}=\"{}\"", escape(v)))
+        .collect();
+    if let Some(le) = le {
+        parts.push(format!("le=\"{le}\""));
+    }
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", parts.join(","))
+    }
+}
+
+/// Escapes a label value as required by the text format.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
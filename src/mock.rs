@@ -0,0 +1,358 @@
+//! Scriptable mock backend used through [`LLM::Dummy`](crate::LLM::Dummy).
+//!
+//! A [`MockLlm`] answers from a queue of scripted [`MockReply`]s and, once the
+//! queue is empty, from an optional responder closure. Replies can simulate
+//! tool calls (which the [`AgentLoop`](crate::AgentLoop) runs against the real
+//! tools registered on the [`Query`](crate::Query)), inject classified errors,
+//! and every round trip can be delayed by a fixed latency. All requests are
+//! recorded for inspection.
+//!
+//! Clones share state, so keep one handle for assertions and pass another to
+//! the query:
+//!
+//! ```rust,ignore
+//! let mock = MockLlm::new()
+//!     .tool_call("weather", json!({"city": "Oslo"}))
+//!     .reply("It is sunny in Oslo.");
+//! let mut query = Query::new(LLM::Dummy(mock.clone()), HistoryConfig::None);
+//! query.components = Some(registry);
+//! let answer = query.execute().await?;
+//! assert_eq!(mock.requests().len(), 2);
+//! assert_eq!(mock.requests()[1].messages.last().unwrap().role, "tool");
+//! ```
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use ollama_rs::generation::chat::ChatMessage;
+
+use crate::ErrorKind;
+#[cfg(feature="tools")]
+use crate::{AgentBackend, AgentReply, Usage};
+
+/// A single message as seen by the mock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockMessage {
+    /// `"system"`, `"user"`, `"assistant"` or `"tool"`.
+    pub role: String,
+    /// Message text (tool results for `"tool"` messages).
+    pub content: String,
+}
+
+/// One round trip received by the mock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockRequest {
+    /// Replayed history, system turn, user turn and any tool results so far.
+    pub messages: Vec<MockMessage>,
+    /// Names of the tools offered to the model.
+    pub tools: Vec<String>,
+    /// Whether structured (JSON) output was requested.
+    pub structured: bool,
+}
+
+impl MockRequest {
+    /// The round trip sending `messages` with `tools` offered.
+    pub(crate) fn new(messages: &[ChatMessage], tools: Vec<String>, structured: bool) -> Self {
+        MockRequest {
+            messages: messages.iter()
+                .map(|m| MockMessage { role: crate::role_name(&m.role).to_string(), content: m.content.clone() })
+                .collect(),
+            tools,
+            structured,
+        }
+    }
+
+    /// Content of the last user message, or `""` if there is none.
+    pub fn user(&self) -> &str {
+        self.messages.iter().rev().find(|m| m.role == "user").map(|m| m.content.as_str()).unwrap_or_default()
+    }
+
+    /// Content of the system message, if one was sent.
+    pub fn system(&self) -> Option<&str> {
+        self.messages.iter().find(|m| m.role == "system").map(|m| m.content.as_str())
+    }
+}
+
+/// An error injected by [`MockLlm::error`].
+///
+/// [`classify_error`](crate::classify_error) reports its `kind`, so retry and
+/// fallback behaviour can be exercised.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockError {
+    /// The kind the error classifies as.
+    pub kind: ErrorKind,
+    /// The error message.
+    pub message: String,
+}
+
+impl fmt::Display for MockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Mock {} error: {}", self.kind, self.message)
+    }
+}
+
+impl std::error::Error for MockError {}
+
+/// A scripted step, consumed in order by [`MockLlm`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockReply {
+    /// Answer with the given text.
+    Text(String),
+    /// Call a registered tool with JSON arguments, append its result as a
+    /// `"tool"` message and continue with the next step.
+    #[cfg(feature="tools")]
+    ToolCall(String, serde_json::Value),
+    /// Call several registered tools at once, as independent calls of a
+    /// single reply, then continue with the next step.
+    #[cfg(feature="tools")]
+    ToolCalls(Vec<(String, serde_json::Value)>),
+    /// Fail the round trip with the given error.
+    Error(MockError),
+}
+
+type Responder = Arc<dyn Fn(&MockRequest) -> String + Send + Sync>;
+
+#[derive(Default)]
+struct State {
+    script: VecDeque<MockReply>,
+    responder: Option<Responder>,
+    latency: Duration,
+    requests: Vec<MockRequest>,
+}
+
+/// A mock LLM backend; see the [module documentation](self).
+#[derive(Clone, Default)]
+pub struct MockLlm {
+    state: Arc<Mutex<State>>,
+}
+
+impl MockLlm {
+    /// Creates a mock with an empty script. Unscripted requests fail.
+    pub fn new() -> Self {
+        MockLlm::default()
+    }
+
+    /// Queues a canned text answer.
+    pub fn reply(self, text: impl Into<String>) -> Self {
+        self.push(MockReply::Text(text.into()))
+    }
+
+    /// Queues a simulated call of the tool `name` with `arguments`.
+    #[cfg(feature="tools")]
+    pub fn tool_call(self, name: impl Into<String>, arguments: serde_json::Value) -> Self {
+        self.push(MockReply::ToolCall(name.into(), arguments))
+    }
+
+    /// Queues simulated calls of several tools in a single reply.
+    #[cfg(feature="tools")]
+    pub fn tool_calls<I, S>(self, calls: I) -> Self
+    where
+        I: IntoIterator<Item = (S, serde_json::Value)>,
+        S: Into<String>,
+    {
+        self.push(MockReply::ToolCalls(calls.into_iter().map(|(name, arguments)| (name.into(), arguments)).collect()))
+    }
+
+    /// Queues an error of the given kind.
+    pub fn error(self, kind: ErrorKind, message: impl Into<String>) -> Self {
+        self.push(MockReply::Error(MockError { kind, message: message.into() }))
+    }
+
+    /// Queues an arbitrary step.
+    pub fn push(self, reply: MockReply) -> Self {
+        self.lock().script.push_back(reply);
+        self
+    }
+
+    /// Answers requests with `f` once the script is exhausted.
+    pub fn respond_with<F>(self, f: F) -> Self
+    where
+        F: Fn(&MockRequest) -> String + Send + Sync + 'static,
+    {
+        self.lock().responder = Some(Arc::new(f));
+        self
+    }
+
+    /// Delays every round trip by `latency`.
+    pub fn latency(self, latency: Duration) -> Self {
+        self.lock().latency = latency;
+        self
+    }
+
+    /// All round trips received so far, oldest first.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.lock().requests.clone()
+    }
+
+    /// The most recent round trip, if any.
+    pub fn last_request(&self) -> Option<MockRequest> {
+        self.lock().requests.last().cloned()
+    }
+
+    /// Number of scripted steps not yet consumed.
+    pub fn remaining(&self) -> usize {
+        self.lock().script.len()
+    }
+
+    /// Clears recorded requests (the script is kept).
+    pub fn clear_requests(&self) {
+        self.lock().requests.clear();
+    }
+
+    /// Records `request` and answers it with the next scripted step, or with
+    /// the responder once the script is exhausted.
+    ///
+    /// # Errors
+    /// Returns scripted errors, or an error when neither a scripted step nor
+    /// a responder is available.
+    async fn next(&self, request: MockRequest) -> Result<MockReply, Box<dyn std::error::Error>> {
+        let (latency, step, responder) = {
+            let mut state = self.lock();
+            state.requests.push(request.clone());
+            (state.latency, state.script.pop_front(), state.responder.clone())
+        };
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        match step {
+            Some(MockReply::Error(e)) => Err(Box::new(e)),
+            Some(step) => Ok(step),
+            None => match responder {
+                Some(f) => Ok(MockReply::Text(f(&request))),
+                None => Err(format!("Mock has no scripted response for: {}", request.user()).into()),
+            },
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for MockLlm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.lock();
+        f.debug_struct("MockLlm")
+            .field("script", &state.script)
+            .field("responder", &state.responder.is_some())
+            .field("latency", &state.latency)
+            .field("requests", &state.requests.len())
+            .finish()
+    }
+}
+
+impl PartialEq for MockLlm {
+    /// Mocks are equal when they share the same state.
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+/// A [`MockLlm`] taking part in an [`AgentLoop`](crate::AgentLoop).
+#[cfg(feature="tools")]
+pub(crate) struct MockAgent<'a> {
+    pub(crate) mock: &'a MockLlm,
+    pub(crate) structured: bool,
+}
+
+#[cfg(feature="tools")]
+impl AgentBackend for MockAgent<'_> {
+    async fn send(&self, messages: &[ChatMessage], tools: &[ollama_rs::generation::tools::ToolInfo]) -> Result<AgentReply, Box<dyn std::error::Error>> {
+        use ollama_rs::generation::{chat::MessageRole, tools::{ToolCall, ToolCallFunction}};
+
+        let tool_names = tools.iter().map(|t| t.function.name.clone()).collect();
+        let calls = match self.mock.next(MockRequest::new(messages, tool_names, self.structured)).await? {
+            MockReply::Text(text) => {
+                return Ok(AgentReply {
+                    message: ChatMessage::new(MessageRole::Assistant, text),
+                    usage: Usage::default(),
+                    model: "mock".to_string(),
+                    finish_reason: Some("stop".to_string()),
+                });
+            }
+            MockReply::ToolCall(name, arguments) => vec![(name, arguments)],
+            MockReply::ToolCalls(calls) => calls,
+            MockReply::Error(e) => return Err(Box::new(e)),
+        };
+        let mut message = ChatMessage::new(MessageRole::Assistant, String::new());
+        message.tool_calls = calls.into_iter()
+            .map(|(name, arguments)| ToolCall { function: ToolCallFunction { name, arguments } })
+            .collect();
+        Ok(AgentReply {
+            message,
+            usage: Usage::default(),
+            model: "mock".to_string(),
+            finish_reason: Some("tool_calls".to_string()),
+        })
+    }
+}
+
+#[cfg(all(test, feature="tools"))]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{Component, ComponentRegistry, HistoryConfig, Query, RetryPolicy, Tool, LLM};
+
+    fn query(mock: &MockLlm) -> Query {
+        let mut registry = ComponentRegistry::new();
+        registry.register(Component {
+            tools: vec![Tool::new("shout", "Upper-cases the text", |s: &String| {
+                let s = s.to_uppercase();
+                async move { s }
+            })],
+            ..Default::default()
+        });
+        let mut query = Query::new(LLM::Dummy(mock.clone()), HistoryConfig::None);
+        query.components = Some(registry);
+        query.setup.prompt = "Say hi loudly".into();
+        query
+    }
+
+    #[tokio::test]
+    async fn execute_runs_scripted_tool_calls() {
+        let mock = MockLlm::new().tool_call("shout", json!({"param": "hi"})).reply("They said HI.");
+        let response = query(&mock).execute().await.unwrap();
+        assert_eq!(response.text, "They said HI.");
+        assert_eq!(response.tool_calls.len(), 1);
+        assert_eq!((response.tool_calls[0].name.as_str(), response.tool_calls[0].result.as_str()), ("shout", "HI"));
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].user().contains("Say hi loudly"));
+        assert_eq!(requests[0].tools, ["shout"]);
+        let tool_message = requests[1].messages.last().unwrap();
+        assert_eq!((tool_message.role.as_str(), tool_message.content.as_str()), ("tool", "HI"));
+        assert_eq!(mock.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn execute_retries_scripted_errors() {
+        let retry = RetryPolicy::new(2).backoff(Duration::ZERO, Duration::ZERO);
+        let mock = MockLlm::new().error(ErrorKind::RateLimited, "slow down").reply("ok");
+        let mut retried = query(&mock);
+        retried.retry = retry.clone();
+        assert_eq!(retried.execute().await.unwrap().text, "ok");
+        assert_eq!(mock.requests().len(), 2);
+
+        let mock = MockLlm::new().error(ErrorKind::Client, "bad request").reply("unused");
+        let mut failed = query(&mock);
+        failed.retry = retry;
+        let err = failed.execute().await.unwrap_err();
+        assert_eq!(crate::classify_error(&*err), ErrorKind::Client);
+        assert_eq!(mock.remaining(), 1);
+    }
+
+    #[tokio::test]
+    async fn execute_returns_the_timeout_error() {
+        let mock = MockLlm::new().latency(Duration::from_millis(200)).reply("too late");
+        let mut query = query(&mock);
+        query.retry = RetryPolicy::new(2).deadline(Duration::from_millis(20));
+        let err = query.execute().await.unwrap_err();
+        assert_eq!(crate::classify_error(&*err), ErrorKind::Timeout);
+    }
+}
@@ -0,0 +1,165 @@
+//! Named model profiles and their capabilities.
+//!
+//! A [`ModelRegistry`] maps short names (e.g. `"fast"`, `"coder"`) to a
+//! [`ModelConfig`] plus the [`ModelCapabilities`] of that model. Attach one to
+//! [`Query::models`](crate::Query::models) and both [`QuerySetup::model`] and
+//! [`UserPrompt::Model`] may name a profile instead of spelling out a full
+//! configuration:
+//!
+//! ```rust,ignore
+//! let registry = ModelRegistry::new()
+//!     .register(
+//!         ModelConfig { short: Some("fast".into()), ..ModelConfig::new("llama3.2:1b") },
+//!         ModelCapabilities::default(),
+//!     )
+//!     .register(
+//!         ModelConfig { short: Some("coder".into()), tool: Some(true), ..ModelConfig::new("qwen2.5-coder:32b") },
+//!         ModelCapabilities { tools: true, json_mode: true, context_size: Some(32768), ..Default::default() },
+//!     );
+//!
+//! let mut query = Query::new(LLM::default(), HistoryConfig::None);
+//! query.models = Some(registry);
+//! query.setup.model = ModelConfig::new("coder");
+//! let answer = query.send_raw(UserPrompt::Model(ModelConfig::new("fast"), prompt)).await?;
+//! ```
+//!
+//! Capabilities are enforced where the crate can: tools are never attached to
+//! a model registered without tool support, and a profile's context size is
+//! capped at the model's limit.
+//!
+//! [`QuerySetup::model`]: crate::QuerySetup::model
+//! [`UserPrompt::Model`]: crate::UserPrompt::Model
+
+use std::collections::BTreeMap;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::ModelConfig;
+
+/// What a model can do.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelCapabilities {
+    /// Supports tool/function calling.
+    pub tools: bool,
+    /// Accepts image input.
+    pub vision: bool,
+    /// Can generate embeddings.
+    pub embeddings: bool,
+    /// Supports constrained JSON output.
+    pub json_mode: bool,
+    /// Maximum context window in tokens, if known.
+    pub context_size: Option<u32>,
+}
+
+impl From<&ModelConfig> for ModelCapabilities {
+    /// Capabilities implied by a bare [`ModelConfig`]: tool support follows
+    /// [`ModelConfig::tool`] and the context size [`ModelConfig::context_size`].
+    fn from(model: &ModelConfig) -> Self {
+        ModelCapabilities {
+            tools: model.tool.unwrap_or(false),
+            context_size: model.context_size,
+            ..Default::default()
+        }
+    }
+}
+
+/// A registered model: its configuration and capabilities.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisteredModel {
+    /// Model identifier and generation parameters.
+    pub config: ModelConfig,
+    /// What the model supports.
+    pub capabilities: ModelCapabilities,
+}
+
+/// A catalog of named model profiles; see the [module documentation](self).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelRegistry {
+    models: BTreeMap<String, RegisteredModel>,
+}
+
+impl ModelRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        ModelRegistry::default()
+    }
+
+    /// Registers `config` under its [`ModelConfig::short`] name, or under the
+    /// model identifier if it has none. An existing entry with the same name
+    /// is replaced.
+    ///
+    /// A config that enables tools on a model without tool capability has
+    /// tools switched off (with a warning), and `context_size` is capped at
+    /// the capability's limit.
+    pub fn register(mut self, config: ModelConfig, capabilities: ModelCapabilities) -> Self {
+        self.insert(config, capabilities);
+        self
+    }
+
+    /// Like [`ModelRegistry::register`] for an existing registry.
+    pub fn insert(&mut self, mut config: ModelConfig, capabilities: ModelCapabilities) {
+        let name = config.short.clone().unwrap_or_else(|| config.model.clone());
+        if config.tool == Some(true) && !capabilities.tools {
+            warn!("Model profile '{name}' enables tools but {} has no tool capability; disabling tools", config.model);
+            config.tool = Some(false);
+        }
+        if let Some(limit) = capabilities.context_size {
+            match config.context_size {
+                Some(ctx) if ctx > limit => {
+                    warn!("Model profile '{name}' context size {ctx} exceeds the limit of {limit}; capping");
+                    config.context_size = Some(limit);
+                }
+                None => config.context_size = Some(limit),
+                _ => {}
+            }
+        }
+        self.models.insert(name, RegisteredModel { config, capabilities });
+    }
+
+    /// The profile registered under `name`.
+    pub fn get(&self, name: &str) -> Option<&RegisteredModel> {
+        self.models.get(name)
+    }
+
+    /// The configuration of the profile registered under `name`.
+    ///
+    /// # Errors
+    /// Returns an error if no such profile exists.
+    pub fn resolve(&self, name: &str) -> Result<ModelConfig, Box<dyn std::error::Error>> {
+        self.get(name)
+            .map(|m| m.config.clone())
+            .ok_or_else(|| format!("Unknown model profile '{name}'").into())
+    }
+
+    /// Capabilities of `model`, looked up by its short name, by profile name
+    /// or by model identifier, in that order.
+    pub fn capabilities(&self, model: &ModelConfig) -> Option<&ModelCapabilities> {
+        model.short.as_deref()
+            .and_then(|short| self.get(short))
+            .or_else(|| self.get(&model.model))
+            .or_else(|| self.models.values().find(|m| m.config.model == model.model))
+            .map(|m| &m.capabilities)
+    }
+
+    /// Names of all registered profiles, sorted.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.models.keys().map(String::as_str)
+    }
+
+    /// All registered profiles with their names, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &RegisteredModel)> {
+        self.models.iter().map(|(name, m)| (name.as_str(), m))
+    }
+
+    /// Number of registered profiles.
+    pub fn len(&self) -> usize {
+        self.models.len()
+    }
+
+    /// Returns `true` if no profile is registered.
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty()
+    }
+}
@@ -0,0 +1,65 @@
+//! Backend answers together with token usage and timing metadata.
+
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+/// Token counts reported by the backend for a single exchange.
+///
+/// Backends that do not report usage leave both counts at `0`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    /// Number of tokens in the prompt (including replayed history and system turn).
+    pub prompt_tokens: u32,
+    /// Number of tokens generated for the answer.
+    pub completion_tokens: u32,
+}
+
+impl Usage {
+    /// Creates a [`Usage`] from prompt and completion token counts.
+    pub fn new(prompt_tokens: u32, completion_tokens: u32) -> Self {
+        Usage { prompt_tokens, completion_tokens }
+    }
+
+    /// Sum of prompt and completion tokens.
+    pub fn total_tokens(&self) -> u32 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// The answer to a [`Query`](crate::Query) along with accounting metadata.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Response {
+    /// The generated text.
+    pub text: String,
+    /// Token usage as reported by the backend.
+    pub usage: Usage,
+    /// Wall-clock time from sending the request until the answer arrived,
+    /// including retries and fallbacks.
+    pub latency: Duration,
+    /// The model that produced the answer, as reported by the backend.
+    pub model: String,
+    /// [`LLM::name`](crate::LLM::name) of the backend that answered.
+    pub backend: String,
+    /// Why generation stopped (e.g. `"stop"`, `"length"`), if the backend says.
+    pub finish_reason: Option<String>,
+    /// Decisions of approval hooks on the tool calls made for this answer,
+    /// in call order (see [`Tool::approve`](crate::Tool::approve)).
+    #[cfg(feature="tools")]
+    pub tool_approvals: Vec<crate::ToolApprovalRecord>,
+    /// Tool calls made for this answer, in call order.
+    #[cfg(feature="tools")]
+    pub tool_calls: Vec<crate::ToolCallRecord>,
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl From<Response> for String {
+    fn from(response: Response) -> Self {
+        response.text
+    }
+}
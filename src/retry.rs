@@ -0,0 +1,218 @@
+//! Retry, backoff and timeout policy for backend calls.
+//!
+//! A [`RetryPolicy`] wraps every chat round trip and embedding call made by
+//! [`Query`](crate::Query). Each attempt can be bounded by
+//! [`RetryPolicy::attempt_timeout`], the whole call (including backoff sleeps)
+//! by [`RetryPolicy::deadline`], and failed attempts are retried with
+//! exponential backoff when their [`ErrorKind`] is listed in
+//! [`RetryPolicy::retry_on`].
+//!
+//! A chat turn that calls tools makes several round trips; only the failing
+//! round trip is repeated, never the tools before it. The deadline bounds the
+//! whole turn, fallback backends included.
+//!
+//! The default policy makes a single attempt without any time limit, which
+//! matches the behaviour before retry support was added.
+//!
+//! # Example
+//! ```rust,ignore
+//! query.retry = RetryPolicy::new(4)
+//!     .backoff(Duration::from_millis(250), Duration::from_secs(5))
+//!     .attempt_timeout(Duration::from_secs(60))
+//!     .deadline(Duration::from_secs(180));
+//! ```
+
+use std::{
+    hash::{BuildHasher, Hasher},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use futures::Future;
+use log::warn;
+
+use crate::error::{classify, ErrorKind, LlmError};
+
+/// Governs how often and how long a backend call may be attempted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one. `0` is treated as `1`.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between attempts.
+    pub max_backoff: Duration,
+    /// Factor the delay grows by after each failed attempt.
+    pub multiplier: f64,
+    /// Randomise each delay to between 50% and 100% of its nominal value, so
+    /// that many clients do not retry in lock-step.
+    pub jitter: bool,
+    /// Time limit for a single attempt.
+    pub attempt_timeout: Option<Duration>,
+    /// Time limit for the whole call, including all retries and backoff.
+    pub deadline: Option<Duration>,
+    /// Error kinds that are retried. Anything else fails immediately.
+    pub retry_on: Vec<ErrorKind>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: true,
+            attempt_timeout: None,
+            deadline: None,
+            retry_on: vec![ErrorKind::Timeout, ErrorKind::Connection, ErrorKind::Server, ErrorKind::RateLimited],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Creates a policy with `max_attempts` attempts and default backoff.
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the initial and maximum backoff delay.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Sets the backoff growth factor.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Enables or disables jitter on backoff delays.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the per-attempt time limit.
+    pub fn attempt_timeout(mut self, timeout: Duration) -> Self {
+        self.attempt_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the overall deadline for a call including retries.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Replaces the list of retryable error kinds.
+    pub fn retry_on(mut self, kinds: &[ErrorKind]) -> Self {
+        self.retry_on = kinds.to_vec();
+        self
+    }
+
+    /// Returns `true` if `err` should be retried under this policy.
+    pub fn is_retryable(&self, err: &(dyn std::error::Error + 'static)) -> bool {
+        self.retry_on.contains(&classify(err))
+    }
+
+    /// Nominal delay after the given (1-based) failed attempt.
+    fn backoff_for(&self, attempt: u32) -> Duration {
+        let exp = self.multiplier.max(1.0).powi(attempt.saturating_sub(1) as i32);
+        let nominal = self.initial_backoff.mul_f64(exp).min(self.max_backoff);
+        if self.jitter {
+            nominal.mul_f64(jitter_factor())
+        } else {
+            nominal
+        }
+    }
+
+    /// Runs `op` under this policy.
+    ///
+    /// `what` names the operation in log messages (e.g. `"Ollama chat"`).
+    /// When neither timeouts nor retries are configured, `op` is awaited
+    /// directly so no timer runtime is required.
+    ///
+    /// # Errors
+    /// Returns the last error from `op` once attempts are exhausted or a
+    /// non-retryable error occurs, [`LlmError::Timeout`] when an attempt times
+    /// out, or [`LlmError::DeadlineExceeded`] when no time is left for another
+    /// attempt.
+    pub(crate) async fn run<T, F, Fut>(&self, what: &str, mut op: F) -> Result<T, Box<dyn std::error::Error>>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Box<dyn std::error::Error>>>,
+    {
+        let start = Instant::now();
+        let max_attempts = self.max_attempts.max(1);
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let remaining = self.deadline.map(|d| d.saturating_sub(start.elapsed()));
+            if let (Some(deadline), Some(Duration::ZERO)) = (self.deadline, remaining) {
+                return Err(Box::new(LlmError::DeadlineExceeded(deadline)));
+            }
+            let limit = match (self.attempt_timeout, remaining) {
+                (Some(a), Some(r)) => Some(a.min(r)),
+                (a, r) => a.or(r),
+            };
+
+            let result = match limit {
+                Some(limit) => match tokio::time::timeout(limit, op()).await {
+                    Ok(r) => r,
+                    Err(_) => Err(Box::new(LlmError::Timeout(limit)) as Box<dyn std::error::Error>),
+                },
+                None => op().await,
+            };
+
+            let err = match result {
+                Ok(v) => return Ok(v),
+                Err(e) => e,
+            };
+            let kind = classify(err.as_ref());
+            if attempt >= max_attempts || !self.retry_on.contains(&kind) {
+                return Err(err);
+            }
+            let delay = self.backoff_for(attempt);
+            if let Some(deadline) = self.deadline
+                && start.elapsed() + delay >= deadline
+            {
+                warn!("{what} failed ({kind}) with no time left before the deadline: {err}");
+                return Err(err);
+            }
+            warn!("{what} failed ({kind}), retrying in {delay:?} (attempt {attempt}/{max_attempts}): {err}");
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Pseudo-random factor in `[0.5, 1.0)` for backoff jitter.
+///
+/// Every [`RandomState`](std::collections::hash_map::RandomState) is seeded
+/// differently, so hashing the clock with a fresh one gives uniformly spread
+/// bits without a `rand` dependency.
+fn jitter_factor() -> f64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(nanos);
+    // The top 53 bits as a fraction in [0, 1).
+    0.5 + (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64 / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jitter_spreads_delays_over_the_upper_half() {
+        let policy = RetryPolicy::new(3).backoff(Duration::from_secs(1), Duration::from_secs(1));
+        let delays: Vec<Duration> = (0..64).map(|_| policy.backoff_for(1)).collect();
+        assert!(delays.iter().all(|d| *d >= Duration::from_millis(500) && *d < Duration::from_secs(1)));
+        assert!(delays.iter().any(|d| *d != delays[0]));
+        assert_eq!(policy.jitter(false).backoff_for(1), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,482 @@
+//! Prompt routing across several backends and models.
+//!
+//! A [`Router`] holds a list of named [`Route`]s, each describing which
+//! [`LLM`] backend, [`ModelConfig`] and (optionally) [`ComponentRegistry`]
+//! should serve a prompt. Incoming prompts are classified by a chain of
+//! [`Classifier`]s tried in order:
+//!
+//! - **Rules** — keyword or regex matches, cheap and deterministic.
+//! - **Embedding** — nearest centroid over example prompts per route.
+//! - **Llm** — ask a (small) model to pick the route by name.
+//!
+//! The first classifier that yields a known route wins; if none does, the
+//! default route is used. Every decision is logged.
+//!
+//! # Example
+//! ```rust,ignore
+//! let router = Router::new(Route::new("chat", LLM::default(), ModelConfig::new("llama3.2:1b")))
+//!     .route(Route::new("code", LLM::default(), ModelConfig::new("qwen2.5-coder:32b")))
+//!     .classifier(Classifier::Rules(vec![
+//!         RoutingRule::keywords("code", &["rust", "compile", "function"]),
+//!     ]));
+//!
+//! let mut query = router.query(setup, HistoryConfig::None).await;
+//! let answer = query.execute().await?;
+//! ```
+
+use log::{debug, info, warn};
+use regex::Regex;
+
+#[cfg(feature="tools")]
+use crate::ComponentRegistry;
+use crate::{HistoryConfig, LLM, ModelConfig, Query, QuerySetup, RetryPolicy, UserPrompt};
+
+/// A named destination for prompts: backend, model and tool set.
+#[derive(Clone)]
+pub struct Route {
+    /// Unique route name, also used as the label for LLM classification.
+    pub name: String,
+    /// Human-readable description of what belongs on this route. Shown to
+    /// the model when [`Classifier::Llm`] is used.
+    pub description: String,
+    /// Backend serving this route.
+    pub connection: LLM,
+    /// Model configuration used on this route.
+    pub model: ModelConfig,
+    /// Optional component/tool registry attached to queries on this route.
+    #[cfg(feature="tools")]
+    pub components: Option<ComponentRegistry>,
+}
+
+impl Route {
+    /// Creates a new [`Route`] without description or components.
+    pub fn new(name: &str, connection: LLM, model: ModelConfig) -> Self {
+        Route {
+            name: name.to_string(),
+            description: String::new(),
+            connection,
+            model,
+            #[cfg(feature="tools")]
+            components: None,
+        }
+    }
+
+    /// Sets the route description used for LLM classification.
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = description.to_string();
+        self
+    }
+
+    /// Attaches a component/tool registry to queries on this route.
+    #[cfg(feature="tools")]
+    pub fn components(mut self, components: ComponentRegistry) -> Self {
+        self.components = Some(components);
+        self
+    }
+
+    /// Returns the backend connection with this route's model applied to
+    /// every backend that carries one, including those inside fallback
+    /// chains and cassettes.
+    fn connection(&self) -> LLM {
+        self.connection.with_model(&self.model)
+    }
+}
+
+/// A deterministic routing rule evaluated by [`Classifier::Rules`].
+#[derive(Debug, Clone)]
+pub enum RoutingRule {
+    /// Matches when the prompt contains any of the keywords (case-insensitive).
+    /// Fields: `(route, keywords)`.
+    Keywords(String, Vec<String>),
+    /// Matches when the regex matches the prompt. Fields: `(route, regex)`.
+    Regex(String, Regex),
+}
+
+impl RoutingRule {
+    /// Convenience constructor for [`RoutingRule::Keywords`].
+    pub fn keywords(route: &str, keywords: &[&str]) -> Self {
+        RoutingRule::Keywords(route.to_string(), keywords.iter().map(|k| k.to_lowercase()).collect())
+    }
+
+    /// Convenience constructor for [`RoutingRule::Regex`].
+    ///
+    /// # Errors
+    /// Returns an error if `pattern` is not a valid regex.
+    pub fn regex(route: &str, pattern: &str) -> Result<Self, regex::Error> {
+        Ok(RoutingRule::Regex(route.to_string(), Regex::new(pattern)?))
+    }
+
+    /// Returns the route name if this rule matches `prompt`.
+    fn matches(&self, prompt: &str) -> Option<&str> {
+        match self {
+            RoutingRule::Keywords(route, keywords) => {
+                let lower = prompt.to_lowercase();
+                keywords.iter().any(|k| lower.contains(k.as_str())).then_some(route.as_str())
+            }
+            RoutingRule::Regex(route, regex) => regex.is_match(prompt).then_some(route.as_str()),
+        }
+    }
+}
+
+/// Strategy used to map a prompt onto a route name.
+#[derive(Clone)]
+pub enum Classifier {
+    /// First matching rule wins.
+    Rules(Vec<RoutingRule>),
+    /// Nearest centroid by cosine similarity.
+    Embedding {
+        /// Ollama instance and embedding model: `(host, port, model_config)`.
+        config: (String, u16, ModelConfig),
+        /// One centroid per route: `(route, centroid)`.
+        centroids: Vec<(String, Vec<f32>)>,
+        /// Minimum cosine similarity required to accept the nearest centroid.
+        threshold: f32,
+    },
+    /// Ask a model to answer with the route name.
+    Llm(LLM),
+}
+
+impl Classifier {
+    /// Builds a [`Classifier::Embedding`] by embedding example prompts for each
+    /// route and averaging them into a centroid.
+    ///
+    /// `examples` is a list of `(route, example_prompts)`. Routes whose examples
+    /// all fail to embed are skipped with a warning.
+    ///
+    /// # Errors
+    /// Returns an error if the embedding backend reports a failure.
+    pub async fn embedding(config: (String, u16, ModelConfig), examples: Vec<(String, Vec<String>)>, threshold: f32) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::embedding_with_retry(config, examples, threshold, &RetryPolicy::default()).await
+    }
+
+    /// Like [`Classifier::embedding`] but embeds the examples under the given
+    /// [`RetryPolicy`].
+    ///
+    /// # Errors
+    /// Returns an error if the embedding backend reports a failure.
+    pub async fn embedding_with_retry(config: (String, u16, ModelConfig), examples: Vec<(String, Vec<String>)>, threshold: f32, retry: &RetryPolicy) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut centroids = Vec::new();
+        for (route, texts) in examples {
+            let mut sum: Vec<f32> = Vec::new();
+            let mut count = 0usize;
+            for text in texts {
+                let v = Query::embed_with_retry(config.clone(), text, retry).await?;
+                if v.is_empty() {
+                    continue;
+                }
+                if sum.is_empty() {
+                    sum = vec![0.0; v.len()];
+                }
+                if v.len() != sum.len() {
+                    warn!("Skipping example for route '{route}' with mismatched embedding size");
+                    continue;
+                }
+                for (s, x) in sum.iter_mut().zip(v.iter()) {
+                    *s += x;
+                }
+                count += 1;
+            }
+            if count == 0 {
+                warn!("No usable examples for route '{route}', skipping centroid");
+                continue;
+            }
+            let centroid = sum.into_iter().map(|s| s / count as f32).collect();
+            centroids.push((route, centroid));
+        }
+        Ok(Classifier::Embedding { config, centroids, threshold })
+    }
+
+    /// Short name used in routing logs.
+    fn kind(&self) -> &'static str {
+        match self {
+            Classifier::Rules(_) => "rules",
+            Classifier::Embedding { .. } => "embedding",
+            Classifier::Llm(_) => "llm",
+        }
+    }
+
+    /// Runs this classifier, making backend calls under `retry`. Returns
+    /// `(route, score)` or `None` when it has no opinion.
+    async fn classify(&self, prompt: &str, routes: &[Route], retry: &RetryPolicy) -> Result<Option<(String, Option<f32>)>, Box<dyn std::error::Error>> {
+        match self {
+            Classifier::Rules(rules) => {
+                Ok(rules.iter().find_map(|r| r.matches(prompt)).map(|r| (r.to_string(), None)))
+            }
+            Classifier::Embedding { config, centroids, threshold } => {
+                let v = Query::embed_with_retry(config.clone(), prompt.to_string(), retry).await?;
+                if v.is_empty() {
+                    return Ok(None);
+                }
+                let best = centroids.iter()
+                    .map(|(route, c)| (route, cosine_similarity(&v, c)))
+                    .max_by(|a, b| a.1.total_cmp(&b.1));
+                Ok(match best {
+                    Some((route, score)) if score >= *threshold => Some((route.clone(), Some(score))),
+                    _ => None,
+                })
+            }
+            Classifier::Llm(connection) => {
+                let options: String = routes.iter()
+                    .map(|r| format!("- {}: {}\n", r.name, r.description))
+                    .collect();
+                let question = format!(
+                    "QUERY: Classify the following prompt into exactly one of these categories. Answer with the category name only.\n\nCATEGORIES:\n{options}\nPROMPT: {prompt}",
+                );
+                let mut q = Query::new(connection.clone(), HistoryConfig::None);
+                q.retry = retry.clone();
+                let answer = q.send_raw(UserPrompt::Default(question)).await?;
+                Ok(match_route_name(&answer, routes).map(|r| (r, None)))
+            }
+        }
+    }
+}
+
+/// The outcome of classifying a prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutingDecision {
+    /// Name of the selected route.
+    pub route: String,
+    /// Which classifier decided (`"rules"`, `"embedding"`, `"llm"` or `"default"`).
+    pub method: &'static str,
+    /// Similarity score, for embedding decisions.
+    pub score: Option<f32>,
+}
+
+/// Dispatches prompts to routes. See the [module documentation](self).
+#[derive(Clone)]
+pub struct Router {
+    /// Name of the route used when no classifier matches.
+    default: String,
+    /// All routes, including the default one.
+    routes: Vec<Route>,
+    classifiers: Vec<Classifier>,
+    /// Policy for classifier calls and the [`Query::retry`] of routed queries.
+    retry: RetryPolicy,
+}
+
+impl Router {
+    /// Creates a new [`Router`] with the given default route and no classifiers.
+    pub fn new(default: Route) -> Self {
+        Router {
+            default: default.name.clone(),
+            routes: vec![default],
+            classifiers: Vec::new(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Adds a route. A route with the same name as an existing one replaces it.
+    pub fn route(mut self, route: Route) -> Self {
+        self.routes.retain(|r| r.name != route.name);
+        self.routes.push(route);
+        self
+    }
+
+    /// Appends a classifier to the chain. Classifiers run in insertion order.
+    pub fn classifier(mut self, classifier: Classifier) -> Self {
+        self.classifiers.push(classifier);
+        self
+    }
+
+    /// Sets the [`RetryPolicy`] for the backend calls of classifiers and for
+    /// the queries returned by [`Router::query`].
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Returns the route with the given name, falling back to the default route.
+    pub fn get(&self, name: &str) -> &Route {
+        self.routes.iter()
+            .find(|r| r.name == name)
+            .or_else(|| self.routes.iter().find(|r| r.name == self.default))
+            .unwrap_or(&self.routes[0])
+    }
+
+    /// Classifies `prompt` by running each classifier in turn.
+    ///
+    /// Classifier errors are logged and the next classifier is tried; a
+    /// classifier that names an unknown route is treated as having no opinion.
+    pub async fn classify(&self, prompt: &str) -> RoutingDecision {
+        for classifier in &self.classifiers {
+            match classifier.classify(prompt, &self.routes, &self.retry).await {
+                Ok(Some((route, score))) if self.routes.iter().any(|r| r.name == route) => {
+                    return RoutingDecision { route, method: classifier.kind(), score };
+                }
+                Ok(Some((route, _))) => {
+                    warn!("Classifier '{}' selected unknown route '{route}'", classifier.kind());
+                }
+                Ok(None) => {
+                    debug!("Classifier '{}' had no match", classifier.kind());
+                }
+                Err(e) => {
+                    warn!("Classifier '{}' failed: {e}", classifier.kind());
+                }
+            }
+        }
+        RoutingDecision { route: self.default.clone(), method: "default", score: None }
+    }
+
+    /// Classifies [`QuerySetup::prompt`] and returns a [`Query`] configured for
+    /// the selected route.
+    ///
+    /// The route's model replaces [`QuerySetup::model`] and its component
+    /// registry (if any) is attached to the query.
+    pub async fn query(&self, setup: QuerySetup, history: HistoryConfig) -> Query {
+        let decision = self.classify(&setup.prompt).await;
+        let route = self.get(&decision.route);
+        info!(
+            "Routing chat {} to '{}' (model: {}, method: {}, score: {:?})",
+            setup.chatuuid, route.name, route.model.model, decision.method, decision.score
+        );
+
+        let mut q = Query::new(route.connection(), history);
+        q.retry = self.retry.clone();
+        q.setup = setup;
+        q.setup.model = route.model.clone();
+        #[cfg(feature="tools")]
+        if let Some(components) = &route.components {
+            q.setup.components = Some(components.clone());
+            q.components = Some(components.clone());
+        }
+        q
+    }
+}
+
+/// Finds the route whose name best matches a free-form model answer.
+///
+/// An exact (case-insensitive, trimmed) match wins; otherwise the longest
+/// route name contained in the answer is chosen.
+fn match_route_name(answer: &str, routes: &[Route]) -> Option<String> {
+    let answer = answer.trim().trim_matches(|c: char| c == '"' || c == '\'' || c == '.').to_lowercase();
+    if let Some(r) = routes.iter().find(|r| r.name.to_lowercase() == answer) {
+        return Some(r.name.clone());
+    }
+    routes.iter()
+        .filter(|r| answer.contains(&r.name.to_lowercase()))
+        .max_by_key(|r| r.name.len())
+        .map(|r| r.name.clone())
+}
+
+/// Cosine similarity of two vectors; `0.0` for mismatched or zero vectors.
+pub(crate) fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let na: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let nb: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if na == 0.0 || nb == 0.0 {
+        0.0
+    } else {
+        dot / (na * nb)
+    }
+}
+
+#[cfg(all(test, feature="tools"))]
+mod tests {
+    use super::*;
+    use crate::MockLlm;
+
+    fn router() -> Router {
+        Router::new(Route::new("chat", LLM::default(), ModelConfig::new("llama3.2:1b")))
+            .route(Route::new("code", LLM::default(), ModelConfig::new("qwen2.5-coder:32b")).description("Programming questions"))
+            .route(Route::new("math", LLM::default(), ModelConfig::new("qwen2-math:7b")).description("Arithmetic and proofs"))
+    }
+
+    #[cfg(feature="testing")]
+    fn setup(prompt: &str) -> QuerySetup {
+        QuerySetup { prompt: prompt.to_string(), ..QuerySetup::new() }
+    }
+
+    #[tokio::test]
+    async fn rules_pick_the_first_matching_route() {
+        let router = router().classifier(Classifier::Rules(vec![
+            RoutingRule::keywords("code", &["Rust", "compile"]),
+            RoutingRule::regex("math", r"\d+\s*[+*/-]\s*\d+").unwrap(),
+            RoutingRule::keywords("poetry", &["poem"]),
+        ]));
+
+        let decision = router.classify("Why won't my RUST code compile?").await;
+        assert_eq!(decision, RoutingDecision { route: "code".into(), method: "rules", score: None });
+        assert_eq!(router.classify("What is 12 * 7?").await.route, "math");
+        // A rule naming an unknown route has no opinion.
+        assert_eq!(router.classify("Write a poem").await, RoutingDecision { route: "chat".into(), method: "default", score: None });
+    }
+
+    #[tokio::test]
+    async fn llm_classifier_matches_the_answer_to_a_route() {
+        let mock = MockLlm::new().reply("Category: math.").reply("I cannot decide").error(crate::ErrorKind::Client, "bad request");
+        let router = router().classifier(Classifier::Llm(LLM::Dummy(mock.clone())));
+
+        assert_eq!(router.classify("What is 12 * 7?").await, RoutingDecision { route: "math".into(), method: "llm", score: None });
+        let question = mock.requests()[0].user().to_string();
+        assert!(question.contains("- code: Programming questions") && question.contains("PROMPT: What is 12 * 7?"), "{question}");
+
+        assert_eq!(router.classify("Hello").await.method, "default");
+        // A failing classifier falls through to the default route.
+        assert_eq!(router.classify("Hello").await.method, "default");
+    }
+
+    #[tokio::test]
+    async fn classifiers_run_in_order_until_one_decides() {
+        let mock = MockLlm::new().reply("code");
+        let router = router()
+            .classifier(Classifier::Rules(vec![RoutingRule::keywords("math", &["sum"])]))
+            .classifier(Classifier::Llm(LLM::Dummy(mock.clone())));
+
+        assert_eq!(router.classify("The sum of 2 and 2").await.method, "rules");
+        assert!(mock.requests().is_empty());
+        assert_eq!(router.classify("Borrow checker woes").await, RoutingDecision { route: "code".into(), method: "llm", score: None });
+    }
+
+    #[cfg(feature="testing")]
+    #[tokio::test]
+    async fn embedding_classifier_picks_the_nearest_centroid_above_the_threshold() {
+        let server = crate::testing::FakeOllama::start().await.unwrap();
+        server.embedding(vec![1.0, 0.0]).embedding(vec![1.0, 0.2]).embedding(vec![0.0, 1.0]);
+        let examples = vec![
+            ("code".to_string(), vec!["fix this function".to_string(), "why does it not compile".to_string()]),
+            ("math".to_string(), vec!["what is 2 + 2".to_string()]),
+        ];
+        let classifier = Classifier::embedding(server.embed_config(ModelConfig::new("nomic-embed-text")), examples, 0.9).await.unwrap();
+        let Classifier::Embedding { centroids, .. } = &classifier else { panic!("not an embedding classifier") };
+        assert_eq!(centroids, &[("code".to_string(), vec![1.0, 0.1]), ("math".to_string(), vec![0.0, 1.0])]);
+
+        let router = router().classifier(classifier);
+        server.embedding(vec![0.9, 0.1]);
+        let decision = router.classify("my loop never ends").await;
+        assert_eq!((decision.route.as_str(), decision.method), ("code", "embedding"));
+        assert!(decision.score.unwrap() > 0.99);
+
+        // Equally close to both centroids: below the threshold, so the default route wins.
+        server.embedding(vec![0.7, 0.7]);
+        assert_eq!(router.classify("hmm").await, RoutingDecision { route: "chat".into(), method: "default", score: None });
+    }
+
+    #[cfg(feature="testing")]
+    #[tokio::test]
+    async fn routes_send_their_own_model() {
+        let server = crate::testing::FakeOllama::start().await.unwrap();
+        let ollama = server.llm(ModelConfig::new("llama3.2:1b"));
+        let router = Router::new(Route::new("chat", ollama.clone(), ModelConfig::new("llama3.2:1b")))
+            .route(Route::new("code", ollama.clone(), ModelConfig::new("qwen2.5-coder:32b")))
+            .route(Route::new("backup", LLM::Fallback(vec![ollama]), ModelConfig::new("gemma3:4b")))
+            .route(Route::new("cloud", LLM::MistralAI("key".into()), ModelConfig::new("mistral-small-latest")))
+            .classifier(Classifier::Rules(vec![
+                RoutingRule::keywords("code", &["rust"]),
+                RoutingRule::keywords("backup", &["backup"]),
+                RoutingRule::keywords("cloud", &["essay"]),
+            ]));
+
+        for (prompt, model) in [("hello", "llama3.2:1b"), ("fix my rust", "qwen2.5-coder:32b"), ("use the backup", "gemma3:4b")] {
+            server.reply("ok");
+            router.query(setup(prompt), HistoryConfig::None).await.execute().await.unwrap();
+            assert_eq!(server.requests().last().unwrap().body["model"], model, "prompt {prompt:?}");
+        }
+
+        let query = router.query(setup("write an essay"), HistoryConfig::None).await;
+        assert_eq!(Query::mistral_backend("key", &query.setup.model, false).model(), "mistral-small-latest");
+        assert_eq!(Query::mistral_backend("key", &ModelConfig::default(), false).model(), crate::MISTRAL_DEFAULT_MODEL);
+    }
+}
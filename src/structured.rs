@@ -0,0 +1,232 @@
+//! Structured (JSON schema constrained) output support.
+//!
+//! Used by [`Query::execute_typed`](crate::Query::execute_typed) to generate
+//! a schema for the target type, extract JSON from the model answer and
+//! validate it before deserialising.
+//!
+//! The validator covers the subset of JSON Schema that `schemars` emits for
+//! ordinary Rust types: `type`, `properties`, `required`,
+//! `additionalProperties: false`, `items`, `enum`, `const`, `anyOf` and
+//! `oneOf`. Anything else is accepted as-is and left to serde.
+
+use schemars::{JsonSchema, Schema, generate::SchemaSettings};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// Generates a self-contained (no `$ref`) draft-07 schema for `T`.
+///
+/// Ollama does not resolve references, so subschemas are inlined.
+pub(crate) fn schema_for<T: JsonSchema>() -> Schema {
+    let mut settings = SchemaSettings::draft07();
+    settings.inline_subschemas = true;
+    settings.into_generator().into_root_schema_for::<T>()
+}
+
+/// Extracts, validates and deserialises a JSON answer.
+///
+/// # Errors
+/// Returns a human-readable message suitable for feeding back to the model
+/// when the answer is not JSON, does not match `schema`, or cannot be
+/// deserialised into `T`.
+pub(crate) fn parse<T: DeserializeOwned>(answer: &str, schema: &Value) -> Result<T, String> {
+    let json = extract_json(answer);
+    let value: Value = serde_json::from_str(json).map_err(|e| format!("response is not valid JSON: {e}"))?;
+    validate(schema, &value, "$")?;
+    serde_json::from_value(value).map_err(|e| format!("response does not match the expected structure: {e}"))
+}
+
+/// Strips Markdown code fences and surrounding prose from a model answer.
+fn extract_json(answer: &str) -> &str {
+    let trimmed = answer.trim();
+    if let Some(rest) = trimmed.strip_prefix("```") {
+        // Skip an optional language tag on the fence line.
+        let body = rest.split_once('\n').map(|(_, b)| b).unwrap_or(rest);
+        return body.trim_end().trim_end_matches("```").trim();
+    }
+    let start = trimmed.find(['{', '[']);
+    let end = trimmed.rfind(['}', ']']);
+    match (start, end) {
+        (Some(s), Some(e)) if e >= s => &trimmed[s..=e],
+        _ => trimmed,
+    }
+}
+
+/// Validates `value` against `schema`. `path` is a JSONPath-like location
+/// used in error messages.
+fn validate(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    let Some(schema) = schema.as_object() else {
+        // `true` / `false` boolean schemas.
+        return match schema {
+            Value::Bool(false) => Err(format!("{path}: no value is allowed here")),
+            _ => Ok(()),
+        };
+    };
+
+    if let Some(expected) = schema.get("const")
+        && expected != value
+    {
+        return Err(format!("{path}: expected constant {expected}"));
+    }
+
+    if let Some(Value::Array(options)) = schema.get("enum")
+        && !options.contains(value)
+    {
+        return Err(format!("{path}: value {value} is not one of {}", Value::Array(options.clone())));
+    }
+
+    for key in ["anyOf", "oneOf"] {
+        if let Some(Value::Array(variants)) = schema.get(key)
+            && !variants.iter().any(|v| validate(v, value, path).is_ok())
+        {
+            return Err(format!("{path}: value does not match any allowed variant"));
+        }
+    }
+
+    if let Some(ty) = schema.get("type") {
+        let allowed: Vec<&str> = match ty {
+            Value::String(s) => vec![s.as_str()],
+            Value::Array(a) => a.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        if !allowed.is_empty() && !allowed.iter().any(|t| type_matches(t, value)) {
+            return Err(format!("{path}: expected {}, got {}", allowed.join(" or "), type_name(value)));
+        }
+    }
+
+    if let Value::Object(map) = value {
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for key in required.iter().filter_map(Value::as_str) {
+                if !map.contains_key(key) {
+                    return Err(format!("{path}: missing required property '{key}'"));
+                }
+            }
+        }
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for (key, v) in map {
+            match properties.and_then(|p| p.get(key)) {
+                Some(sub) => validate(sub, v, &format!("{path}.{key}"))?,
+                None => {
+                    if schema.get("additionalProperties") == Some(&Value::Bool(false)) {
+                        return Err(format!("{path}: unexpected property '{key}'"));
+                    }
+                }
+            }
+        }
+    }
+
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        for (i, item) in items.iter().enumerate() {
+            validate(item_schema, item, &format!("{path}[{i}]"))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn type_matches(ty: &str, value: &Value) -> bool {
+    match ty {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{HistoryConfig, MockLlm, Query, LLM};
+
+    #[derive(Debug, PartialEq, Deserialize, JsonSchema)]
+    #[serde(deny_unknown_fields)]
+    struct City {
+        name: String,
+        population: u64,
+        tags: Vec<String>,
+    }
+
+    fn city_schema() -> Value {
+        schema_for::<City>().as_value().clone()
+    }
+
+    #[test]
+    fn extracts_json_from_fences_and_prose() {
+        assert_eq!(extract_json("```json\n{\"a\": 1}\n```"), "{\"a\": 1}");
+        assert_eq!(extract_json("```\n[1, 2]\n```"), "[1, 2]");
+        assert_eq!(extract_json("Sure! Here it is: {\"a\": {\"b\": 2}} Hope that helps."), "{\"a\": {\"b\": 2}}");
+        assert_eq!(extract_json("  no json here "), "no json here");
+    }
+
+    #[test]
+    fn reports_where_a_value_mismatches_the_schema() {
+        let schema = city_schema();
+        assert_eq!(validate(&schema, &json!({"name": "Oslo", "population": 700000, "tags": ["capital"]}), "$"), Ok(()));
+        assert_eq!(
+            validate(&schema, &json!({"name": "Oslo", "population": "many", "tags": []}), "$"),
+            Err("$.population: expected integer, got string".to_string())
+        );
+        assert_eq!(
+            validate(&schema, &json!({"name": "Oslo", "tags": []}), "$"),
+            Err("$: missing required property 'population'".to_string())
+        );
+        assert_eq!(
+            validate(&schema, &json!({"name": "Oslo", "population": 1, "tags": [1]}), "$"),
+            Err("$.tags[0]: expected string, got number".to_string())
+        );
+        let closed = json!({"type": "object", "properties": {}, "additionalProperties": false});
+        assert_eq!(validate(&closed, &json!({"x": 1}), "$"), Err("$: unexpected property 'x'".to_string()));
+        let choice = json!({"enum": ["a", "b"]});
+        assert!(validate(&choice, &json!("c"), "$").is_err());
+    }
+
+    #[test]
+    fn parses_fenced_answers_into_the_target_type() {
+        let city: City = parse("```json\n{\"name\": \"Oslo\", \"population\": 1, \"tags\": []}\n```", &city_schema()).unwrap();
+        assert_eq!(city, City { name: "Oslo".into(), population: 1, tags: vec![] });
+        assert!(parse::<City>("not json", &city_schema()).unwrap_err().starts_with("response is not valid JSON"));
+    }
+
+    #[tokio::test]
+    async fn execute_typed_feeds_validation_errors_back() {
+        let mock = MockLlm::new()
+            .reply(r#"{"name": "Oslo", "population": "many", "tags": []}"#)
+            .reply(r#"{"name": "Oslo", "population": 700000, "tags": []}"#);
+        let mut query = Query::new(LLM::Dummy(mock.clone()), HistoryConfig::None);
+        query.setup.prompt = "Describe Oslo".into();
+        let city: City = query.execute_typed().await.unwrap();
+        assert_eq!(city.population, 700000);
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|r| r.structured));
+        assert!(requests[1].user().contains("$.population: expected integer, got string"));
+    }
+
+    #[tokio::test]
+    async fn execute_typed_gives_up_after_the_retry_budget() {
+        let mock = MockLlm::new().reply("{}").reply("{}");
+        let mut query = Query::new(LLM::Dummy(mock.clone()), HistoryConfig::None);
+        query.structured_retries = 1;
+        let error = query.execute_typed::<City>().await.unwrap_err();
+        assert!(error.to_string().contains("after 2 attempts"));
+        assert_eq!(mock.remaining(), 0);
+    }
+}
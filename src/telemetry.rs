@@ -0,0 +1,58 @@
+//! `tracing` instrumentation.
+//!
+//! The crate emits the following spans, which can be exported to an
+//! OpenTelemetry collector with `tracing-opentelemetry` or printed with any
+//! other `tracing` subscriber:
+//!
+//! | Span              | Emitted by                       | Fields |
+//! |-------------------|----------------------------------|--------|
+//! | `query.execute`   | [`Query::execute`](crate::Query::execute) and `execute_typed` | `chatuuid`, `user` |
+//! | `prompt.build`    | [`PromptComposer::build`](crate::PromptComposer::build) | `has_context`, `resources`, `has_style`, `has_output_format`, `system_len`, `system`* |
+//! | `llm.chat`        | every chat turn sent by a [`Query`](crate::Query) | `chatuuid`, `model`, `backend`, `prompt_tokens`, `completion_tokens`, `finish_reason`, `latency_ms`, `prompt`*, `response`* |
+//! | `llm.request`     | each backend tried within `llm.chat` | `backend` |
+//! | `tool.call`       | each tool invocation by the model | `tool`, `arguments`*, `result`* |
+//! | `history.store` / `history.read` | history persistence | `chatuuid`, `store` |
+//!
+//! Fields marked * carry prompt, response or tool content. They are only
+//! recorded after [`set_capture_content`] has been called with `true`, so
+//! user data does not leave the process unless explicitly enabled. The same
+//! switch governs debug logs, which otherwise give only lengths and ids.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tracing::Span;
+
+static CAPTURE_CONTENT: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables recording of prompt, response and tool content on
+/// spans and in debug logs.
+///
+/// Disabled by default.
+pub fn set_capture_content(enabled: bool) {
+    CAPTURE_CONTENT.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns `true` if content capture is enabled (see [`set_capture_content`]).
+pub fn capture_content() -> bool {
+    CAPTURE_CONTENT.load(Ordering::Relaxed)
+}
+
+/// `value` for a log message: the text itself if content capture is
+/// enabled, otherwise only its length.
+pub(crate) fn log_content(value: &str) -> String {
+    if capture_content() {
+        value.to_string()
+    } else {
+        format!("<{} chars>", value.chars().count())
+    }
+}
+
+/// Records `value` on `field` of `span` if content capture is enabled.
+///
+/// `field` must have been declared (usually as `tracing::field::Empty`) when
+/// the span was created.
+pub(crate) fn record_content(span: &Span, field: &'static str, value: &str) {
+    if capture_content() {
+        span.record(field, value);
+    }
+}
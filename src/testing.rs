@@ -0,0 +1,478 @@
+//! Test support: an in-process fake Ollama HTTP server.
+//!
+//! [`FakeOllama`] implements enough of Ollama's REST API for the real
+//! [`LLM::Ollama`] code path (including the tool-calling [`AgentLoop`](crate::AgentLoop) and
+//! [`Query::embed`](crate::Query::embed)) to be integration tested without a
+//! GPU or network:
+//!
+//! - `POST /api/chat` – answers from a script of [`FakeReply`]s with a single
+//!   JSON object, as the crate always sends `"stream": false`;
+//! - `POST /api/embed` and `POST /api/embeddings` – scripted or deterministic
+//!   embeddings;
+//! - `GET /api/tags` – the models registered with [`FakeOllama::model`];
+//! - `POST /api/show`, `POST /api/pull` and `DELETE /api/delete` – model
+//!   metadata set with [`FakeOllama::model_info`], simulated downloads and
+//!   removal, for the `admin` feature. A `/api/chat` request without
+//!   messages (a load or unload) is answered without consuming the script.
+//!
+//! Every request is recorded and can be inspected with
+//! [`FakeOllama::requests`]. The server stops when the handle is dropped.
+//!
+//! ```rust,ignore
+//! #[tokio::test]
+//! async fn answers_from_script() {
+//!     let server = FakeOllama::start().await.unwrap();
+//!     server.reply("Hello!");
+//!     let mut query = Query::new(server.llm(ModelConfig::new("llama3.2:1b")), HistoryConfig::None);
+//!     query.setup.prompt = "Hi".into();
+//!     assert_eq!(query.execute().await.unwrap().text, "Hello!");
+//!     assert_eq!(server.requests()[0].path, "/api/chat");
+//! }
+//! ```
+//!
+//! Requires the `testing` feature.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+use crate::{http, ModelConfig, LLM};
+
+/// A scripted answer to a `/api/chat` request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FakeReply {
+    /// An assistant message with the given content.
+    Text(String),
+    /// An assistant message asking for the tool `name` to be called with
+    /// `arguments`. The agent loop runs the tool and sends a follow-up
+    /// request, which is answered by the next scripted reply.
+    ToolCall(String, Value),
+    /// An HTTP error response with the given status and message.
+    Error(u16, String),
+}
+
+/// A request received by the fake server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeRequest {
+    /// HTTP method, e.g. `"POST"`.
+    pub method: String,
+    /// Request path, e.g. `"/api/chat"`.
+    pub path: String,
+    /// JSON body (`Value::Null` when empty or not JSON).
+    pub body: Value,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    replies: VecDeque<FakeReply>,
+    embeddings: VecDeque<Vec<f32>>,
+    models: Vec<String>,
+    /// Context length and capabilities per model, for `/api/show`.
+    model_info: HashMap<String, (u32, Vec<String>)>,
+    requests: Vec<FakeRequest>,
+}
+
+/// Dimension of the deterministic embeddings served when none are scripted.
+const DEFAULT_EMBEDDING_DIM: usize = 8;
+
+/// An in-process fake Ollama server; see the [module documentation](self).
+#[derive(Debug)]
+pub struct FakeOllama {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    shutdown: CancellationToken,
+}
+
+impl FakeOllama {
+    /// Binds to an ephemeral port on `127.0.0.1` and starts serving on the
+    /// current Tokio runtime.
+    ///
+    /// # Errors
+    /// Returns an error if the listener cannot be bound.
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State::default()));
+        let shutdown = CancellationToken::new();
+
+        let accept_state = state.clone();
+        let accept_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            loop {
+                let accept = Box::pin(listener.accept());
+                let stop = Box::pin(accept_shutdown.cancelled());
+                let (stream, _) = match futures::future::select(accept, stop).await {
+                    futures::future::Either::Left((Ok(conn), _)) => conn,
+                    futures::future::Either::Left((Err(e), _)) => {
+                        log::warn!("Fake Ollama accept failed: {e}");
+                        continue;
+                    }
+                    futures::future::Either::Right(_) => break,
+                };
+                let state = accept_state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, state).await {
+                        log::debug!("Fake Ollama connection error: {e}");
+                    }
+                });
+            }
+        });
+        log::debug!("Fake Ollama listening on {addr}");
+
+        Ok(FakeOllama { addr, state, shutdown })
+    }
+
+    /// Host to pass to [`LLM::Ollama`] (`"http://127.0.0.1"`).
+    pub fn host(&self) -> String {
+        format!("http://{}", self.addr.ip())
+    }
+
+    /// Port the server listens on.
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// An [`LLM::Ollama`] pointing at this server.
+    pub fn llm(&self, model: ModelConfig) -> LLM {
+        LLM::Ollama(self.host(), self.port(), model)
+    }
+
+    /// A `(host, port, model)` tuple for [`Query::embed`](crate::Query::embed).
+    pub fn embed_config(&self, model: ModelConfig) -> (String, u16, ModelConfig) {
+        (self.host(), self.port(), model)
+    }
+
+    /// Queues a text answer for `/api/chat`.
+    pub fn reply(&self, text: impl Into<String>) -> &Self {
+        self.push(FakeReply::Text(text.into()))
+    }
+
+    /// Queues a tool call for `/api/chat`.
+    pub fn tool_call(&self, name: impl Into<String>, arguments: Value) -> &Self {
+        self.push(FakeReply::ToolCall(name.into(), arguments))
+    }
+
+    /// Queues an HTTP error for `/api/chat`.
+    pub fn error(&self, status: u16, message: impl Into<String>) -> &Self {
+        self.push(FakeReply::Error(status, message.into()))
+    }
+
+    /// Queues an arbitrary `/api/chat` reply.
+    pub fn push(&self, reply: FakeReply) -> &Self {
+        self.lock().replies.push_back(reply);
+        self
+    }
+
+    /// Queues an embedding vector for the next embedded input.
+    pub fn embedding(&self, vector: Vec<f32>) -> &Self {
+        self.lock().embeddings.push_back(vector);
+        self
+    }
+
+    /// Adds a model to the `/api/tags` listing.
+    pub fn model(&self, name: impl Into<String>) -> &Self {
+        self.lock().models.push(name.into());
+        self
+    }
+
+    /// Adds a model to the `/api/tags` listing with the context length and
+    /// capabilities (e.g. `["completion", "tools"]`) reported by `/api/show`.
+    pub fn model_info(&self, name: impl Into<String>, context_length: u32, capabilities: &[&str]) -> &Self {
+        let name = name.into();
+        let mut state = self.lock();
+        if !state.models.contains(&name) {
+            state.models.push(name.clone());
+        }
+        state.model_info.insert(name, (context_length, capabilities.iter().map(|c| c.to_string()).collect()));
+        self
+    }
+
+    /// All requests received so far, oldest first.
+    pub fn requests(&self) -> Vec<FakeRequest> {
+        self.lock().requests.clone()
+    }
+
+    /// Number of scripted chat replies not yet served.
+    pub fn remaining(&self) -> usize {
+        self.lock().replies.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for FakeOllama {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+/// Reads one HTTP request from `stream`, answers it and closes the connection.
+async fn handle(mut stream: TcpStream, state: Arc<Mutex<State>>) -> std::io::Result<()> {
+    let Some(request) = http::read_request(&mut stream).await? else {
+        return Ok(());
+    };
+    let body: Value = serde_json::from_slice(&request.body).unwrap_or(Value::Null);
+
+    let (status, content_type, payload) = {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        state.requests.push(FakeRequest { method: request.method.clone(), path: request.path.clone(), body: body.clone() });
+        route(&mut state, &request.method, &request.path, &body)
+    };
+    http::write_response(&mut stream, status, content_type, &[], &payload).await
+}
+
+/// Produces `(status, content type, body)` for a request.
+fn route(state: &mut State, method: &str, path: &str, body: &Value) -> (u16, &'static str, String) {
+    match (method, path) {
+        ("POST", "/api/chat") => chat(state, body),
+        ("POST", "/api/embed") | ("POST", "/api/embeddings") => {
+            let inputs: Vec<String> = match body.get("input").or_else(|| body.get("prompt")) {
+                Some(Value::String(s)) => vec![s.clone()],
+                Some(Value::Array(a)) => a.iter().map(|v| v.as_str().unwrap_or_default().to_string()).collect(),
+                _ => vec![String::new()],
+            };
+            let embeddings: Vec<Vec<f32>> = inputs
+                .iter()
+                .map(|input| state.embeddings.pop_front().unwrap_or_else(|| deterministic_embedding(input)))
+                .collect();
+            let model = body.get("model").cloned().unwrap_or(Value::Null);
+            (200, "application/json", json!({ "model": model, "embeddings": embeddings }).to_string())
+        }
+        ("GET", "/api/tags") => {
+            let models: Vec<Value> = state.models.iter()
+                .map(|name| json!({
+                    "name": name,
+                    "model": name,
+                    "modified_at": "2024-01-01T00:00:00Z",
+                    "size": 0,
+                    "digest": "",
+                }))
+                .collect();
+            (200, "application/json", json!({ "models": models }).to_string())
+        }
+        ("POST", "/api/show") => {
+            let name = model_name(body);
+            if !state.models.contains(&name) {
+                return (404, "application/json", json!({ "error": format!("model '{name}' not found") }).to_string());
+            }
+            let (context_length, capabilities) = state.model_info.get(&name)
+                .cloned()
+                .unwrap_or_else(|| (2048, vec!["completion".to_string()]));
+            (200, "application/json", json!({
+                "license": "",
+                "modelfile": format!("FROM {name}"),
+                "parameters": "",
+                "template": "{{ .Prompt }}",
+                "model_info": { "general.architecture": "fake", "fake.context_length": context_length },
+                "capabilities": capabilities,
+            }).to_string())
+        }
+        ("POST", "/api/pull") => {
+            let name = model_name(body);
+            if !state.models.contains(&name) {
+                state.models.push(name);
+            }
+            let mut lines = vec![json!({ "status": "pulling manifest" })];
+            lines.extend((0..=2).map(|i| json!({ "status": "downloading", "digest": "sha256:fake", "total": 200, "completed": i * 100 })));
+            lines.push(json!({ "status": "success" }));
+            let lines: Vec<String> = lines.iter().map(Value::to_string).collect();
+            (200, "application/x-ndjson", lines.join("\n") + "\n")
+        }
+        ("DELETE", "/api/delete") => {
+            let name = model_name(body);
+            match state.models.iter().position(|m| *m == name) {
+                Some(i) => {
+                    state.models.remove(i);
+                    (200, "application/json", String::new())
+                }
+                None => (404, "application/json", json!({ "error": format!("model '{name}' not found") }).to_string()),
+            }
+        }
+        _ => (404, "application/json", json!({ "error": format!("{method} {path} not found") }).to_string()),
+    }
+}
+
+/// The model named in a request body (`"model"`, or the older `"name"`).
+fn model_name(body: &Value) -> String {
+    body.get("model").or_else(|| body.get("name")).and_then(Value::as_str).unwrap_or_default().to_string()
+}
+
+/// Answers `/api/chat` from the script.
+fn chat(state: &mut State, body: &Value) -> (u16, &'static str, String) {
+    let model = body.get("model").and_then(Value::as_str).unwrap_or_default().to_string();
+    if body.get("messages").and_then(Value::as_array).is_some_and(|m| m.is_empty()) {
+        let unload = body.get("keep_alive").is_some_and(|k| k == 0 || k == "0");
+        return (200, "application/json", json!({
+            "model": model,
+            "created_at": "2024-01-01T00:00:00Z",
+            "message": { "role": "assistant", "content": "" },
+            "done": true,
+            "done_reason": if unload { "unload" } else { "load" },
+        }).to_string());
+    }
+    let prompt_tokens: usize = body.get("messages")
+        .and_then(Value::as_array)
+        .map(|msgs| msgs.iter().filter_map(|m| m.get("content").and_then(Value::as_str)).map(count_tokens).sum())
+        .unwrap_or(0);
+
+    let (content, tool_calls) = match state.replies.pop_front() {
+        Some(FakeReply::Text(text)) => (text, Vec::new()),
+        Some(FakeReply::ToolCall(name, arguments)) => {
+            (String::new(), vec![json!({ "function": { "name": name, "arguments": arguments } })])
+        }
+        Some(FakeReply::Error(status, message)) => {
+            return (status, "application/json", json!({ "error": message }).to_string());
+        }
+        None => {
+            return (500, "application/json", json!({ "error": "fake ollama: no scripted reply" }).to_string());
+        }
+    };
+
+    let message = json!({
+        "model": model,
+        "created_at": "2024-01-01T00:00:00Z",
+        "message": { "role": "assistant", "content": content, "tool_calls": tool_calls },
+        "done": true,
+        "done_reason": "stop",
+        "total_duration": 0,
+        "load_duration": 0,
+        "prompt_eval_count": prompt_tokens,
+        "prompt_eval_duration": 0,
+        "eval_count": count_tokens(&content_or(&tool_calls, &content)),
+        "eval_duration": 0,
+    });
+    (200, "application/json", message.to_string())
+}
+
+/// Content used for token counting of a reply: the tool calls if present.
+fn content_or(tool_calls: &[Value], content: &str) -> String {
+    if tool_calls.is_empty() {
+        content.to_string()
+    } else {
+        Value::Array(tool_calls.to_vec()).to_string()
+    }
+}
+
+/// Crude token count: whitespace-separated words.
+fn count_tokens(text: &str) -> usize {
+    text.split_whitespace().count()
+}
+
+/// A stable, normalised pseudo-embedding derived from the input text, so that
+/// equal inputs embed equally and different inputs (usually) differ.
+fn deterministic_embedding(input: &str) -> Vec<f32> {
+    let mut v = vec![0f32; DEFAULT_EMBEDDING_DIM];
+    for (i, byte) in input.bytes().enumerate() {
+        v[i % DEFAULT_EMBEDDING_DIM] += f32::from(byte);
+    }
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+    v
+}
+
+#[cfg(all(test, feature="tools"))]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{Component, ComponentRegistry, HistoryConfig, LlmError, ModelCapabilities, ModelRegistry, Query, RetryPolicy, Tool};
+
+    fn query(server: &FakeOllama, model: ModelConfig) -> Query {
+        let mut query = Query::new(server.llm(model), HistoryConfig::None);
+        query.setup.prompt = "Hi".into();
+        query.retry = RetryPolicy::new(2).backoff(Duration::ZERO, Duration::ZERO);
+        query
+    }
+
+    #[tokio::test]
+    async fn execute_answers_from_script() {
+        let server = FakeOllama::start().await.unwrap();
+        server.reply("Hello there!");
+        let mut model = ModelConfig::new("llama3.2:1b");
+        model.min_p = Some(0.05);
+        let response = query(&server, model).execute().await.unwrap();
+        assert_eq!(response.text, "Hello there!");
+        assert_eq!(response.finish_reason.as_deref(), Some("stop"));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/api/chat");
+        assert_eq!(requests[0].body["model"], "llama3.2:1b");
+        assert_eq!(requests[0].body["options"]["min_p"], json!(0.05f32));
+        assert!(requests[0].body["messages"].as_array().unwrap().last().unwrap()["content"].as_str().unwrap().contains("Hi"));
+    }
+
+    #[tokio::test]
+    async fn execute_runs_tool_calls() {
+        let server = FakeOllama::start().await.unwrap();
+        server.tool_call("shout", json!({"param": "hi"})).reply("They said HI.");
+        let mut registry = ComponentRegistry::new();
+        registry.register(Component {
+            tools: vec![Tool::new("shout", "Upper-cases the text", |s: &String| {
+                let s = s.to_uppercase();
+                async move { s }
+            })],
+            ..Default::default()
+        });
+        let mut model = ModelConfig::new("llama3.2:1b");
+        model.tool = Some(true);
+        let mut query = query(&server, model);
+        query.components = Some(registry);
+        let response = query.execute().await.unwrap();
+        assert_eq!(response.text, "They said HI.");
+        assert_eq!(response.tool_calls.len(), 1);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].body["tools"][0]["function"]["name"], "shout");
+        let tool_message = requests[1].body["messages"].as_array().unwrap().last().unwrap().clone();
+        assert_eq!(tool_message["role"], "tool");
+        assert_eq!(tool_message["content"], "HI");
+    }
+
+    #[tokio::test]
+    async fn execute_refuses_tools_for_a_model_without_tool_capability() {
+        let server = FakeOllama::start().await.unwrap();
+        server.reply("unused");
+        let mut registry = ComponentRegistry::new();
+        registry.register(Component {
+            tools: vec![Tool::new("shout", "Upper-cases the text", |s: &String| {
+                let s = s.to_uppercase();
+                async move { s }
+            })],
+            ..Default::default()
+        });
+        let mut model = ModelConfig::new("llama3.2:1b");
+        model.tool = Some(true);
+        let mut query = query(&server, model);
+        query.components = Some(registry);
+        query.models = Some(ModelRegistry::new().register(ModelConfig::new("llama3.2:1b"), ModelCapabilities::default()));
+
+        let err = query.execute().await.unwrap_err();
+        assert_eq!(err.downcast_ref::<LlmError>(), Some(&LlmError::ToolsUnsupported("llama3.2:1b".into())));
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn execute_retries_server_errors() {
+        let server = FakeOllama::start().await.unwrap();
+        server.error(503, "overloaded").reply("ok");
+        assert_eq!(query(&server, ModelConfig::new("llama3.2:1b")).execute().await.unwrap().text, "ok");
+        assert_eq!(server.requests().len(), 2);
+
+        server.error(400, "invalid options").reply("unused");
+        let err = query(&server, ModelConfig::new("llama3.2:1b")).execute().await.unwrap_err();
+        assert_eq!(err.downcast_ref::<LlmError>(), Some(&LlmError::Http { status: 400, message: r#"{"error":"invalid options"}"#.into() }));
+        assert_eq!(server.remaining(), 1);
+    }
+}